@@ -5,8 +5,9 @@ use glutin::{
     display::{GetGlDisplay, GlDisplay},
     surface::{SurfaceAttributesBuilder, WindowSurface},
 };
-use std::fs;
 use std::num::NonZeroU32;
+use std::path::PathBuf;
+use std::{env, fs};
 
 use imgui_winit_support::winit::{dpi::LogicalSize, event_loop::EventLoop, window::WindowBuilder};
 use raw_window_handle::HasRawWindowHandle;
@@ -15,6 +16,9 @@ use winit::{
     window::Window,
 };
 
+use ps::exe::Executable;
+use ps::sio::memory_card::MemoryCard;
+
 use crate::{emulator::Emulator, ui::DebuggerUI};
 
 pub struct App {
@@ -35,6 +39,47 @@ impl App {
         let bios: Box<[u8]> = fs::read("./binaries/SCPH1001.BIN").unwrap().into_boxed_slice();
         let mut emu = Emulator::new(bios);
 
+        // debugger [executable] [--card1 <file>] [--card2 <file>] [--state <file>] [--rewind <MiB>] [--movie <file>]
+        let mut args = env::args().skip(1);
+        let mut load_state = false;
+        while let Some(arg) = args.next() {
+            let port = match arg.as_str() {
+                "--card1" => 0,
+                "--card2" => 1,
+                "--state" => {
+                    emu.state_path = PathBuf::from(args.next().expect("missing state path"));
+                    load_state = true;
+                    continue;
+                }
+                "--movie" => {
+                    emu.movie_path = PathBuf::from(args.next().expect("missing movie path"));
+                    continue;
+                }
+                "--rewind" => {
+                    let budget: usize = args
+                        .next()
+                        .and_then(|mib| mib.parse().ok())
+                        .expect("missing rewind budget");
+                    emu.ps.enable_rewind(budget * 1024 * 1024);
+                    continue;
+                }
+                path => {
+                    let data = fs::read(path).unwrap();
+                    emu.ps.sideload(Executable::parse(&data).unwrap());
+                    emu.executable = Some(data);
+                    continue;
+                }
+            };
+            let path = PathBuf::from(args.next().expect("missing memory card path"));
+            let card = MemoryCard::open(&path).unwrap();
+            emu.ps.sio0.insert_memory_card(port, Some(Box::new(card)));
+            emu.memory_cards[port] = Some(path);
+        }
+        // after the cards are in, the state checks it finds the same devices plugged in
+        if load_state {
+            emu.load_state().unwrap();
+        }
+
         let mut debugger_ui = DebuggerUI::new(&mut renderer, &mut display);
 
         event_loop
@@ -63,7 +108,7 @@ impl App {
                         }
 
                         // Render the debugger UI and pass it the current emulator state and the UI context
-                        debugger_ui.render_ui(&mut emu, &ui, &mut renderer);
+                        debugger_ui.render_ui(&mut emu, &ui, &mut renderer, &display);
 
                         // Prepare the Glium rendering target
                         let mut target = display.draw();
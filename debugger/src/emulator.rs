@@ -1,15 +1,33 @@
 use std::collections::HashSet;
+use std::fs;
+use std::path::PathBuf;
 
 use ps::{
     cpu::mipsr3000,
+    movie::{Input, Movie},
     playstation::{CYCLES_PER_FRAME, PlayStation},
 };
 
+// memory for the states kept to step and rewind backwards
+pub const REWIND_BUDGET: usize = 256 * 1024 * 1024;
+
 pub struct Emulator {
     pub ps: PlayStation,
     pub running: bool,
     pub breakpoints: HashSet<u32>,
     pub step_over_target: Option<u32>,
+    // images inserted in the memory card slots, the debugger reads saves back from them
+    pub memory_cards: [Option<PathBuf>; 2],
+    // file behind the save and load state buttons, and how the last attempt went
+    pub state_path: PathBuf,
+    pub state_message: Option<String>,
+    // the sideloaded executable, movies keep its hash
+    pub executable: Option<Vec<u8>>,
+    // pads as held on the keyboard, applied when a frame starts
+    pub input: Input,
+    // input movie being recorded and where it is saved
+    pub movie: Option<Movie>,
+    pub movie_path: PathBuf,
 }
 
 impl Emulator {
@@ -30,19 +48,30 @@ impl Emulator {
         //bps.insert(0x80056424);//srav
         //bps.insert(0x80052668);//multu
         bps.insert(0x80051054); // write to $zero
+        let mut ps = PlayStation::new(bios);
+        ps.enable_rewind(REWIND_BUDGET);
         Emulator {
-            ps: PlayStation::new(bios),
+            ps,
             running: false,
             breakpoints: bps,
             step_over_target: None,
+            memory_cards: [None, None],
+            state_path: PathBuf::from("debugger.state"),
+            state_message: None,
+            executable: None,
+            input: Input::default(),
+            movie: None,
+            movie_path: PathBuf::from("debugger.movie"),
         }
     }
 
+    // runs to the start of the next frame, like PlayStation::run_next_frame
     pub fn run(&mut self) {
-        let mut total_cycles: usize = 0;
+        self.ps.checkpoint();
+        let end = (self.ps.cycles / CYCLES_PER_FRAME as u64 + 1) * CYCLES_PER_FRAME as u64;
 
-        while total_cycles < CYCLES_PER_FRAME {
-            let cycles: usize = mipsr3000::run_cycle(&mut self.ps);
+        while self.ps.cycles < end {
+            self.execute();
             if self.breakpoints.contains(&self.ps.cpu.pc) {
                 self.running = !self.running;
                 break;
@@ -53,11 +82,82 @@ impl Emulator {
                     self.step_over_target = None;
                 }
             }
-            // assume every instruction takes one cycle, I don't know if its accurate, will fix later.
-            total_cycles += cycles;
         }
     }
 
+    pub fn step(&mut self) {
+        self.ps.checkpoint();
+        self.execute();
+    }
+
+    // the pads only change when a frame starts so a movie can play the run back
+    fn execute(&mut self) {
+        if self.ps.cycles.is_multiple_of(CYCLES_PER_FRAME as u64) {
+            match &mut self.movie {
+                Some(movie) => movie.record(&mut self.ps, self.input),
+                None => self.input.apply(&mut self.ps),
+            }
+        }
+        mipsr3000::run_instruction(&mut self.ps);
+    }
+
+    // goes back to the last state before the previous instruction and runs up to it
+    pub fn step_back(&mut self) -> Result<(), String> {
+        let target = self.ps.cycles.checked_sub(1).ok_or("already at power on")?;
+        self.ps.rewind_to(target)?;
+        self.rewind_movie();
+        while self.ps.cycles < target {
+            self.execute();
+        }
+        Ok(())
+    }
+
+    pub fn rewind(&mut self, frames: usize) -> Result<(), String> {
+        self.running = false;
+        self.step_over_target = None;
+        self.ps.rewind(frames)?;
+        self.rewind_movie();
+        Ok(())
+    }
+
+    // the frames that will be played again are recorded again
+    fn rewind_movie(&mut self) {
+        if let Some(movie) = &mut self.movie
+            && !movie.truncate(self.ps.cycles)
+        {
+            self.movie = None;
+            self.state_message =
+                Some("rewound past the start of the movie, recording dropped".to_string());
+        }
+    }
+
+    pub fn start_recording(&mut self) {
+        self.movie = Some(Movie::new(&self.ps, self.executable.as_deref(), None));
+    }
+
+    pub fn stop_recording(&mut self) -> Result<(), String> {
+        let Some(mut movie) = self.movie.take() else {
+            return Ok(());
+        };
+        movie.finish(&self.ps);
+        movie.save(&self.movie_path).map_err(|e| format!("{}: {}", self.movie_path.display(), e))
+    }
+
+    pub fn save_state(&self) -> Result<(), String> {
+        fs::write(&self.state_path, self.ps.save_state())
+            .map_err(|e| format!("{}: {}", self.state_path.display(), e))
+    }
+
+    pub fn load_state(&mut self) -> Result<(), String> {
+        let data = fs::read(&self.state_path)
+            .map_err(|e| format!("{}: {}", self.state_path.display(), e))?;
+        self.ps.load_state(&data).map_err(|e| format!("{}: {}", self.state_path.display(), e))?;
+        self.step_over_target = None;
+        // a movie can't jump to another state
+        self.movie = None;
+        Ok(())
+    }
+
     pub fn add_breakpoint(&mut self, address: u32) {
         self.breakpoints.insert(address);
     }
@@ -2,7 +2,6 @@ mod app;
 mod emulator;
 mod ui;
 
-
 use app::App;
 
 const TITLE: &str = "PlayStation Emulator";
@@ -11,6 +10,7 @@ const WINDOW_HEIGHT: u32 = 1080;
 
 fn main() {
     //env::set_var("RUST_BACKTRACE", "1");
+    ps::playstation::install_panic_hook();
     let mut app = App::new(WINDOW_WIDTH, WINDOW_HEIGHT);
     app.run_app(TITLE);
 }
@@ -1,17 +1,48 @@
-use disassembler::disassembler::disassemble;
-use glium::Display;
+use std::path::PathBuf;
+use std::rc::Rc;
+
+use disassembler::disassembler::disassemble_at;
+use glium::texture::RawImage2d;
+use glium::uniforms::{MagnifySamplerFilter, MinifySamplerFilter, SamplerBehavior};
+use glium::{Display, Texture2d};
 use glutin::surface::WindowSurface;
-use imgui::Ui;
-use imgui_glium_renderer::Renderer;
+use imgui::{Key, TextureId, Ui};
+use imgui_glium_renderer::{Renderer, Texture};
+use memcard::card::Save;
+use memcard::formats::load_card;
+use memcard::icon::ICON_SIZE;
 
 use ps::cpu::{
     instruction::{self, JTypeOperation, Operation},
-    mipsr3000,
     utils::register_name,
 };
+use ps::kernel::log::KernelLog;
+use ps::sio::controller::button;
 
 use crate::emulator::Emulator;
 
+// BIOS calls kept while logging, and the latest of them shown
+const KERNEL_LOG_LIMIT: usize = 10_000;
+const KERNEL_LOG_SHOWN: usize = 500;
+
+// keyboard layout of the pad in port 1
+const PAD_KEYS: [(Key, u16); 14] = [
+    (Key::UpArrow, button::UP),
+    (Key::DownArrow, button::DOWN),
+    (Key::LeftArrow, button::LEFT),
+    (Key::RightArrow, button::RIGHT),
+    (Key::X, button::CROSS),
+    (Key::C, button::CIRCLE),
+    (Key::S, button::SQUARE),
+    (Key::D, button::TRIANGLE),
+    (Key::Q, button::L2),
+    (Key::W, button::L1),
+    (Key::E, button::R1),
+    (Key::R, button::R2),
+    (Key::Enter, button::START),
+    (Key::Backspace, button::SELECT),
+];
+
 pub struct DebuggerUI {
     breakpoint_address_input: String,
     cpu_window: bool,
@@ -24,6 +55,26 @@ pub struct DebuggerUI {
     new_watch_address: String,
     new_watch_name: String,
     selected_format: usize,
+    memory_card_window: bool,
+    // None until the cards were read for the first time
+    memory_cards: Option<Vec<CardView>>,
+    tty_window: bool,
+    // length of the TTY text last shown, the view follows new output
+    tty_length: usize,
+    kernel_window: bool,
+    // calls logged when the view last followed them
+    kernel_logged: u64,
+}
+
+struct CardView {
+    path: PathBuf,
+    saves: Result<Vec<SaveView>, String>,
+    free_blocks: usize,
+}
+
+struct SaveView {
+    save: Save,
+    icon: Vec<TextureId>,
 }
 
 impl DebuggerUI {
@@ -56,24 +107,56 @@ impl DebuggerUI {
             new_watch_address: String::with_capacity(32),
             new_watch_name: String::with_capacity(32),
             selected_format: 0, // Default to Hex
+            memory_card_window: true,
+            memory_cards: None,
+            tty_window: true,
+            tty_length: 0,
+            kernel_window: true,
+            kernel_logged: 0,
         }
     }
 
-    pub fn render_ui(&mut self, emu: &mut Emulator, ui: &Ui, renderer: &mut Renderer) {
+    pub fn render_ui(
+        &mut self,
+        emu: &mut Emulator,
+        ui: &Ui,
+        renderer: &mut Renderer,
+        display: &Display<WindowSurface>,
+    ) {
+        self.read_pad(ui, emu);
         self.cpu_self_window(ui, emu);
         self.control_window(ui, emu);
         self.code_window(ui, emu);
         self.memory_window(ui, emu);
+        self.memory_card_window(ui, emu, renderer, display);
+        self.tty_window(ui, emu);
+        self.kernel_window(ui, emu);
         //self.watch_window(ui, emu);
     }
+    // keys typed into the debugger's own fields don't press buttons
+    fn read_pad(&self, ui: &Ui, emu: &mut Emulator) {
+        let mut buttons = 0;
+        if !ui.io().want_text_input {
+            for (key, bit) in PAD_KEYS {
+                if ui.is_key_down(key) {
+                    buttons |= bit;
+                }
+            }
+        }
+        emu.input.pads[0].buttons = buttons;
+    }
+
     fn control_window(&self, ui: &Ui, emu: &mut Emulator) {
         ui.window("Controls")
             .size([300.0, 200.0], imgui::Condition::FirstUseEver)
             .position([10.0, 10.0], imgui::Condition::FirstUseEver)
             .build(|| {
-                let ps = &mut emu.ps;
                 if ui.button("Step") {
-                    mipsr3000::run_instruction(ps);
+                    emu.step();
+                }
+                ui.same_line();
+                if ui.button("Step Back") {
+                    emu.state_message = emu.step_back().err();
                 }
                 if ui.button(if emu.running { "Pause" } else { "Run" }) {
                     emu.running = !emu.running;
@@ -84,6 +167,49 @@ impl DebuggerUI {
                     emu.ps.cpu.next_pc = 0xbfc00004
                     //TODO move the reset function to ps. with cop0 handling and shit
                 }
+
+                if ui.button("Rewind 1s") {
+                    emu.state_message = emu.rewind(60).err();
+                }
+                if let Some(rewind) = &emu.ps.rewind {
+                    ui.text(format!(
+                        "rewind: {} frames, {} KiB",
+                        rewind.len(),
+                        rewind.size() / 1024
+                    ));
+                }
+
+                ui.separator();
+                if ui.button("Save State") {
+                    let result = emu.save_state();
+                    emu.state_message =
+                        Some(result.map_or_else(|e| e, |_| "state saved".to_string()));
+                }
+                ui.same_line();
+                if ui.button("Load State") {
+                    let result = emu.load_state();
+                    emu.state_message =
+                        Some(result.map_or_else(|e| e, |_| "state loaded".to_string()));
+                }
+                ui.text(emu.state_path.display().to_string());
+
+                if emu.movie.is_none() {
+                    if ui.button("Record Movie") {
+                        emu.start_recording();
+                    }
+                } else if ui.button("Stop Recording") {
+                    let result = emu.stop_recording();
+                    emu.state_message =
+                        Some(result.map_or_else(|e| e, |_| "movie saved".to_string()));
+                }
+                if let Some(movie) = &emu.movie {
+                    ui.same_line();
+                    ui.text(format!("{} frames", movie.inputs.len()));
+                }
+                ui.text(emu.movie_path.display().to_string());
+                if let Some(message) = &emu.state_message {
+                    ui.text_wrapped(message);
+                }
             });
     }
 
@@ -101,6 +227,10 @@ impl DebuggerUI {
                 let cpu = &emu.ps.cpu;
 
                 register_label(cpu.pc, "PC");
+                if let Some(name) = emu.ps.symbols.describe(cpu.pc) {
+                    ui.same_line();
+                    ui.text(format!(" <{}>", name));
+                }
                 ui.separator();
                 for i in 0..32 {
                     register_label(cpu.registers[i], &register_name(i as u8));
@@ -181,8 +311,13 @@ impl DebuggerUI {
                 // Display the code lines with click-to-toggle breakpoints
                 for i in 0..31 {
                     let pc = emu.ps.cpu.pc + i * 4;
-                    let ins = instruction::Instruction(emu.ps.read32(pc));
-                    let disassembled = disassemble(&ins);
+                    let ins = instruction::Instruction(emu.ps.peek32(pc).unwrap_or(0));
+                    let disassembled = disassemble_at(&ins, pc, &emu.ps.symbols);
+
+                    // label lines for functions and objects imported from the executable
+                    if let Some(symbol) = emu.ps.symbols.get(pc) {
+                        ui.text_colored([0.4, 0.8, 1.0, 1.0], format!("{}:", symbol.name));
+                    }
 
                     // Check if there is already a breakpoint at this address
                     let is_breakpoint = emu.breakpoints.contains(&pc);
@@ -252,10 +387,11 @@ impl DebuggerUI {
 
         for row in 0..num_rows {
             let addr = base_addr.wrapping_add((row * num_columns) as u32);
-            let mut bytes = [0u8; 16];
+            // peeking leaves the FIFOs behind registers alone, unmapped bytes show as ??
+            let mut bytes = [None; 16];
 
             for i in 0..num_columns {
-                bytes[i] = emu.ps.read8(addr.wrapping_add(i as u32));
+                bytes[i] = emu.ps.peek8(addr.wrapping_add(i as u32));
             }
 
             // Address
@@ -264,20 +400,26 @@ impl DebuggerUI {
 
             // Hex bytes
             for &byte in &bytes {
-                ui.text(format!("{:02X} ", byte));
+                match byte {
+                    Some(byte) => ui.text(format!("{:02X} ", byte)),
+                    None => ui.text("?? "),
+                }
                 ui.same_line();
             }
 
             // ASCII representation
             let ascii: String = bytes
                 .iter()
-                .map(|&b| if b.is_ascii_graphic() || b == b' ' { b as char } else { '.' })
+                .map(|&b| match b {
+                    Some(b) if b.is_ascii_graphic() || b == b' ' => b as char,
+                    _ => '.',
+                })
                 .collect();
             ui.text(format!("|{}|", ascii));
         }
     }
 
-  /*  fn watch_window(&mut self, ui: &Ui, emu: &mut Emulator) {
+    /*  fn watch_window(&mut self, ui: &Ui, emu: &mut Emulator) {
         ui.window("Watch Window")
             .size([400.0, 300.0], imgui::Condition::FirstUseEver)
             .position([1190.0, 420.0], imgui::Condition::FirstUseEver)
@@ -389,6 +531,208 @@ impl DebuggerUI {
             });
     }*/
 
+    fn memory_card_window(
+        &mut self,
+        ui: &Ui,
+        emu: &mut Emulator,
+        renderer: &mut Renderer,
+        display: &Display<WindowSurface>,
+    ) {
+        if !self.memory_card_window || emu.memory_cards.iter().all(Option::is_none) {
+            return;
+        }
+        if self.memory_cards.is_none() {
+            self.load_memory_cards(emu, renderer, display);
+        }
+
+        let mut reload = false;
+        let mut write_back = false;
+        ui.window("Memory Cards")
+            .size([460.0, 400.0], imgui::Condition::FirstUseEver)
+            .position([10.0, 220.0], imgui::Condition::FirstUseEver)
+            .build(|| {
+                // the game writes to the files, reload to see its new saves
+                reload = ui.button("Reload##memory_cards_reload");
+                // loaded states and rewinds only change the cards in memory until the game saves
+                ui.same_line();
+                write_back = ui.button("Write Back##memory_cards_write_back");
+
+                // icons animate like in the BIOS memory card screen
+                let vblanks = (ui.time() * 60.0) as usize;
+                for card in self.memory_cards.iter().flatten() {
+                    ui.separator();
+                    ui.text(format!("{} ({} free blocks)", card.path.display(), card.free_blocks));
+                    let saves = match &card.saves {
+                        Ok(saves) => saves,
+                        Err(e) => {
+                            ui.text_colored([1.0, 0.0, 0.0, 1.0], e);
+                            continue;
+                        }
+                    };
+                    for view in saves {
+                        if !view.icon.is_empty() {
+                            let frame = (vblanks / memcard::icon::FRAME_VBLANKS) % view.icon.len();
+                            imgui::Image::new(view.icon[frame], [32.0, 32.0]).build(ui);
+                            ui.same_line();
+                        }
+                        ui.text(format!(
+                            "{:>2} {}\n   {} ({} blocks)",
+                            view.save.first_block,
+                            view.save.title,
+                            view.save.filename,
+                            view.save.blocks.len()
+                        ));
+                    }
+                }
+            });
+
+        if write_back {
+            for card in emu.ps.sio0.ports.iter_mut().filter_map(|port| port.memory_card.as_mut()) {
+                if let Err(e) = card.write_back() {
+                    eprintln!("can't write back the memory card: {}", e);
+                }
+            }
+        }
+        if reload || write_back {
+            self.load_memory_cards(emu, renderer, display);
+        }
+    }
+
+    // what the BIOS and the program printed, with the BIOS boot progress
+    fn tty_window(&mut self, ui: &Ui, emu: &mut Emulator) {
+        if !self.tty_window {
+            return;
+        }
+        ui.window("TTY")
+            .size([460.0, 300.0], imgui::Condition::FirstUseEver)
+            .position([480.0, 220.0], imgui::Condition::FirstUseEver)
+            .build(|| {
+                if ui.button("Clear##tty_clear") {
+                    emu.ps.tty.clear();
+                }
+                ui.same_line();
+                ui.text(format!("POST {:02x}", emu.ps.exp2.post()));
+                ui.separator();
+
+                ui.child_window("TtyView").size([0.0, 0.0]).horizontal_scrollbar(true).build(
+                    || {
+                        let text = emu.ps.tty.text();
+                        ui.text(text);
+                        if text.len() != self.tty_length {
+                            self.tty_length = text.len();
+                            ui.set_scroll_here_y_with_ratio(1.0);
+                        }
+                    },
+                );
+            });
+    }
+
+    // the BIOS calls with their arguments and results, logged while the window asks for it
+    fn kernel_window(&mut self, ui: &Ui, emu: &mut Emulator) {
+        if !self.kernel_window {
+            return;
+        }
+        ui.window("Kernel Calls")
+            .size([560.0, 300.0], imgui::Condition::FirstUseEver)
+            .position([480.0, 530.0], imgui::Condition::FirstUseEver)
+            .build(|| {
+                if emu.ps.kernel_log.is_none() {
+                    if ui.button("Start Logging") {
+                        emu.ps.kernel_log = Some(KernelLog::new(KERNEL_LOG_LIMIT));
+                        self.kernel_logged = 0;
+                    }
+                } else if ui.button("Stop Logging") {
+                    emu.ps.kernel_log = None;
+                }
+                let Some(log) = &mut emu.ps.kernel_log else {
+                    return;
+                };
+                ui.same_line();
+                if ui.button("Clear##kernel_clear") {
+                    log.clear();
+                }
+                ui.same_line();
+                ui.text(format!("{} calls", log.logged()));
+                ui.separator();
+
+                ui.child_window("KernelView").size([0.0, 0.0]).horizontal_scrollbar(true).build(
+                    || {
+                        let calls = log.calls();
+                        for call in calls.iter().skip(calls.len().saturating_sub(KERNEL_LOG_SHOWN))
+                        {
+                            ui.text(call.to_string());
+                        }
+                        if log.logged() != self.kernel_logged {
+                            self.kernel_logged = log.logged();
+                            ui.set_scroll_here_y_with_ratio(1.0);
+                        }
+                    },
+                );
+            });
+    }
+
+    // reads the inserted card images and uploads the save icons
+    fn load_memory_cards(
+        &mut self,
+        emu: &Emulator,
+        renderer: &mut Renderer,
+        display: &Display<WindowSurface>,
+    ) {
+        for card in self.memory_cards.take().into_iter().flatten() {
+            for view in card.saves.into_iter().flatten() {
+                for texture in view.icon {
+                    renderer.textures().remove(texture);
+                }
+            }
+        }
+
+        let mut cards = Vec::new();
+        for path in emu.memory_cards.iter().flatten() {
+            let card = match load_card(path) {
+                Ok(card) => card,
+                Err(e) => {
+                    cards.push(CardView { path: path.clone(), saves: Err(e), free_blocks: 0 });
+                    continue;
+                }
+            };
+
+            let saves = card
+                .saves()
+                .into_iter()
+                .map(|save| {
+                    let icon = card
+                        .icon(&save)
+                        .frames
+                        .into_iter()
+                        .map(|frame| {
+                            let image = RawImage2d::from_raw_rgba(
+                                frame,
+                                (ICON_SIZE as u32, ICON_SIZE as u32),
+                            );
+                            let texture =
+                                Texture2d::new(display, image).expect("Failed to upload icon");
+                            renderer.textures().insert(Texture {
+                                texture: Rc::new(texture),
+                                sampler: SamplerBehavior {
+                                    magnify_filter: MagnifySamplerFilter::Nearest,
+                                    minify_filter: MinifySamplerFilter::Nearest,
+                                    ..Default::default()
+                                },
+                            })
+                        })
+                        .collect();
+                    SaveView { save, icon }
+                })
+                .collect();
+            cards.push(CardView {
+                path: path.clone(),
+                saves: Ok(saves),
+                free_blocks: card.free_blocks(),
+            });
+        }
+        self.memory_cards = Some(cards);
+    }
+
     fn display_irq_info(&self, ui: &Ui, register_name: &str, value: u32) {
         if register_name == "I_STAT" || register_name == "I_MASK" {
             ui.separator();
@@ -396,9 +740,18 @@ impl DebuggerUI {
 
             // PlayStation interrupt names
             let interrupt_names = [
-                "VBLANK", "GPU", "CDROM", "DMA",
-                "TMR0", "TMR1", "TMR2", "Controller/Mem Card",
-                "SIO", "SPU", "PIO", "Reserved"
+                "VBLANK",
+                "GPU",
+                "CDROM",
+                "DMA",
+                "TMR0",
+                "TMR1",
+                "TMR2",
+                "Controller/Mem Card",
+                "SIO",
+                "SPU",
+                "PIO",
+                "Reserved",
             ];
 
             // Only show active bits
@@ -410,11 +763,8 @@ impl DebuggerUI {
             }
         }
     }
-
 }
 
-
-
 struct WatchEntry {
     address: u32,
     name: String,
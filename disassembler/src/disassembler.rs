@@ -1,19 +1,37 @@
 use ps::cpu::instruction;
+use ps::exe::symbols::SymbolTable;
 use std::fs::File;
 use std::io::Read;
 use std::io::Write;
 
 pub fn disassemble(ins: &instruction::Instruction) -> String {
+    ins.operation().to_string()
+}
+
+// like disassemble, with the symbol of jump and branch targets appended
+pub fn disassemble_at(ins: &instruction::Instruction, pc: u32, symbols: &SymbolTable) -> String {
+    use instruction::ITypeOperation::*;
+    use instruction::JTypeOperation::*;
     use instruction::Operation::*;
-    let op = ps::cpu::instruction::Instruction::operation(&ins);
-    match op {
-        RType(r_op) => r_op.to_string(),
-        IType(i_op) => i_op.to_string(),
-        JType(j_op) => j_op.to_string(),
-        COP0(cop0_op) => cop0_op.to_string(),
-        GTE(gte_op) => gte_op.to_string(),
-        NOOP => format!("noop"),
-        ILLEGAL => format!("illegal"),
+
+    let text = disassemble(ins);
+    let branch = |offset: u32| pc.wrapping_add(4).wrapping_add(offset << 2);
+    let target = match ins.operation() {
+        JType(J { target }) | JType(JAL { target }) => (pc.wrapping_add(4) & 0xf0000000) | target,
+        IType(BLTZ { immediate_se, .. })
+        | IType(BGEZ { immediate_se, .. })
+        | IType(BLTZAL { immediate_se, .. })
+        | IType(BGEZAL { immediate_se, .. })
+        | IType(BEQ { immediate_se, .. })
+        | IType(BNE { immediate_se, .. })
+        | IType(BLEZ { immediate_se, .. })
+        | IType(BGTZ { immediate_se, .. }) => branch(immediate_se),
+        _ => return text,
+    };
+
+    match symbols.describe(target) {
+        Some(name) => format!("{} <{}>", text, name),
+        None => text,
     }
 }
 
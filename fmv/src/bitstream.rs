@@ -0,0 +1,319 @@
+// STR v2/v3 frames: an 8 byte header followed by MPEG-1 style VLC codes which expand to the
+// MDEC's run-length halfwords. The bitstream is read as little endian halfwords, MSB first.
+// Every macroblock holds Cr, Cb and four Y blocks, the same order the MDEC takes them in.
+
+pub const FRAME_HEADER_SIZE: usize = 8;
+const FRAME_MAGIC: u16 = 0x3800;
+
+const END_OF_BLOCK: u16 = 0xfe00;
+// "10" ends a block, "000001" is followed by a 6 bit run and a 10 bit level
+const EOB_CODE: (u32, u32) = (0b10, 2);
+const ESCAPE_CODE: (u32, u32) = (0b000001, 6);
+
+// (code, length, run, level), the level is followed by a sign bit
+const AC_CODES: [(u32, u32, u16, u16); 111] = [
+    (0b11, 2, 0, 1),
+    (0b011, 3, 1, 1),
+    (0b0100, 4, 0, 2),
+    (0b0101, 4, 2, 1),
+    (0b00101, 5, 0, 3),
+    (0b00111, 5, 3, 1),
+    (0b00110, 5, 4, 1),
+    (0b000110, 6, 1, 2),
+    (0b000111, 6, 5, 1),
+    (0b000101, 6, 6, 1),
+    (0b000100, 6, 7, 1),
+    (0b0000110, 7, 0, 4),
+    (0b0000100, 7, 2, 2),
+    (0b0000111, 7, 8, 1),
+    (0b0000101, 7, 9, 1),
+    (0b00100110, 8, 0, 5),
+    (0b00100001, 8, 0, 6),
+    (0b00100101, 8, 1, 3),
+    (0b00100100, 8, 3, 2),
+    (0b00100111, 8, 10, 1),
+    (0b00100011, 8, 11, 1),
+    (0b00100010, 8, 12, 1),
+    (0b00100000, 8, 13, 1),
+    (0b0000001010, 10, 0, 7),
+    (0b0000001100, 10, 1, 4),
+    (0b0000001011, 10, 2, 3),
+    (0b0000001111, 10, 4, 2),
+    (0b0000001001, 10, 5, 2),
+    (0b0000001110, 10, 14, 1),
+    (0b0000001101, 10, 15, 1),
+    (0b0000001000, 10, 16, 1),
+    (0b000000011101, 12, 0, 8),
+    (0b000000011000, 12, 0, 9),
+    (0b000000010011, 12, 0, 10),
+    (0b000000010000, 12, 0, 11),
+    (0b000000011011, 12, 1, 5),
+    (0b000000010100, 12, 2, 4),
+    (0b000000011100, 12, 3, 3),
+    (0b000000010010, 12, 4, 3),
+    (0b000000011110, 12, 6, 2),
+    (0b000000010101, 12, 7, 2),
+    (0b000000010001, 12, 8, 2),
+    (0b000000011111, 12, 17, 1),
+    (0b000000011010, 12, 18, 1),
+    (0b000000011001, 12, 19, 1),
+    (0b000000010111, 12, 20, 1),
+    (0b000000010110, 12, 21, 1),
+    (0b0000000011010, 13, 0, 12),
+    (0b0000000011001, 13, 0, 13),
+    (0b0000000011000, 13, 0, 14),
+    (0b0000000010111, 13, 0, 15),
+    (0b0000000010110, 13, 1, 6),
+    (0b0000000010101, 13, 1, 7),
+    (0b0000000010100, 13, 2, 5),
+    (0b0000000010011, 13, 3, 4),
+    (0b0000000010010, 13, 5, 3),
+    (0b0000000010001, 13, 9, 2),
+    (0b0000000010000, 13, 10, 2),
+    (0b0000000011111, 13, 22, 1),
+    (0b0000000011110, 13, 23, 1),
+    (0b0000000011101, 13, 24, 1),
+    (0b0000000011100, 13, 25, 1),
+    (0b0000000011011, 13, 26, 1),
+    (0b00000000011111, 14, 0, 16),
+    (0b00000000011110, 14, 0, 17),
+    (0b00000000011101, 14, 0, 18),
+    (0b00000000011100, 14, 0, 19),
+    (0b00000000011011, 14, 0, 20),
+    (0b00000000011010, 14, 0, 21),
+    (0b00000000011001, 14, 0, 22),
+    (0b00000000011000, 14, 0, 23),
+    (0b00000000010111, 14, 0, 24),
+    (0b00000000010110, 14, 0, 25),
+    (0b00000000010101, 14, 0, 26),
+    (0b00000000010100, 14, 0, 27),
+    (0b00000000010011, 14, 0, 28),
+    (0b00000000010010, 14, 0, 29),
+    (0b00000000010001, 14, 0, 30),
+    (0b00000000010000, 14, 0, 31),
+    (0b000000000011000, 15, 0, 32),
+    (0b000000000010111, 15, 0, 33),
+    (0b000000000010110, 15, 0, 34),
+    (0b000000000010101, 15, 0, 35),
+    (0b000000000010100, 15, 0, 36),
+    (0b000000000010011, 15, 0, 37),
+    (0b000000000010010, 15, 0, 38),
+    (0b000000000010001, 15, 0, 39),
+    (0b000000000010000, 15, 0, 40),
+    (0b000000000011111, 15, 1, 8),
+    (0b000000000011110, 15, 1, 9),
+    (0b000000000011101, 15, 1, 10),
+    (0b000000000011100, 15, 1, 11),
+    (0b000000000011011, 15, 1, 12),
+    (0b000000000011010, 15, 1, 13),
+    (0b000000000011001, 15, 1, 14),
+    (0b0000000000010011, 16, 1, 15),
+    (0b0000000000010010, 16, 1, 16),
+    (0b0000000000010001, 16, 1, 17),
+    (0b0000000000010000, 16, 1, 18),
+    (0b0000000000010100, 16, 6, 3),
+    (0b0000000000011010, 16, 11, 2),
+    (0b0000000000011001, 16, 12, 2),
+    (0b0000000000011000, 16, 13, 2),
+    (0b0000000000010111, 16, 14, 2),
+    (0b0000000000010110, 16, 15, 2),
+    (0b0000000000010101, 16, 16, 2),
+    (0b0000000000011111, 16, 27, 1),
+    (0b0000000000011110, 16, 28, 1),
+    (0b0000000000011101, 16, 29, 1),
+    (0b0000000000011100, 16, 30, 1),
+    (0b0000000000011011, 16, 31, 1),
+];
+
+// v3 codes the DC coefficients as differences, prefixed by their size in bits like MPEG-1
+const LUMA_DC_SIZES: [(u32, u32, u32); 9] = [
+    (0b100, 3, 0),
+    (0b00, 2, 1),
+    (0b01, 2, 2),
+    (0b101, 3, 3),
+    (0b110, 3, 4),
+    (0b1110, 4, 5),
+    (0b11110, 5, 6),
+    (0b111110, 6, 7),
+    (0b1111110, 7, 8),
+];
+const CHROMA_DC_SIZES: [(u32, u32, u32); 9] = [
+    (0b00, 2, 0),
+    (0b01, 2, 1),
+    (0b10, 2, 2),
+    (0b110, 3, 3),
+    (0b1110, 4, 4),
+    (0b11110, 5, 5),
+    (0b111110, 6, 6),
+    (0b1111110, 7, 7),
+    (0b11111110, 8, 8),
+];
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct FrameHeader {
+    // run-length halfwords the frame expands to, divided by two
+    pub mdec_words: u16,
+    pub quant_scale: u16,
+    pub version: u16,
+}
+
+impl FrameHeader {
+    pub fn parse(data: &[u8]) -> Result<FrameHeader, String> {
+        if data.len() < FRAME_HEADER_SIZE {
+            return Err(String::from("frame is shorter than its header"));
+        }
+        let halfword = |offset: usize| u16::from_le_bytes([data[offset], data[offset + 1]]);
+        if halfword(2) != FRAME_MAGIC {
+            return Err(format!("bad frame magic {:04x}", halfword(2)));
+        }
+        let header =
+            FrameHeader { mdec_words: halfword(0), quant_scale: halfword(4), version: halfword(6) };
+        match header.version {
+            1..=3 => Ok(header),
+            version => Err(format!("unsupported bitstream version {}", version)),
+        }
+    }
+}
+
+struct BitReader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(data: &'a [u8]) -> BitReader<'a> {
+        BitReader { data, position: 0 }
+    }
+
+    // reads past the end return zeroes, the block decoder catches the garbage
+    fn peek(&self, bits: u32) -> u32 {
+        (0..bits as usize).fold(0, |value, i| {
+            let position = self.position + i;
+            let offset = (position / 16) * 2;
+            let halfword = match self.data.get(offset..offset + 2) {
+                Some(bytes) => u16::from_le_bytes([bytes[0], bytes[1]]),
+                None => 0,
+            };
+            (value << 1) | ((halfword >> (15 - position % 16)) & 1) as u32
+        })
+    }
+
+    fn skip(&mut self, bits: u32) {
+        self.position += bits as usize;
+    }
+
+    fn read(&mut self, bits: u32) -> u32 {
+        let value = self.peek(bits);
+        self.skip(bits);
+        value
+    }
+
+    fn at_end(&self) -> bool {
+        self.position >= self.data.len() * 8
+    }
+}
+
+const CR: usize = 0;
+const CB: usize = 1;
+const Y: usize = 2;
+
+struct Decoder<'a> {
+    reader: BitReader<'a>,
+    header: FrameHeader,
+    // v3 DC predictors for Cr, Cb and Y, in the 10 bit MDEC scale
+    predictors: [i32; 3],
+}
+
+impl Decoder<'_> {
+    fn macroblock(&mut self, codes: &mut Vec<u16>) -> Result<(), String> {
+        for component in [CR, CB, Y, Y, Y, Y] {
+            let dc = self.dc(component)?;
+            codes.push((self.header.quant_scale << 10) | (dc as u16 & 0x3ff));
+            self.ac(codes)?;
+        }
+        Ok(())
+    }
+
+    fn dc(&mut self, component: usize) -> Result<i32, String> {
+        if self.header.version != 3 {
+            return Ok(sign_extend(self.reader.read(10), 10));
+        }
+
+        let table = if component == Y { &LUMA_DC_SIZES } else { &CHROMA_DC_SIZES };
+        let &(_, length, size) = table
+            .iter()
+            .find(|&&(code, length, _)| self.reader.peek(length) == code)
+            .ok_or_else(|| String::from("bad DC size code"))?;
+        self.reader.skip(length);
+
+        // sizes work like JPEG, a clear top bit means a negative difference
+        let bits = self.reader.read(size) as i32;
+        let difference =
+            if size == 0 || bits & (1 << (size - 1)) != 0 { bits } else { bits - (1 << size) + 1 };
+
+        // the differences are in steps of 4
+        let dc = self.predictors[component] + difference * 4;
+        if !(-0x200..0x200).contains(&dc) {
+            return Err(format!("DC coefficient {} out of range", dc));
+        }
+        self.predictors[component] = dc;
+        Ok(dc)
+    }
+
+    fn ac(&mut self, codes: &mut Vec<u16>) -> Result<(), String> {
+        let mut index = 0;
+        loop {
+            if self.reader.at_end() {
+                return Err(String::from("bitstream ended in the middle of a block"));
+            }
+
+            let (run, level) = if self.reader.peek(EOB_CODE.1) == EOB_CODE.0 {
+                self.reader.skip(EOB_CODE.1);
+                codes.push(END_OF_BLOCK);
+                return Ok(());
+            } else if self.reader.peek(ESCAPE_CODE.1) == ESCAPE_CODE.0 {
+                self.reader.skip(ESCAPE_CODE.1);
+                let run = self.reader.read(6) as u16;
+                (run, self.reader.read(10) as u16)
+            } else {
+                let &(_, length, run, level) = AC_CODES
+                    .iter()
+                    .find(|&&(code, length, _, _)| self.reader.peek(length) == code)
+                    .ok_or_else(|| String::from("bad AC code"))?;
+                self.reader.skip(length);
+                let negative = self.reader.read(1) == 1;
+                (run, if negative { level.wrapping_neg() } else { level })
+            };
+
+            index += run as usize + 1;
+            if index > 63 {
+                return Err(String::from("more than 64 coefficients in a block"));
+            }
+            codes.push((run << 10) | (level & 0x3ff));
+        }
+    }
+}
+
+// expands a frame to the run-length codes of each macroblock, in the order they are stored
+pub fn decode_frame(
+    data: &[u8],
+    macroblocks: usize,
+) -> Result<(FrameHeader, Vec<Vec<u16>>), String> {
+    let header = FrameHeader::parse(data)?;
+    let mut decoder =
+        Decoder { reader: BitReader::new(&data[FRAME_HEADER_SIZE..]), header, predictors: [0; 3] };
+
+    let mut codes = Vec::with_capacity(macroblocks);
+    for macroblock in 0..macroblocks {
+        let mut expanded = Vec::new();
+        decoder
+            .macroblock(&mut expanded)
+            .map_err(|e| format!("macroblock {}: {}", macroblock, e))?;
+        codes.push(expanded);
+    }
+    Ok((header, codes))
+}
+
+fn sign_extend(value: u32, bits: u32) -> i32 {
+    ((value << (32 - bits)) as i32) >> (32 - bits)
+}
@@ -0,0 +1,124 @@
+use std::collections::BTreeMap;
+
+use ps::cdrom::disc::DATA_SIZE;
+use ps::cdrom::xa::{Subheader, XaDecoder};
+
+use crate::source::Sector;
+
+const DATA_OFFSET: usize = 24;
+// video sectors start with a 32 byte header: status 0x0160 and type 0x8001, the chunk
+// position, the frame number and size, the frame dimensions and a copy of the frame header
+const VIDEO_MAGIC: [u8; 4] = [0x60, 0x01, 0x01, 0x80];
+const VIDEO_HEADER_SIZE: usize = 32;
+const CHUNK_SIZE: usize = DATA_SIZE - VIDEO_HEADER_SIZE;
+
+pub struct Frame {
+    // counted by the encoder from 1, gaps are frames missing from the file
+    pub number: u32,
+    pub width: u16,
+    pub height: u16,
+    // index of the frame's first sector in the file
+    pub sector: usize,
+    pub chunks: usize,
+    // chunks that never showed up are left zeroed
+    pub missing_chunks: usize,
+    pub data: Vec<u8>,
+}
+
+pub struct AudioStream {
+    pub channel: u8,
+    pub rate: u32,
+    pub stereo: bool,
+    pub bits_per_sample: u32,
+    pub sectors: usize,
+    // at the native rate, mono streams are duplicated to both channels
+    pub samples: Vec<(i16, i16)>,
+}
+
+impl AudioStream {
+    pub fn seconds(&self) -> f64 {
+        self.samples.len() as f64 / self.rate as f64
+    }
+}
+
+pub struct Movie {
+    pub sectors: usize,
+    pub frames: Vec<Frame>,
+    // one stream per XA channel, in channel order
+    pub audio: Vec<AudioStream>,
+}
+
+fn is_video(sector: &Sector) -> bool {
+    sector[DATA_OFFSET..DATA_OFFSET + 4] == VIDEO_MAGIC
+}
+
+fn read_u16(data: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([data[offset], data[offset + 1]])
+}
+
+fn read_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([data[offset], data[offset + 1], data[offset + 2], data[offset + 3]])
+}
+
+// sorts the sectors into video frames and XA audio, the audio is decoded on the way
+pub fn demux(sectors: &[Sector]) -> Movie {
+    let mut frames: Vec<Frame> = Vec::new();
+    let mut audio: BTreeMap<u8, (XaDecoder, AudioStream)> = BTreeMap::new();
+    // only the first video channel is kept, interleaved alternate videos are rare
+    let mut video_channel = None;
+
+    for (index, sector) in sectors.iter().enumerate() {
+        let subheader = Subheader::from_sector(sector);
+
+        if subheader.is_audio() && subheader.is_form2() {
+            let (decoder, stream) = audio.entry(subheader.channel).or_insert_with(|| {
+                let stream = AudioStream {
+                    channel: subheader.channel,
+                    rate: subheader.sample_rate(),
+                    stereo: subheader.stereo(),
+                    bits_per_sample: subheader.bits_per_sample(),
+                    sectors: 0,
+                    samples: Vec::new(),
+                };
+                (XaDecoder::new(), stream)
+            });
+            stream.samples.extend(decoder.decode_sector(sector));
+            stream.sectors += 1;
+        } else if is_video(sector)
+            && *video_channel.get_or_insert(subheader.channel) == subheader.channel
+        {
+            let data = &sector[DATA_OFFSET..DATA_OFFSET + DATA_SIZE];
+            let chunk = read_u16(data, 4) as usize;
+            let chunks = read_u16(data, 6) as usize;
+            let number = read_u32(data, 8);
+            if chunk >= chunks {
+                continue;
+            }
+
+            if frames.last().is_none_or(|frame| frame.number != number) {
+                frames.push(Frame {
+                    number,
+                    width: read_u16(data, 16),
+                    height: read_u16(data, 18),
+                    sector: index,
+                    chunks,
+                    missing_chunks: chunks,
+                    data: vec![0; chunks * CHUNK_SIZE],
+                });
+            }
+            let frame = frames.last_mut().unwrap();
+            if let Some(destination) =
+                frame.data.get_mut(chunk * CHUNK_SIZE..(chunk + 1) * CHUNK_SIZE)
+            {
+                destination.copy_from_slice(&data[VIDEO_HEADER_SIZE..]);
+                frame.missing_chunks = frame.missing_chunks.saturating_sub(1);
+            }
+        }
+    }
+
+    Movie {
+        sectors: sectors.len(),
+        frames,
+        audio: audio.into_values().map(|(_, stream)| stream).collect(),
+    }
+}
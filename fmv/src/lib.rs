@@ -0,0 +1,4 @@
+pub mod bitstream;
+pub mod demux;
+pub mod source;
+pub mod video;
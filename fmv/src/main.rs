@@ -0,0 +1,138 @@
+use fmv::bitstream::FrameHeader;
+use fmv::demux::{Movie, demux};
+use fmv::source::{Sector, list_files, read_dump, read_from_disc};
+use fmv::video::{FrameDecoder, write_png};
+use ps::wav::WavWriter;
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::process::exit;
+
+const USAGE: &str = "usage: fmv files <disc>
+       fmv info <movie> [path on disc]
+       fmv decode <movie> <output dir> [path on disc]
+movies are ripped .str/.xa files (2352 or 2336 byte sectors, RIFF CDXA), or files
+on a .cue/.bin image when a path like MOVIE/OPENING.STR is given";
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let result = match args.iter().map(String::as_str).collect::<Vec<_>>().as_slice() {
+        ["files", disc] => files(Path::new(disc)),
+        ["info", movie] => load(movie, None).and_then(|sectors| info(&sectors)),
+        ["info", disc, file] => load(disc, Some(file)).and_then(|sectors| info(&sectors)),
+        ["decode", movie, output] => {
+            load(movie, None).and_then(|sectors| decode(&sectors, Path::new(output)))
+        }
+        ["decode", disc, output, file] => {
+            load(disc, Some(file)).and_then(|sectors| decode(&sectors, Path::new(output)))
+        }
+        _ => {
+            eprintln!("{}", USAGE);
+            exit(2);
+        }
+    };
+
+    if let Err(e) = result {
+        eprintln!("{}", e);
+        exit(1);
+    }
+}
+
+fn load(path: &str, file: Option<&str>) -> Result<Vec<Sector>, String> {
+    match file {
+        Some(file) => read_from_disc(Path::new(path), file),
+        None => read_dump(Path::new(path)),
+    }
+}
+
+fn files(disc: &Path) -> Result<(), String> {
+    for (name, record) in list_files(disc)? {
+        // XA files have to be read raw, that's where movies and streamed audio live
+        let raw = record.xa.is_some_and(|xa| xa.is_raw());
+        println!(
+            "{:>8} {:>10}  {}{}",
+            record.lba,
+            record.size,
+            name,
+            if raw { "  (XA)" } else { "" }
+        );
+    }
+    Ok(())
+}
+
+fn info(sectors: &[Sector]) -> Result<(), String> {
+    let movie = demux(sectors);
+    println!("{} sectors", movie.sectors);
+
+    if let (Some(first), Some(last)) = (movie.frames.first(), movie.frames.last()) {
+        let versions: Vec<String> = versions(&movie).iter().map(|v| format!("v{}", v)).collect();
+        let missing = movie.frames.iter().filter(|frame| frame.missing_chunks > 0).count();
+        println!(
+            "video: {} frames ({}-{}), {}x{}, {}, {:.1} sectors per frame{}",
+            movie.frames.len(),
+            first.number,
+            last.number,
+            first.width,
+            first.height,
+            versions.join("/"),
+            (last.sector - first.sector) as f64 / (movie.frames.len() - 1).max(1) as f64,
+            if missing > 0 { format!(", {} incomplete", missing) } else { String::new() }
+        );
+    } else {
+        println!("video: none");
+    }
+
+    for stream in &movie.audio {
+        println!(
+            "audio channel {}: {} Hz {} {} bit, {} sectors, {:.1} s",
+            stream.channel,
+            stream.rate,
+            if stream.stereo { "stereo" } else { "mono" },
+            stream.bits_per_sample,
+            stream.sectors,
+            stream.seconds()
+        );
+    }
+    Ok(())
+}
+
+fn versions(movie: &Movie) -> Vec<u16> {
+    let mut versions: Vec<u16> = movie
+        .frames
+        .iter()
+        .filter_map(|frame| FrameHeader::parse(&frame.data).ok())
+        .map(|h| h.version)
+        .collect();
+    versions.sort();
+    versions.dedup();
+    versions
+}
+
+fn decode(sectors: &[Sector], output: &Path) -> Result<(), String> {
+    let movie = demux(sectors);
+    fs::create_dir_all(output).map_err(|e| format!("{}: {}", output.display(), e))?;
+
+    let mut decoder = FrameDecoder::new();
+    let mut written = 0;
+    for frame in &movie.frames {
+        // a bad frame shouldn't stop the rest, rips often have a few
+        match decoder.decode(frame) {
+            Ok(image) => {
+                write_png(&image, &output.join(format!("frame_{:04}.png", frame.number)))?;
+                written += 1;
+            }
+            Err(e) => eprintln!("{}", e),
+        }
+    }
+    println!("{} of {} frames written", written, movie.frames.len());
+
+    for stream in &movie.audio {
+        let path = output.join(format!("audio_{}.wav", stream.channel));
+        let error = |e: std::io::Error| format!("{}: {}", path.display(), e);
+        let mut wav = WavWriter::create_with_rate(&path, stream.rate).map_err(error)?;
+        wav.write_samples(&stream.samples).map_err(error)?;
+        wav.finish().map_err(error)?;
+        println!("{} ({:.1} s)", path.display(), stream.seconds());
+    }
+    Ok(())
+}
@@ -0,0 +1,87 @@
+use std::fs;
+use std::path::Path;
+
+use ps::cdrom::disc::{Disc, SECTOR_SIZE, SYNC};
+use ps::cdrom::iso9660::{DirectoryRecord, Iso9660};
+
+// Mode2 sectors without the sync and header, what most CD-ROM drivers hand out for XA files
+const XA_SECTOR_SIZE: usize = 2336;
+// files copied off a disc on Windows get wrapped in a 44 byte RIFF CDXA header
+const RIFF_HEADER_SIZE: usize = 44;
+
+pub type Sector = [u8; SECTOR_SIZE];
+
+// a ripped .str/.xa file: raw 2352 byte sectors, 2336 byte XA sectors or RIFF CDXA
+pub fn read_dump(path: &Path) -> Result<Vec<Sector>, String> {
+    let data = fs::read(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let data = if data.starts_with(b"RIFF") && data.get(8..12) == Some(b"CDXA") {
+        &data[RIFF_HEADER_SIZE.min(data.len())..]
+    } else {
+        &data[..]
+    };
+
+    if data.starts_with(&SYNC) && data.len() % SECTOR_SIZE == 0 {
+        Ok(data.chunks(SECTOR_SIZE).map(|chunk| chunk.try_into().unwrap()).collect())
+    } else if data.len() % XA_SECTOR_SIZE == 0 {
+        // rebuild the framing so the sectors look like they came off the disc
+        let sectors = data
+            .chunks(XA_SECTOR_SIZE)
+            .map(|chunk| {
+                let mut sector = [0; SECTOR_SIZE];
+                sector[..12].copy_from_slice(&SYNC);
+                sector[15] = 2;
+                sector[16..].copy_from_slice(chunk);
+                sector
+            })
+            .collect();
+        Ok(sectors)
+    } else {
+        Err(format!(
+            "{}: not a raw 2352 or 2336 byte sector dump ({} bytes)",
+            path.display(),
+            data.len()
+        ))
+    }
+}
+
+// a file on a .cue/.bin image, read as raw sectors so the XA subheaders survive
+pub fn read_from_disc(path: &Path, file: &str) -> Result<Vec<Sector>, String> {
+    let error = |e: std::io::Error| format!("{}: {}", path.display(), e);
+    let mut disc = Disc::open(path).map_err(error)?;
+    let mut iso = Iso9660::open(&mut disc).map_err(error)?;
+    let record = iso
+        .find(file)
+        .map_err(error)?
+        .ok_or_else(|| format!("{}: no file {}", path.display(), file))?;
+    if record.is_directory() {
+        return Err(format!("{}: {} is a directory", path.display(), file));
+    }
+
+    let data = iso.read_file_raw(&record).map_err(error)?;
+    Ok(data.chunks(SECTOR_SIZE).map(|chunk| chunk.try_into().unwrap()).collect())
+}
+
+// every file on the disc with its full path, sorted by path
+pub fn list_files(path: &Path) -> Result<Vec<(String, DirectoryRecord)>, String> {
+    let error = |e: std::io::Error| format!("{}: {}", path.display(), e);
+    let mut disc = Disc::open(path).map_err(error)?;
+    let mut iso = Iso9660::open(&mut disc).map_err(error)?;
+
+    let mut files = Vec::new();
+    let mut pending = vec![(String::new(), iso.volume.root.clone())];
+    while let Some((prefix, dir)) = pending.pop() {
+        for entry in iso.read_dir(&dir).map_err(error)? {
+            if entry.name == "." || entry.name == ".." {
+                continue;
+            }
+            let name = format!("{}/{}", prefix, entry.name);
+            if entry.is_directory() {
+                pending.push((name, entry));
+            } else {
+                files.push((name, entry));
+            }
+        }
+    }
+    files.sort_by(|a, b| a.0.cmp(&b.0));
+    Ok(files)
+}
@@ -0,0 +1,114 @@
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+
+use ps::mdec::{MDEC, ZAGZIG};
+
+use crate::bitstream::decode_frame;
+use crate::demux::Frame;
+
+const MACROBLOCK_SIZE: usize = 16;
+// 16x16 pixels at 24bpp
+const MACROBLOCK_WORDS: usize = MACROBLOCK_SIZE * MACROBLOCK_SIZE * 3 / 4;
+
+// MDEC commands, decode is 24bpp unsigned
+const DECODE_24BPP: u32 = 0x3000_0000;
+const SET_QUANT_TABLES: u32 = 0x4000_0001;
+const SET_SCALE_TABLE: u32 = 0x6000_0000;
+
+// the tables libpress uploads, STR frames are encoded against them.
+// the quantisation matrix is the MPEG-1 intra one with a DC of 2, in row order
+const QUANT_MATRIX: [u8; 64] = [
+    2, 16, 19, 22, 26, 27, 29, 34, 16, 16, 22, 24, 27, 29, 34, 37, 19, 22, 26, 27, 29, 34, 34, 38,
+    22, 22, 26, 27, 29, 34, 37, 40, 22, 26, 27, 29, 32, 35, 40, 48, 26, 27, 29, 32, 35, 40, 48, 58,
+    26, 27, 29, 34, 38, 46, 56, 69, 27, 29, 35, 38, 46, 56, 69, 83,
+];
+const SCALE_TABLE: [i16; 64] = [
+    0x5a82, 0x5a82, 0x5a82, 0x5a82, 0x5a82, 0x5a82, 0x5a82, 0x5a82, 0x7d8a, 0x6a6d, 0x471c, 0x18f8,
+    -0x18f9, -0x471d, -0x6a6e, -0x7d8b, 0x7641, 0x30fb, -0x30fc, -0x7642, -0x7642, -0x30fc, 0x30fb,
+    0x7641, 0x6a6d, -0x18f9, -0x7d8b, -0x471d, 0x471c, 0x7d8a, 0x18f8, -0x6a6e, 0x5a82, -0x5a83,
+    -0x5a83, 0x5a82, 0x5a82, -0x5a83, -0x5a83, 0x5a82, 0x471c, -0x7d8b, 0x18f8, 0x6a6d, -0x6a6e,
+    -0x18f9, 0x7d8a, -0x471d, 0x30fb, -0x7642, 0x7641, -0x30fc, -0x30fc, 0x7641, -0x7642, 0x30fb,
+    0x18f8, -0x471d, 0x6a6d, -0x7d8b, 0x7d8a, -0x6a6e, 0x471c, -0x18f9,
+];
+
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    // RGB8
+    pub pixels: Vec<u8>,
+}
+
+// runs the frames through the emulator's MDEC, the same path the games take
+pub struct FrameDecoder {
+    mdec: MDEC,
+}
+
+impl FrameDecoder {
+    pub fn new() -> FrameDecoder {
+        let mut mdec = MDEC::new();
+
+        // the MDEC takes the matrix in zigzag order, once for luma and once for chroma
+        let quant: Vec<u8> = ZAGZIG.iter().map(|&position| QUANT_MATRIX[position]).collect();
+        mdec.write_command(SET_QUANT_TABLES);
+        for word in quant.chunks(4).cycle().take(32) {
+            mdec.write_command(u32::from_le_bytes([word[0], word[1], word[2], word[3]]));
+        }
+
+        mdec.write_command(SET_SCALE_TABLE);
+        for pair in SCALE_TABLE.chunks(2) {
+            mdec.write_command(pair[0] as u16 as u32 | ((pair[1] as u16 as u32) << 16));
+        }
+        FrameDecoder { mdec }
+    }
+
+    pub fn decode(&mut self, frame: &Frame) -> Result<Image, String> {
+        let (width, height) = (frame.width as usize, frame.height as usize);
+        if width == 0 || height == 0 {
+            return Err(format!("frame {} has no size", frame.number));
+        }
+        let rows = height.div_ceil(MACROBLOCK_SIZE);
+        let columns = width.div_ceil(MACROBLOCK_SIZE);
+        let (_, macroblocks) = decode_frame(&frame.data, rows * columns)
+            .map_err(|e| format!("frame {}: {}", frame.number, e))?;
+
+        let mut pixels = vec![0; width * height * 3];
+        for (index, codes) in macroblocks.iter().enumerate() {
+            // macroblocks are stored column by column, the order DecDCTout slices them in
+            let (x, y) = ((index / rows) * MACROBLOCK_SIZE, (index % rows) * MACROBLOCK_SIZE);
+
+            self.mdec.write_command(DECODE_24BPP | codes.len().div_ceil(2) as u32);
+            for pair in codes.chunks(2) {
+                let high = pair.get(1).copied().unwrap_or(0xfe00);
+                self.mdec.write_command(pair[0] as u32 | ((high as u32) << 16));
+            }
+            let rgb: Vec<u8> =
+                (0..MACROBLOCK_WORDS).flat_map(|_| self.mdec.read_data().to_le_bytes()).collect();
+
+            for row in 0..MACROBLOCK_SIZE.min(height - y) {
+                let visible = MACROBLOCK_SIZE.min(width - x);
+                let source = &rgb[row * MACROBLOCK_SIZE * 3..(row * MACROBLOCK_SIZE + visible) * 3];
+                let start = ((y + row) * width + x) * 3;
+                pixels[start..start + visible * 3].copy_from_slice(source);
+            }
+        }
+        Ok(Image { width, height, pixels })
+    }
+}
+
+impl Default for FrameDecoder {
+    fn default() -> FrameDecoder {
+        FrameDecoder::new()
+    }
+}
+
+pub fn write_png(image: &Image, path: &Path) -> Result<(), String> {
+    let error = |e: png::EncodingError| format!("{}: {}", path.display(), e);
+    let file = File::create(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let mut encoder =
+        png::Encoder::new(BufWriter::new(file), image.width as u32, image.height as u32);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header().map_err(error)?;
+    writer.write_image_data(&image.pixels).map_err(error)
+}
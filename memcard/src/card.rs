@@ -0,0 +1,385 @@
+use encoding_rs::SHIFT_JIS;
+use ps::sio::memory_card::{CARD_SIZE, SECTOR_SIZE, format};
+
+// 16 blocks of 8 KiB, block 0 holds the header and the directory frames of blocks 1-15
+pub const BLOCK_SIZE: usize = 8 * 1024;
+pub const BLOCK_COUNT: usize = 15;
+
+// directory frame states
+const STATE_FREE: u8 = 0xa0;
+const STATE_FIRST: u8 = 0x51;
+const STATE_MIDDLE: u8 = 0x52;
+const STATE_LAST: u8 = 0x53;
+// deleted blocks keep their data, the BIOS only flips the state
+const STATE_DELETED_FIRST: u8 = 0xa1;
+const STATE_DELETED_MIDDLE: u8 = 0xa2;
+const STATE_DELETED_LAST: u8 = 0xa3;
+
+const NO_NEXT_BLOCK: u16 = 0xffff;
+const FILENAME_LENGTH: usize = 20;
+const TITLE_LENGTH: usize = 64;
+
+#[derive(Clone, Debug)]
+pub struct Save {
+    // 1-15, the slot the directory shows it in
+    pub first_block: usize,
+    pub blocks: Vec<usize>,
+    // e.g. "BASCUS-94900CRASH", the product code followed by a game specific name
+    pub filename: String,
+    pub size: u32,
+    pub title: String,
+    // 1-3 animation frames, 0 when the title frame has no "SC" magic
+    pub icon_frames: usize,
+}
+
+// a save outside of a card: its directory frame followed by its blocks, the PSXGameEdit .mcs layout
+#[derive(Clone, Debug)]
+pub struct SaveFile {
+    pub directory: [u8; SECTOR_SIZE],
+    pub data: Vec<u8>,
+}
+
+impl SaveFile {
+    pub fn filename(&self) -> String {
+        read_filename(&self.directory)
+    }
+
+    pub fn blocks(&self) -> usize {
+        self.data.len() / BLOCK_SIZE
+    }
+}
+
+pub struct Card {
+    data: Box<[u8]>,
+}
+
+impl Card {
+    // an empty formatted card
+    pub fn new() -> Card {
+        Card { data: format() }
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Result<Card, String> {
+        if data.len() != CARD_SIZE {
+            return Err(format!("memory card images are {} bytes, got {}", CARD_SIZE, data.len()));
+        }
+        if &data[0..2] != b"MC" {
+            return Err(String::from("missing MC header, not a formatted memory card"));
+        }
+        Ok(Card { data: data.into() })
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    fn directory(&self, block: usize) -> &[u8] {
+        &self.data[block * SECTOR_SIZE..(block + 1) * SECTOR_SIZE]
+    }
+
+    fn directory_mut(&mut self, block: usize) -> &mut [u8] {
+        &mut self.data[block * SECTOR_SIZE..(block + 1) * SECTOR_SIZE]
+    }
+
+    pub fn block(&self, block: usize) -> &[u8] {
+        &self.data[block * BLOCK_SIZE..(block + 1) * BLOCK_SIZE]
+    }
+
+    fn state(&self, block: usize) -> u8 {
+        self.directory(block)[0]
+    }
+
+    fn next_block(&self, block: usize) -> Option<usize> {
+        let directory = self.directory(block);
+        match u16::from_le_bytes([directory[8], directory[9]]) {
+            next @ 0..=14 => Some(next as usize + 1),
+            _ => None,
+        }
+    }
+
+    pub fn free_blocks(&self) -> usize {
+        (1..=BLOCK_COUNT).filter(|&block| is_free(self.state(block))).count()
+    }
+
+    pub fn saves(&self) -> Vec<Save> {
+        (1..=BLOCK_COUNT)
+            .filter(|&block| self.state(block) == STATE_FIRST)
+            .map(|block| self.save(block))
+            .collect()
+    }
+
+    fn save(&self, first_block: usize) -> Save {
+        // a broken chain can't loop more than the number of blocks
+        let mut blocks = vec![first_block];
+        let mut block = first_block;
+        while let Some(next) = self.next_block(block) {
+            if blocks.contains(&next) || blocks.len() == BLOCK_COUNT {
+                break;
+            }
+            blocks.push(next);
+            block = next;
+        }
+
+        let directory = self.directory(first_block);
+        let title_frame = &self.block(first_block)[..SECTOR_SIZE];
+        let (title, icon_frames) = if &title_frame[0..2] == b"SC" {
+            let icon_frames = match title_frame[2] {
+                0x11 => 1,
+                0x12 => 2,
+                0x13 => 3,
+                _ => 1,
+            };
+            (decode_title(&title_frame[4..4 + TITLE_LENGTH]), icon_frames)
+        } else {
+            (String::new(), 0)
+        };
+
+        Save {
+            first_block,
+            blocks,
+            filename: read_filename(directory),
+            size: u32::from_le_bytes([directory[4], directory[5], directory[6], directory[7]]),
+            title,
+            icon_frames,
+        }
+    }
+
+    pub fn find(&self, first_block: usize) -> Option<Save> {
+        self.saves().into_iter().find(|save| save.first_block == first_block)
+    }
+
+    pub fn export(&self, first_block: usize) -> Result<SaveFile, String> {
+        let save = self
+            .find(first_block)
+            .ok_or_else(|| format!("no save starts at block {}", first_block))?;
+        let mut directory = [0; SECTOR_SIZE];
+        directory.copy_from_slice(self.directory(first_block));
+        // standalone saves have no next block
+        directory[8..10].copy_from_slice(&NO_NEXT_BLOCK.to_le_bytes());
+        set_checksum(&mut directory);
+
+        let data =
+            save.blocks.iter().flat_map(|&block| self.block(block).iter().copied()).collect();
+        Ok(SaveFile { directory, data })
+    }
+
+    // returns the first block the save was written to
+    pub fn import(&mut self, save: &SaveFile) -> Result<usize, String> {
+        let count = save.blocks();
+        if count == 0 || !save.data.len().is_multiple_of(BLOCK_SIZE) {
+            return Err(format!(
+                "save data has to be whole 8 KiB blocks, got {} bytes",
+                save.data.len()
+            ));
+        }
+        let filename = save.filename();
+        if self.saves().iter().any(|existing| existing.filename == filename) {
+            return Err(format!("a save named {} is already on the card", filename));
+        }
+        let free: Vec<usize> =
+            (1..=BLOCK_COUNT).filter(|&block| is_free(self.state(block))).take(count).collect();
+        if free.len() < count {
+            return Err(format!(
+                "{} needs {} blocks, the card has {} free",
+                filename,
+                count,
+                self.free_blocks()
+            ));
+        }
+
+        for (i, &block) in free.iter().enumerate() {
+            let state = match i {
+                0 => STATE_FIRST,
+                _ if i == count - 1 => STATE_LAST,
+                _ => STATE_MIDDLE,
+            };
+            let next = free.get(i + 1).map_or(NO_NEXT_BLOCK, |&next| (next - 1) as u16);
+
+            let mut directory = [0; SECTOR_SIZE];
+            directory[0] = state;
+            if i == 0 {
+                directory[4..8].copy_from_slice(&((count * BLOCK_SIZE) as u32).to_le_bytes());
+                directory[0x0a..0x0a + FILENAME_LENGTH]
+                    .copy_from_slice(&save.directory[0x0a..0x0a + FILENAME_LENGTH]);
+            }
+            directory[8..10].copy_from_slice(&next.to_le_bytes());
+            set_checksum(&mut directory);
+            self.directory_mut(block).copy_from_slice(&directory);
+
+            let data = &save.data[i * BLOCK_SIZE..(i + 1) * BLOCK_SIZE];
+            self.data[block * BLOCK_SIZE..(block + 1) * BLOCK_SIZE].copy_from_slice(data);
+        }
+        Ok(free[0])
+    }
+
+    pub fn delete(&mut self, first_block: usize) -> Result<Save, String> {
+        let save = self
+            .find(first_block)
+            .ok_or_else(|| format!("no save starts at block {}", first_block))?;
+        for &block in &save.blocks {
+            let directory = self.directory_mut(block);
+            directory[0] = match directory[0] {
+                STATE_FIRST => STATE_DELETED_FIRST,
+                STATE_MIDDLE => STATE_DELETED_MIDDLE,
+                STATE_LAST => STATE_DELETED_LAST,
+                state => state,
+            };
+            set_checksum(directory);
+        }
+        Ok(save)
+    }
+}
+
+impl Default for Card {
+    fn default() -> Card {
+        Card::new()
+    }
+}
+
+fn is_free(state: u8) -> bool {
+    matches!(state, STATE_FREE | STATE_DELETED_FIRST | STATE_DELETED_MIDDLE | STATE_DELETED_LAST)
+}
+
+fn read_filename(directory: &[u8]) -> String {
+    let name = &directory[0x0a..0x0a + FILENAME_LENGTH];
+    let end = name.iter().position(|&byte| byte == 0).unwrap_or(FILENAME_LENGTH);
+    String::from_utf8_lossy(&name[..end]).to_string()
+}
+
+// titles are Shift-JIS, mostly full width ASCII which we turn back into plain ASCII
+fn decode_title(title: &[u8]) -> String {
+    let end = title.iter().position(|&byte| byte == 0).unwrap_or(title.len());
+    let (decoded, _) = SHIFT_JIS.decode_without_bom_handling(&title[..end]);
+    decoded
+        .chars()
+        .map(|c| match c {
+            '\u{3000}' => ' ',
+            '\u{ff01}'..='\u{ff5e}' => char::from_u32(c as u32 - 0xff01 + 0x21).unwrap_or(c),
+            _ => c,
+        })
+        .collect::<String>()
+        .trim_end()
+        .to_string()
+}
+
+// byte 127 is the XOR of the other 127
+fn set_checksum(frame: &mut [u8]) {
+    frame[SECTOR_SIZE - 1] = frame[..SECTOR_SIZE - 1].iter().fold(0, |sum, byte| sum ^ byte);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a save with an "SC" title frame and every block filled with its own byte
+    fn save_file(filename: &str, blocks: usize, fill: u8) -> SaveFile {
+        let mut directory = [0; SECTOR_SIZE];
+        directory[0] = STATE_FIRST;
+        directory[0x0a..0x0a + filename.len()].copy_from_slice(filename.as_bytes());
+        let mut data: Vec<u8> = (0..blocks)
+            .flat_map(|block| vec![fill.wrapping_add(block as u8); BLOCK_SIZE])
+            .collect();
+        data[0..4].copy_from_slice(&[b'S', b'C', 0x11, 1]);
+        // "TEST" in full width letters
+        data[4..12].copy_from_slice(&[0x82, 0x73, 0x82, 0x64, 0x82, 0x72, 0x82, 0x73]);
+        data[12..4 + TITLE_LENGTH].fill(0);
+        SaveFile { directory, data }
+    }
+
+    fn checksum_ok(card: &Card, block: usize) -> bool {
+        card.directory(block).iter().fold(0, |sum, byte| sum ^ byte) == 0
+    }
+
+    #[test]
+    fn import_lists_the_save() {
+        let mut card = Card::new();
+        let first = card.import(&save_file("BASCUS-00000TEST", 3, 0x10)).unwrap();
+
+        assert_eq!(first, 1);
+        let saves = card.saves();
+        assert_eq!(saves.len(), 1);
+        assert_eq!(saves[0].blocks, vec![1, 2, 3]);
+        assert_eq!(saves[0].filename, "BASCUS-00000TEST");
+        assert_eq!(saves[0].size, 3 * BLOCK_SIZE as u32);
+        assert_eq!(saves[0].title, "TEST");
+        assert_eq!(saves[0].icon_frames, 1);
+        assert_eq!(card.free_blocks(), BLOCK_COUNT - 3);
+        assert_eq!(
+            [card.state(1), card.state(2), card.state(3)],
+            [STATE_FIRST, STATE_MIDDLE, STATE_LAST]
+        );
+        assert!((1..=3).all(|block| checksum_ok(&card, block)));
+    }
+
+    #[test]
+    fn export_import_round_trip() {
+        let mut card = Card::new();
+        card.import(&save_file("BASCUS-00000ONE", 1, 0x20)).unwrap();
+        let first = card.import(&save_file("BASCUS-00000TWO", 2, 0x30)).unwrap();
+        let exported = card.export(first).unwrap();
+
+        let mut other = Card::new();
+        let imported = other.import(&exported).unwrap();
+        let again = other.export(imported).unwrap();
+        assert_eq!(again.directory, exported.directory);
+        assert_eq!(again.data, exported.data);
+        assert_eq!(exported.blocks(), 2);
+        assert_eq!(exported.filename(), "BASCUS-00000TWO");
+        assert_eq!(
+            u16::from_le_bytes([exported.directory[8], exported.directory[9]]),
+            NO_NEXT_BLOCK
+        );
+    }
+
+    #[test]
+    fn delete_keeps_the_data_and_frees_the_blocks() {
+        let mut card = Card::new();
+        let first = card.import(&save_file("BASCUS-00000TEST", 2, 0x40)).unwrap();
+        let block = card.block(2).to_vec();
+
+        let deleted = card.delete(first).unwrap();
+        assert_eq!(deleted.blocks, vec![1, 2]);
+        assert!(card.saves().is_empty());
+        assert_eq!(card.free_blocks(), BLOCK_COUNT);
+        assert_eq!([card.state(1), card.state(2)], [STATE_DELETED_FIRST, STATE_DELETED_LAST]);
+        assert_eq!(card.block(2), &block[..]);
+        assert!(checksum_ok(&card, 1) && checksum_ok(&card, 2));
+        assert!(card.delete(first).is_err());
+    }
+
+    #[test]
+    fn import_chains_through_freed_blocks() {
+        let mut card = Card::new();
+        let small = card.import(&save_file("BASCUS-00000SMALL", 1, 0x50)).unwrap();
+        card.import(&save_file("BASCUS-00000KEPT", 2, 0x60)).unwrap();
+        card.delete(small).unwrap();
+
+        let save = save_file("BASCUS-00000LARGE", 3, 0x70);
+        let first = card.import(&save).unwrap();
+        assert_eq!(first, 1);
+        assert_eq!(card.find(first).unwrap().blocks, vec![1, 4, 5]);
+        assert_eq!(card.export(first).unwrap().data, save.data);
+    }
+
+    #[test]
+    fn import_refusals() {
+        let mut card = Card::new();
+        card.import(&save_file("BASCUS-00000TEST", 14, 0)).unwrap();
+
+        assert!(card.import(&save_file("BASCUS-00000TEST", 1, 0)).is_err());
+        assert!(card.import(&save_file("BASCUS-00000BIG", 2, 0)).is_err());
+        let mut partial = save_file("BASCUS-00000PART", 1, 0);
+        partial.data.pop();
+        assert!(card.import(&partial).is_err());
+        assert_eq!(card.free_blocks(), 1);
+    }
+
+    #[test]
+    fn looping_chain_ends() {
+        let mut card = Card::new();
+        let first = card.import(&save_file("BASCUS-00000TEST", 2, 0)).unwrap();
+        // block 2 points back at block 1
+        card.directory_mut(2)[8..10].copy_from_slice(&0u16.to_le_bytes());
+        assert_eq!(card.find(first).unwrap().blocks, vec![1, 2]);
+    }
+}
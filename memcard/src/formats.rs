@@ -0,0 +1,189 @@
+use std::fs;
+use std::path::Path;
+
+use crate::card::{BLOCK_SIZE, Card, SaveFile};
+use ps::sio::memory_card::{CARD_SIZE, SECTOR_SIZE};
+
+// DexDrive images start with a 64 byte header followed by 15 256 byte block comments
+const GME_MAGIC: &[u8] = b"123-456-STD";
+const GME_HEADER_SIZE: usize = 64 + 15 * 256;
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum CardFormat {
+    // .mcr/.mcd/.ps1 and friends, the 128 KiB card as is
+    Raw,
+    // DexDrive .gme
+    Gme,
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum SaveFormat {
+    // PSXGameEdit .mcs/.psx, the directory frame followed by the blocks
+    Mcs,
+}
+
+fn extension(path: &Path) -> String {
+    path.extension().map(|e| e.to_string_lossy().to_ascii_lowercase()).unwrap_or_default()
+}
+
+pub fn card_format(path: &Path) -> Option<CardFormat> {
+    match extension(path).as_str() {
+        "mcr" | "mcd" | "ps1" | "mc" | "bin" | "mem" | "ddf" => Some(CardFormat::Raw),
+        "gme" => Some(CardFormat::Gme),
+        _ => None,
+    }
+}
+
+pub fn save_format(path: &Path) -> Option<SaveFormat> {
+    match extension(path).as_str() {
+        "mcs" | "psx" => Some(SaveFormat::Mcs),
+        _ => None,
+    }
+}
+
+pub fn load_card(path: &Path) -> Result<Card, String> {
+    let data = fs::read(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let format = card_format(path)
+        .ok_or_else(|| format!("{}: unknown memory card format", path.display()))?;
+    let card = match format {
+        CardFormat::Raw => Card::from_bytes(&data),
+        CardFormat::Gme => {
+            if !data.starts_with(GME_MAGIC) || data.len() < GME_HEADER_SIZE + CARD_SIZE {
+                Err(String::from("not a DexDrive image"))
+            } else {
+                Card::from_bytes(&data[GME_HEADER_SIZE..GME_HEADER_SIZE + CARD_SIZE])
+            }
+        }
+    };
+    card.map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+pub fn write_card(card: &Card, path: &Path) -> Result<(), String> {
+    let format = card_format(path)
+        .ok_or_else(|| format!("{}: unknown memory card format", path.display()))?;
+    let data = match format {
+        CardFormat::Raw => card.data().to_vec(),
+        CardFormat::Gme => {
+            let mut data = gme_header(card);
+            data.extend_from_slice(card.data());
+            data
+        }
+    };
+    fs::write(path, data).map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+// DexPlorer fills in a copy of the directory states and next block pointers, the comments stay empty
+fn gme_header(card: &Card) -> Vec<u8> {
+    let mut header = vec![0; GME_HEADER_SIZE];
+    header[..GME_MAGIC.len()].copy_from_slice(GME_MAGIC);
+    header[0x12..0x18].copy_from_slice(&[0x00, 0x01, 0x00, 0x01, b'M', b'Q']);
+    for block in 0..16 {
+        let directory = &card.data()[block * SECTOR_SIZE..(block + 1) * SECTOR_SIZE];
+        header[0x20 + block] = directory[0];
+        header[0x30 + block] = directory[8];
+    }
+    header
+}
+
+pub fn load_save(path: &Path) -> Result<SaveFile, String> {
+    let data = fs::read(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    save_format(path).ok_or_else(|| format!("{}: unknown save format", path.display()))?;
+    if data.len() < SECTOR_SIZE + BLOCK_SIZE
+        || !(data.len() - SECTOR_SIZE).is_multiple_of(BLOCK_SIZE)
+    {
+        return Err(format!("{}: not a single save file ({} bytes)", path.display(), data.len()));
+    }
+
+    let mut directory = [0; SECTOR_SIZE];
+    directory.copy_from_slice(&data[..SECTOR_SIZE]);
+    Ok(SaveFile { directory, data: data[SECTOR_SIZE..].to_vec() })
+}
+
+pub fn write_save(save: &SaveFile, path: &Path) -> Result<(), String> {
+    save_format(path).ok_or_else(|| format!("{}: unknown save format", path.display()))?;
+    let mut data = save.directory.to_vec();
+    data.extend_from_slice(&save.data);
+    fs::write(path, data).map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::path::PathBuf;
+    use std::process;
+
+    fn temporary(name: &str) -> PathBuf {
+        env::temp_dir().join(format!("memcard-{}-{}", process::id(), name))
+    }
+
+    fn card_with_save() -> Card {
+        let mut directory = [0; SECTOR_SIZE];
+        directory[0x0a..0x0a + 16].copy_from_slice(b"BASCUS-00000TEST");
+        let data = (0..2 * BLOCK_SIZE).map(|i| (i / 7) as u8).collect();
+        let mut card = Card::new();
+        card.import(&SaveFile { directory, data }).unwrap();
+        card
+    }
+
+    fn round_trip_card(name: &str) {
+        let card = card_with_save();
+        let path = temporary(name);
+        write_card(&card, &path).unwrap();
+        let loaded = load_card(&path);
+        fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.unwrap().data(), card.data());
+    }
+
+    #[test]
+    fn raw_round_trip() {
+        round_trip_card("raw.mcr");
+    }
+
+    #[test]
+    fn gme_round_trip() {
+        round_trip_card("dexdrive.gme");
+    }
+
+    #[test]
+    fn gme_header_copies_the_directory() {
+        let card = card_with_save();
+        let header = gme_header(&card);
+        assert_eq!(header.len(), GME_HEADER_SIZE);
+        assert!(header.starts_with(GME_MAGIC));
+        // block 1 starts the save and goes on to block 2 (stored as 1)
+        assert_eq!(header[0x21..0x24], [0x51, 0x53, 0xa0]);
+        assert_eq!(header[0x31], 1);
+    }
+
+    #[test]
+    fn mcs_round_trip() {
+        let card = card_with_save();
+        let save = card.export(1).unwrap();
+        let path = temporary("save.mcs");
+        write_save(&save, &path).unwrap();
+        assert_eq!(fs::metadata(&path).unwrap().len() as usize, SECTOR_SIZE + 2 * BLOCK_SIZE);
+        let loaded = load_save(&path);
+        fs::remove_file(&path).unwrap();
+
+        let loaded = loaded.unwrap();
+        assert_eq!(loaded.directory, save.directory);
+        assert_eq!(loaded.data, save.data);
+        let mut other = Card::new();
+        other.import(&loaded).unwrap();
+        assert_eq!(other.data(), card.data());
+    }
+
+    #[test]
+    fn rejects_unknown_and_short_files() {
+        assert_eq!(card_format(Path::new("card.MCD")), Some(CardFormat::Raw));
+        assert_eq!(card_format(Path::new("card.txt")), None);
+        assert_eq!(save_format(Path::new("save.PSX")), Some(SaveFormat::Mcs));
+
+        let path = temporary("short.mcs");
+        fs::write(&path, vec![0; SECTOR_SIZE + 100]).unwrap();
+        let loaded = load_save(&path);
+        fs::remove_file(&path).unwrap();
+        assert!(loaded.is_err());
+    }
+}
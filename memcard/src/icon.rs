@@ -0,0 +1,53 @@
+use crate::card::{Card, Save};
+use ps::sio::memory_card::SECTOR_SIZE;
+
+pub const ICON_SIZE: usize = 16;
+
+// the title frame holds the 16 color CLUT, the 1-3 frames after it the 4bpp pixels
+const CLUT_OFFSET: usize = 0x60;
+
+// the BIOS shows every frame for 16 vblanks
+pub const FRAME_VBLANKS: usize = 16;
+
+pub struct Icon {
+    // 16x16 RGBA8 images
+    pub frames: Vec<Vec<u8>>,
+}
+
+impl Icon {
+    // decodes the icon from the first block of a save
+    pub fn decode(block: &[u8], frame_count: usize) -> Icon {
+        let clut: Vec<[u8; 4]> = (0..16)
+            .map(|i| {
+                let offset = CLUT_OFFSET + i * 2;
+                rgba(u16::from_le_bytes([block[offset], block[offset + 1]]))
+            })
+            .collect();
+
+        let frames = (1..=frame_count.min(3))
+            .map(|frame| {
+                let pixels = &block[frame * SECTOR_SIZE..(frame + 1) * SECTOR_SIZE];
+                // the low nibble is the left pixel
+                pixels
+                    .iter()
+                    .flat_map(|&byte| [byte & 0xf, byte >> 4])
+                    .flat_map(|index| clut[index as usize])
+                    .collect()
+            })
+            .collect();
+        Icon { frames }
+    }
+}
+
+impl Card {
+    pub fn icon(&self, save: &Save) -> Icon {
+        Icon::decode(self.block(save.first_block), save.icon_frames)
+    }
+}
+
+// 15 bit BGR, 0x0000 is transparent
+fn rgba(color: u16) -> [u8; 4] {
+    let expand = |value: u16| ((value << 3) | (value >> 2)) as u8;
+    let alpha = if color == 0 { 0 } else { 0xff };
+    [expand(color & 0x1f), expand((color >> 5) & 0x1f), expand((color >> 10) & 0x1f), alpha]
+}
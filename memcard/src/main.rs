@@ -0,0 +1,86 @@
+use memcard::card::Card;
+use memcard::formats::{load_card, load_save, write_card, write_save};
+use std::env;
+use std::path::Path;
+use std::process::exit;
+
+const USAGE: &str = "usage: memcard list <card>
+       memcard export <card> <block> <save.mcs>
+       memcard import <card> <save.mcs>...
+       memcard delete <card> <block>
+       memcard convert <card> <output card>
+       memcard format <card>
+cards: .mcr .mcd .ps1 (raw) .gme (DexDrive), saves: .mcs .psx (PSXGameEdit)";
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let result = match args.iter().map(String::as_str).collect::<Vec<_>>().as_slice() {
+        ["list", card] => list(Path::new(card)),
+        ["export", card, block, output] => export(Path::new(card), block, Path::new(output)),
+        ["import", card, saves @ ..] if !saves.is_empty() => import(Path::new(card), saves),
+        ["delete", card, block] => delete(Path::new(card), block),
+        ["convert", card, output] => {
+            load_card(Path::new(card)).and_then(|card| write_card(&card, Path::new(output)))
+        }
+        ["format", card] => write_card(&Card::new(), Path::new(card)),
+        _ => {
+            eprintln!("{}", USAGE);
+            exit(2);
+        }
+    };
+
+    if let Err(e) = result {
+        eprintln!("{}", e);
+        exit(1);
+    }
+}
+
+fn parse_block(block: &str) -> Result<usize, String> {
+    match block.parse() {
+        Ok(block @ 1..=15) => Ok(block),
+        _ => Err(format!("bad block {}, saves start at blocks 1-15", block)),
+    }
+}
+
+fn list(path: &Path) -> Result<(), String> {
+    let card = load_card(path)?;
+    for save in card.saves() {
+        println!(
+            "{:>2}  {:<20} {:>2} block{} {} icon frame{}  {}",
+            save.first_block,
+            save.filename,
+            save.blocks.len(),
+            if save.blocks.len() == 1 { " " } else { "s" },
+            save.icon_frames,
+            if save.icon_frames == 1 { " " } else { "s" },
+            save.title
+        );
+    }
+    println!("{} free blocks", card.free_blocks());
+    Ok(())
+}
+
+fn export(path: &Path, block: &str, output: &Path) -> Result<(), String> {
+    let card = load_card(path)?;
+    let save = card.export(parse_block(block)?)?;
+    write_save(&save, output)?;
+    println!("{} -> {} ({} blocks)", save.filename(), output.display(), save.blocks());
+    Ok(())
+}
+
+fn import(path: &Path, saves: &[&str]) -> Result<(), String> {
+    let mut card = load_card(path)?;
+    for save in saves {
+        let save = load_save(Path::new(save))?;
+        let block = card.import(&save)?;
+        println!("{} -> block {} ({} blocks)", save.filename(), block, save.blocks());
+    }
+    write_card(&card, path)
+}
+
+fn delete(path: &Path, block: &str) -> Result<(), String> {
+    let mut card = load_card(path)?;
+    let save = card.delete(parse_block(block)?)?;
+    println!("deleted {} ({} blocks)", save.filename, save.blocks.len());
+    write_card(&card, path)
+}
@@ -0,0 +1,399 @@
+use std::collections::VecDeque;
+
+use super::disc::{Disc, Msf, SECTOR_SIZE};
+use super::to_bcd;
+use super::xa::{Resampler, Subheader, XaDecoder};
+use crate::spu::SPU;
+
+// CD-DA sectors are 588 stereo frames of 16bit little endian samples
+pub const CDDA_FRAMES_PER_SECTOR: usize = SECTOR_SIZE / 4;
+
+// ATV0..ATV3, 0x80 is 100%
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct VolumeMatrix {
+    pub left_to_left: u8,
+    pub left_to_right: u8,
+    pub right_to_right: u8,
+    pub right_to_left: u8,
+}
+
+impl VolumeMatrix {
+    pub fn new() -> VolumeMatrix {
+        VolumeMatrix {
+            left_to_left: 0x80,
+            left_to_right: 0,
+            right_to_right: 0x80,
+            right_to_left: 0,
+        }
+    }
+
+    pub fn apply(&self, (left, right): (i16, i16)) -> (i16, i16) {
+        let (left, right) = (left as i32, right as i32);
+        let out_left = (left * self.left_to_left as i32 + right * self.right_to_left as i32) >> 7;
+        let out_right =
+            (left * self.left_to_right as i32 + right * self.right_to_right as i32) >> 7;
+        (clamp16(out_left), clamp16(out_right))
+    }
+}
+
+impl Default for VolumeMatrix {
+    fn default() -> VolumeMatrix {
+        VolumeMatrix::new()
+    }
+}
+
+// Everything between the drive and the SPU's CD input: CD-DA playback with its
+// position reports, XA-ADPCM decoding with the Setfilter file/channel selection,
+// and the volume matrix. The CD-ROM controller feeds it sectors as they are read.
+pub struct CdAudio {
+    // Setmode bit 6, XA-ADPCM sectors go to the SPU instead of the data fifo
+    pub xa_enabled: bool,
+    // Setmode bit 3, only play XA sectors matching the Setfilter file/channel
+    pub filter_enabled: bool,
+    pub filter_file: u8,
+    pub filter_channel: u8,
+    // Setmode bit 2, send a report interrupt every 10 CD-DA sectors
+    pub report_enabled: bool,
+    // ADPCTL bit 0
+    pub muted: bool,
+
+    // ATV registers are written to the pending matrix and take effect on "apply volume"
+    pub pending_volume: VolumeMatrix,
+    pub volume: VolumeMatrix,
+
+    decoder: XaDecoder,
+    resampler: Resampler,
+    // last XA file/channel played, a new stream restarts the ADPCM history
+    xa_stream: Option<(u8, u8)>,
+
+    // set by Play, cleared by Pause/Stop
+    pub playing: bool,
+    pub position: u32,
+
+    output: VecDeque<(i16, i16)>,
+}
+
+impl CdAudio {
+    pub fn new() -> CdAudio {
+        CdAudio {
+            xa_enabled: false,
+            filter_enabled: false,
+            filter_file: 0,
+            filter_channel: 0,
+            report_enabled: false,
+            muted: false,
+            pending_volume: VolumeMatrix::new(),
+            volume: VolumeMatrix::new(),
+            decoder: XaDecoder::new(),
+            resampler: Resampler::new(),
+            xa_stream: None,
+            playing: false,
+            position: 0,
+            output: VecDeque::new(),
+        }
+    }
+
+    pub fn set_filter(&mut self, file: u8, channel: u8) {
+        self.filter_file = file;
+        self.filter_channel = channel;
+    }
+
+    pub fn apply_volume(&mut self) {
+        self.volume = self.pending_volume;
+    }
+
+    // Play command, with no track given playback continues from the current position
+    pub fn play(&mut self, disc: &Disc, track: Option<u8>) {
+        if let Some(start) = track.and_then(|number| disc.track(number)).map(|track| track.start) {
+            self.position = start;
+        }
+        self.playing = true;
+    }
+
+    pub fn stop(&mut self) {
+        self.playing = false;
+        self.output.clear();
+    }
+
+    // reads the next CD-DA sector while playing, returns the report packet when one is due
+    pub fn play_sector(&mut self, disc: &mut Disc, stat: u8) -> Option<[u8; 8]> {
+        if !self.playing {
+            return None;
+        }
+        let sector = match disc.read_sector(self.position) {
+            Ok(sector) => sector,
+            Err(_) => {
+                // end of disc
+                self.playing = false;
+                return None;
+            }
+        };
+
+        let frames = cdda_frames(&sector);
+        let report = if self.report_enabled { self.report(disc, stat, &frames) } else { None };
+        self.output.extend(frames);
+        self.position += 1;
+        report
+    }
+
+    // Reports alternate between absolute and track relative time, relative positions have bit 7
+    // of the seconds set. The peak is the loudest sample of the sector, bit 15 marks the right channel.
+    fn report(&self, disc: &Disc, stat: u8, frames: &[(i16, i16)]) -> Option<[u8; 8]> {
+        let track = disc.track_at(self.position)?;
+        let absolute = Msf::from_lba(self.position);
+        if !absolute.frame.is_multiple_of(10) {
+            return None;
+        }
+
+        let relative = !absolute.frame.is_multiple_of(20);
+        let (time, second_flag) = if relative {
+            (Msf::from_sectors(self.position.saturating_sub(track.start)), 0x80)
+        } else {
+            (absolute, 0)
+        };
+
+        let peak = frames
+            .iter()
+            .map(|&(left, right)| if relative { right } else { left }.unsigned_abs())
+            .max()
+            .unwrap_or(0)
+            .min(0x7fff);
+        let peak = if relative { peak | 0x8000 } else { peak };
+
+        Some([
+            stat,
+            to_bcd(track.number),
+            0x01,
+            to_bcd(time.minute),
+            to_bcd(time.second) | second_flag,
+            to_bcd(time.frame),
+            peak as u8,
+            (peak >> 8) as u8,
+        ])
+    }
+
+    // returns false when the sector is not meant for the SPU and should go to the data fifo
+    pub fn push_xa_sector(&mut self, sector: &[u8]) -> bool {
+        let header = Subheader::from_sector(sector);
+        if !self.xa_enabled || !header.is_audio() || !header.is_form2() {
+            return false;
+        }
+        if self.filter_enabled
+            && (header.file != self.filter_file || header.channel != self.filter_channel)
+        {
+            // filtered out sectors are dropped, not delivered as data
+            return true;
+        }
+
+        if self.xa_stream != Some((header.file, header.channel)) {
+            self.xa_stream = Some((header.file, header.channel));
+            self.decoder.reset();
+            self.resampler.reset();
+        }
+
+        let mut samples = self.decoder.decode_sector(sector);
+        if header.sample_rate() == 18900 {
+            // half rate streams play every sample twice
+            samples = samples.into_iter().flat_map(|sample| [sample, sample]).collect();
+        }
+
+        let mut resampled = Vec::with_capacity(samples.len() * 7 / 6 + 1);
+        self.resampler.resample(&samples, 37800, &mut resampled);
+        self.output.extend(resampled);
+        true
+    }
+
+    // next 44.1 kHz sample after the volume matrix
+    pub fn next_sample(&mut self) -> (i16, i16) {
+        let sample = self.output.pop_front().unwrap_or((0, 0));
+        if self.muted { (0, 0) } else { self.volume.apply(sample) }
+    }
+
+    pub fn pending_samples(&self) -> usize {
+        self.output.len()
+    }
+
+    // moves everything decoded so far into the SPU's CD input
+    pub fn feed_spu(&mut self, spu: &mut SPU) {
+        while !self.output.is_empty() {
+            let (left, right) = self.next_sample();
+            spu.push_cd_input(left, right);
+        }
+    }
+}
+
+impl Default for CdAudio {
+    fn default() -> CdAudio {
+        CdAudio::new()
+    }
+}
+
+pub fn cdda_frames(sector: &[u8]) -> Vec<(i16, i16)> {
+    sector
+        .chunks_exact(4)
+        .map(|frame| {
+            (i16::from_le_bytes([frame[0], frame[1]]), i16::from_le_bytes([frame[2], frame[3]]))
+        })
+        .collect()
+}
+
+fn clamp16(value: i32) -> i16 {
+    value.clamp(i16::MIN as i32, i16::MAX as i32) as i16
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cdrom::xa::submode;
+    use std::fs;
+
+    fn xa_sector(file: u8, channel: u8, coding: u8) -> Vec<u8> {
+        let mut sector = vec![0; SECTOR_SIZE];
+        sector[16..20].copy_from_slice(&[file, channel, submode::AUDIO | submode::FORM2, coding]);
+        sector
+    }
+
+    fn xa_audio() -> CdAudio {
+        let mut audio = CdAudio::new();
+        audio.xa_enabled = true;
+        audio
+    }
+
+    #[test]
+    fn xa_samples_per_sector() {
+        // 4032 samples at 37.8 kHz are 4704 at 44.1 kHz, 18.9 kHz sectors last twice as long
+        let mut audio = xa_audio();
+        assert!(audio.push_xa_sector(&xa_sector(1, 0, 0x00)));
+        assert_eq!(audio.pending_samples(), 4704);
+
+        let mut audio = xa_audio();
+        assert!(audio.push_xa_sector(&xa_sector(1, 0, 0x04)));
+        assert_eq!(audio.pending_samples(), 9408);
+
+        // stereo halves the samples per channel
+        let mut audio = xa_audio();
+        assert!(audio.push_xa_sector(&xa_sector(1, 0, 0x01)));
+        assert_eq!(audio.pending_samples(), 2352);
+        assert!(audio.push_xa_sector(&xa_sector(1, 0, 0x05)));
+        assert_eq!(audio.pending_samples(), 2352 + 4704);
+    }
+
+    #[test]
+    fn setfilter_drops_other_channels() {
+        let mut audio = xa_audio();
+        audio.filter_enabled = true;
+        audio.set_filter(1, 3);
+        // taken away from the data fifo but not played
+        assert!(audio.push_xa_sector(&xa_sector(1, 2, 0x00)));
+        assert!(audio.push_xa_sector(&xa_sector(2, 3, 0x00)));
+        assert_eq!(audio.pending_samples(), 0);
+        assert!(audio.push_xa_sector(&xa_sector(1, 3, 0x00)));
+        assert_eq!(audio.pending_samples(), 4704);
+
+        // without the filter every channel plays
+        audio.filter_enabled = false;
+        assert!(audio.push_xa_sector(&xa_sector(2, 0, 0x00)));
+        assert_eq!(audio.pending_samples(), 2 * 4704);
+    }
+
+    #[test]
+    fn data_sectors_go_to_the_fifo() {
+        let mut audio = CdAudio::new();
+        assert!(!audio.push_xa_sector(&xa_sector(1, 0, 0x00)));
+        let mut audio = xa_audio();
+        let mut data = xa_sector(1, 0, 0x00);
+        data[18] = submode::DATA;
+        assert!(!audio.push_xa_sector(&data));
+        data[18] = submode::AUDIO;
+        assert!(!audio.push_xa_sector(&data));
+        assert_eq!(audio.pending_samples(), 0);
+    }
+
+    #[test]
+    fn volume_matrix() {
+        let mut audio = CdAudio::new();
+        audio.output.extend([(1000, -2000), (1000, -2000), (1000, -2000)]);
+        assert_eq!(audio.next_sample(), (1000, -2000));
+
+        audio.pending_volume = VolumeMatrix {
+            left_to_left: 0x40,
+            left_to_right: 0x80,
+            right_to_right: 0,
+            right_to_left: 0x80,
+        };
+        assert_eq!(audio.next_sample(), (1000, -2000));
+        audio.apply_volume();
+        assert_eq!(audio.next_sample(), (500 - 2000, 1000));
+
+        audio.muted = true;
+        audio.output.push_back((i16::MAX, i16::MAX));
+        assert_eq!(audio.next_sample(), (0, 0));
+        audio.muted = false;
+        audio.volume = VolumeMatrix { left_to_left: 0xff, ..VolumeMatrix::new() };
+        audio.output.push_back((i16::MAX, 0));
+        assert_eq!(audio.next_sample(), (i16::MAX, 0));
+    }
+
+    // track 1 is 10 data sectors, track 2 the audio after it, every sector's samples are
+    // (lba, -2 * lba)
+    fn disc() -> (Disc, std::path::PathBuf) {
+        let folder = std::env::temp_dir().join(format!("ps-cdda-{}", std::process::id()));
+        fs::create_dir_all(&folder).unwrap();
+        fs::write(folder.join("data.bin"), vec![0; 10 * SECTOR_SIZE]).unwrap();
+        let audio: Vec<u8> = (10..60i16)
+            .flat_map(|lba| {
+                let frame = [lba.to_le_bytes(), (-2 * lba).to_le_bytes()].concat();
+                frame.repeat(CDDA_FRAMES_PER_SECTOR)
+            })
+            .collect();
+        fs::write(folder.join("audio.bin"), audio).unwrap();
+        fs::write(
+            folder.join("disc.cue"),
+            "FILE \"data.bin\" BINARY\n  TRACK 01 MODE2/2352\n    INDEX 01 00:00:00\n\
+             FILE \"audio.bin\" BINARY\n  TRACK 02 AUDIO\n    INDEX 01 00:00:00\n",
+        )
+        .unwrap();
+        (Disc::open(&folder.join("disc.cue")).unwrap(), folder)
+    }
+
+    #[test]
+    fn reports_every_ten_sectors() {
+        let (mut disc, folder) = disc();
+        let mut audio = CdAudio::new();
+        audio.report_enabled = true;
+        audio.play(&disc, Some(2));
+        assert_eq!(audio.position, 10);
+
+        let reports: Vec<(u32, [u8; 8])> = (10..35)
+            .filter_map(|lba| audio.play_sector(&mut disc, 0x82).map(|report| (lba, report)))
+            .collect();
+        fs::remove_dir_all(&folder).unwrap();
+
+        // lba 10 is 00:02:10, frames 10, 30, 50 and 70 report the time in the track with the
+        // right channel's peak, frames 0, 20, 40 and 60 the absolute time with the left one's
+        assert_eq!(
+            reports,
+            [
+                (10, [0x82, 0x02, 0x01, 0x00, 0x80, 0x00, 20, 0x80]),
+                (20, [0x82, 0x02, 0x01, 0x00, 0x02, 0x20, 20, 0x00]),
+                (30, [0x82, 0x02, 0x01, 0x00, 0x80, 0x20, 60, 0x80]),
+            ]
+        );
+        assert_eq!(audio.pending_samples(), 25 * CDDA_FRAMES_PER_SECTOR);
+        assert_eq!(audio.next_sample(), (10, -20));
+    }
+
+    #[test]
+    fn playback_stops_at_the_end_of_the_disc() {
+        let (mut disc, folder) = disc();
+        let mut audio = CdAudio::new();
+        audio.play(&disc, Some(2));
+        audio.position = 59;
+        assert_eq!(audio.play_sector(&mut disc, 0x82), None);
+        assert!(audio.playing);
+        assert_eq!(audio.play_sector(&mut disc, 0x82), None);
+        fs::remove_dir_all(&folder).unwrap();
+        assert!(!audio.playing);
+        assert_eq!(audio.pending_samples(), CDDA_FRAMES_PER_SECTOR);
+    }
+}
@@ -0,0 +1,273 @@
+use std::fs::File;
+use std::io::{self, Error, ErrorKind, Read, Seek, SeekFrom};
+use std::path::Path;
+
+use super::to_bcd;
+
+// raw sector as read by the drive: 12 sync bytes, 4 header bytes, then mode dependent data
+pub const SECTOR_SIZE: usize = 2352;
+// user data of a Mode1 / Mode2 Form1 sector, this is what the filesystem sees
+pub const DATA_SIZE: usize = 2048;
+// lba 0 is at 00:02:00, the first two seconds are the lead-in pregap
+pub const PREGAP_SECTORS: u32 = 150;
+pub const SECTORS_PER_SECOND: u32 = 75;
+
+pub const SYNC: [u8; 12] = [0x00, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x00];
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Msf {
+    pub minute: u8,
+    pub second: u8,
+    pub frame: u8,
+}
+
+impl Msf {
+    pub fn new(minute: u8, second: u8, frame: u8) -> Msf {
+        Msf { minute, second, frame }
+    }
+
+    pub fn from_lba(lba: u32) -> Msf {
+        Msf::from_sectors(lba + PREGAP_SECTORS)
+    }
+
+    pub fn to_lba(&self) -> u32 {
+        self.to_sectors().saturating_sub(PREGAP_SECTORS)
+    }
+
+    // plain sector count without the pregap, used for cue sheet offsets and relative positions
+    pub fn to_sectors(&self) -> u32 {
+        (self.minute as u32 * 60 + self.second as u32) * SECTORS_PER_SECOND + self.frame as u32
+    }
+
+    pub fn from_sectors(sectors: u32) -> Msf {
+        Msf {
+            minute: (sectors / (60 * SECTORS_PER_SECOND)) as u8,
+            second: ((sectors / SECTORS_PER_SECOND) % 60) as u8,
+            frame: (sectors % SECTORS_PER_SECOND) as u8,
+        }
+    }
+
+    pub fn to_bcd(&self) -> [u8; 3] {
+        [to_bcd(self.minute), to_bcd(self.second), to_bcd(self.frame)]
+    }
+
+    // "mm:ss:ff" as written in cue sheets
+    pub fn parse(text: &str) -> Option<Msf> {
+        let mut parts = text.split(':').map(|p| p.trim().parse::<u8>().ok());
+        let msf = Msf::new(parts.next()??, parts.next()??, parts.next()??);
+        if parts.next().is_some() || msf.second >= 60 || msf.frame >= 75 {
+            return None;
+        }
+        Some(msf)
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum TrackKind {
+    Audio,
+    Mode1,
+    Mode2,
+}
+
+#[derive(Clone, Debug)]
+pub struct Track {
+    pub number: u8,
+    pub kind: TrackKind,
+    // lba of INDEX 01
+    pub start: u32,
+    pub length: u32,
+
+    file: usize,
+    // lba of the first sector stored in the track's file
+    file_start: u32,
+    // 2352 for raw images, 2048 for cooked .iso files
+    sector_size: usize,
+}
+
+impl Track {
+    pub fn contains(&self, lba: u32) -> bool {
+        lba >= self.start && lba < self.start + self.length
+    }
+}
+
+pub struct Disc {
+    files: Vec<File>,
+    pub tracks: Vec<Track>,
+}
+
+impl Disc {
+    // .cue sheets are parsed for their tracks, anything else is treated as a single data track
+    pub fn open(path: &Path) -> io::Result<Disc> {
+        let is_cue = path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("cue"));
+        if is_cue { Disc::from_cue(path) } else { Disc::from_image(path) }
+    }
+
+    pub fn from_image(path: &Path) -> io::Result<Disc> {
+        let file = File::open(path)?;
+        let size = file.metadata()?.len();
+
+        let sector_size = if size % SECTOR_SIZE as u64 == 0 {
+            SECTOR_SIZE
+        } else if size % DATA_SIZE as u64 == 0 {
+            DATA_SIZE
+        } else {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "image size is not a multiple of a sector",
+            ));
+        };
+
+        let track = Track {
+            number: 1,
+            kind: if sector_size == DATA_SIZE { TrackKind::Mode1 } else { TrackKind::Mode2 },
+            start: 0,
+            length: (size / sector_size as u64) as u32,
+            file: 0,
+            file_start: 0,
+            sector_size,
+        };
+        Ok(Disc { files: vec![file], tracks: vec![track] })
+    }
+
+    pub fn from_cue(path: &Path) -> io::Result<Disc> {
+        let sheet = std::fs::read_to_string(path)?;
+        let dir = path.parent().unwrap_or(Path::new("."));
+        let invalid =
+            |line: &str| Error::new(ErrorKind::InvalidData, format!("bad cue line: {}", line));
+
+        let mut files: Vec<File> = Vec::new();
+        let mut tracks: Vec<Track> = Vec::new();
+        // lba where the current file starts, and its length in sectors
+        let mut file_start = 0;
+        let mut file_sectors = 0;
+
+        for line in sheet.lines() {
+            let line = line.trim();
+            let mut words = line.split_whitespace();
+
+            match words.next() {
+                Some("FILE") => {
+                    let name = match (line.find('"'), line.rfind('"')) {
+                        (Some(open), Some(close)) if close > open => &line[open + 1..close],
+                        _ => words.next().ok_or_else(|| invalid(line))?,
+                    };
+                    let file = File::open(dir.join(name))?;
+
+                    file_start += file_sectors;
+                    file_sectors = (file.metadata()?.len() / SECTOR_SIZE as u64) as u32;
+                    files.push(file);
+                }
+                Some("TRACK") => {
+                    if files.is_empty() {
+                        return Err(invalid(line));
+                    }
+                    let number =
+                        words.next().and_then(|n| n.parse().ok()).ok_or_else(|| invalid(line))?;
+                    let kind = match words.next() {
+                        Some("AUDIO") => TrackKind::Audio,
+                        Some(mode) if mode.starts_with("MODE1") => TrackKind::Mode1,
+                        Some(mode) if mode.starts_with("MODE2") => TrackKind::Mode2,
+                        _ => return Err(invalid(line)),
+                    };
+                    tracks.push(Track {
+                        number,
+                        kind,
+                        start: file_start,
+                        length: 0,
+                        file: files.len() - 1,
+                        file_start,
+                        sector_size: SECTOR_SIZE,
+                    });
+                }
+                Some("INDEX") => {
+                    let index = words.next().ok_or_else(|| invalid(line))?;
+                    let msf = words.next().and_then(Msf::parse).ok_or_else(|| invalid(line))?;
+                    let track = tracks.last_mut().ok_or_else(|| invalid(line))?;
+                    if index == "01" {
+                        track.start = file_start + msf.to_sectors();
+                    }
+                }
+                _ => (),
+            }
+        }
+
+        if tracks.is_empty() {
+            return Err(Error::new(ErrorKind::InvalidData, "cue sheet has no tracks"));
+        }
+
+        // a track runs until the next one starts (its pregap included) or its file ends
+        let disc_end = file_start + file_sectors;
+        for i in 0..tracks.len() {
+            let end = match tracks.get(i + 1) {
+                Some(next) if next.file == tracks[i].file => next.start,
+                Some(next) => next.file_start,
+                None => disc_end,
+            };
+            tracks[i].length = end.saturating_sub(tracks[i].start);
+        }
+
+        Ok(Disc { files, tracks })
+    }
+
+    pub fn track(&self, number: u8) -> Option<&Track> {
+        self.tracks.iter().find(|track| track.number == number)
+    }
+
+    pub fn track_at(&self, lba: u32) -> Option<&Track> {
+        self.tracks.iter().find(|track| track.contains(lba))
+    }
+
+    pub fn first_track(&self) -> u8 {
+        self.tracks.first().map_or(1, |track| track.number)
+    }
+
+    pub fn last_track(&self) -> u8 {
+        self.tracks.last().map_or(1, |track| track.number)
+    }
+
+    // lba of the lead-out area
+    pub fn end(&self) -> u32 {
+        self.tracks.last().map_or(0, |track| track.start + track.length)
+    }
+
+    pub fn read_sector(&mut self, lba: u32) -> io::Result<[u8; SECTOR_SIZE]> {
+        let track = self
+            .track_at(lba)
+            .ok_or_else(|| {
+                Error::new(
+                    ErrorKind::UnexpectedEof,
+                    format!("lba {} is past the end of the disc", lba),
+                )
+            })?
+            .clone();
+
+        let mut sector = [0u8; SECTOR_SIZE];
+        let offset = (lba - track.file_start) as u64 * track.sector_size as u64;
+        let file = &mut self.files[track.file];
+        file.seek(SeekFrom::Start(offset))?;
+
+        if track.sector_size == SECTOR_SIZE {
+            file.read_exact(&mut sector)?;
+        } else {
+            // cooked images only store user data, rebuild the Mode1 framing around it
+            file.read_exact(&mut sector[16..16 + DATA_SIZE])?;
+            sector[..12].copy_from_slice(&SYNC);
+            sector[12..15].copy_from_slice(&Msf::from_lba(lba).to_bcd());
+            sector[15] = 1;
+        }
+        Ok(sector)
+    }
+
+    // the 2048 byte user data of a data sector
+    pub fn read_data(&mut self, lba: u32) -> io::Result<[u8; DATA_SIZE]> {
+        let sector = self.read_sector(lba)?;
+        let mut data = [0u8; DATA_SIZE];
+        data.copy_from_slice(&sector[data_offset(&sector)..data_offset(&sector) + DATA_SIZE]);
+        Ok(data)
+    }
+}
+
+// Mode1 data follows the header, Mode2 has the 8 byte XA subheader in between
+pub fn data_offset(sector: &[u8]) -> usize {
+    if sector[15] == 2 { 24 } else { 16 }
+}
@@ -0,0 +1,259 @@
+use std::io::{self, Error, ErrorKind};
+
+use super::disc::{DATA_SIZE, Disc, SECTOR_SIZE};
+
+// the volume descriptors start after the 16 sector system area
+const VOLUME_DESCRIPTOR_LBA: u32 = 16;
+const PRIMARY_VOLUME_DESCRIPTOR: u8 = 1;
+const VOLUME_DESCRIPTOR_TERMINATOR: u8 = 255;
+
+const FLAG_DIRECTORY: u8 = 0x02;
+
+// XA attribute bits, stored big endian in the system use area of directory records
+pub const XA_FORM1: u16 = 0x0800;
+pub const XA_FORM2: u16 = 0x1000;
+pub const XA_INTERLEAVED: u16 = 0x2000;
+pub const XA_CDDA: u16 = 0x4000;
+pub const XA_DIRECTORY: u16 = 0x8000;
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct XaAttributes {
+    pub group_id: u16,
+    pub user_id: u16,
+    pub attributes: u16,
+    pub file_number: u8,
+}
+
+impl XaAttributes {
+    // files that have to be read as raw sectors, XA audio and STR movies
+    pub fn is_raw(&self) -> bool {
+        self.attributes & (XA_FORM2 | XA_INTERLEAVED | XA_CDDA) != 0
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct RecordingDate {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+}
+
+#[derive(Clone, Debug)]
+pub struct DirectoryRecord {
+    // without the ";1" version suffix, "." and ".." for the self and parent entries
+    pub name: String,
+    pub lba: u32,
+    pub size: u32,
+    pub flags: u8,
+    pub date: RecordingDate,
+    pub xa: Option<XaAttributes>,
+}
+
+impl DirectoryRecord {
+    fn parse(record: &[u8]) -> Option<DirectoryRecord> {
+        let length = *record.first()? as usize;
+        if length < 34 || record.len() < length {
+            return None;
+        }
+
+        let name_length = record[32] as usize;
+        let raw_name = record.get(33..33 + name_length)?;
+        let name = match raw_name {
+            [0] => String::from("."),
+            [1] => String::from(".."),
+            _ => {
+                let name = String::from_utf8_lossy(raw_name);
+                name.split(';').next().unwrap_or_default().to_string()
+            }
+        };
+
+        // the system use area is word aligned after the name
+        let system_use = 33 + name_length + (1 - name_length % 2);
+        let xa = match record.get(system_use..length) {
+            Some(area) if area.len() >= 14 && &area[6..8] == b"XA" => Some(XaAttributes {
+                group_id: u16::from_be_bytes([area[0], area[1]]),
+                user_id: u16::from_be_bytes([area[2], area[3]]),
+                attributes: u16::from_be_bytes([area[4], area[5]]),
+                file_number: area[8],
+            }),
+            _ => None,
+        };
+
+        Some(DirectoryRecord {
+            name,
+            lba: read_u32(record, 2),
+            size: read_u32(record, 10),
+            flags: record[25],
+            date: RecordingDate {
+                year: 1900 + record[18] as u16,
+                month: record[19],
+                day: record[20],
+                hour: record[21],
+                minute: record[22],
+                second: record[23],
+            },
+            xa,
+        })
+    }
+
+    pub fn is_directory(&self) -> bool {
+        self.flags & FLAG_DIRECTORY != 0
+    }
+
+    pub fn sectors(&self) -> u32 {
+        self.size.div_ceil(DATA_SIZE as u32)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct VolumeDescriptor {
+    pub system_id: String,
+    pub volume_id: String,
+    pub volume_space_size: u32,
+    pub logical_block_size: u16,
+    pub path_table_size: u32,
+    pub path_table_lba: u32,
+    pub root: DirectoryRecord,
+}
+
+#[derive(Clone, Debug)]
+pub struct PathTableEntry {
+    pub name: String,
+    pub lba: u32,
+    // 1 based index of the parent entry, the root is its own parent
+    pub parent: u16,
+}
+
+pub struct Iso9660<'a> {
+    disc: &'a mut Disc,
+    pub volume: VolumeDescriptor,
+}
+
+impl<'a> Iso9660<'a> {
+    pub fn open(disc: &'a mut Disc) -> io::Result<Iso9660<'a>> {
+        let mut lba = VOLUME_DESCRIPTOR_LBA;
+        loop {
+            let sector = disc.read_data(lba)?;
+            if &sector[1..6] != b"CD001" || sector[0] == VOLUME_DESCRIPTOR_TERMINATOR {
+                return Err(invalid("no primary volume descriptor"));
+            }
+            if sector[0] == PRIMARY_VOLUME_DESCRIPTOR {
+                let root = DirectoryRecord::parse(&sector[156..190])
+                    .ok_or_else(|| invalid("bad root record"))?;
+                let volume = VolumeDescriptor {
+                    system_id: read_string(&sector[8..40]),
+                    volume_id: read_string(&sector[40..72]),
+                    volume_space_size: read_u32(&sector, 80),
+                    logical_block_size: u16::from_le_bytes([sector[128], sector[129]]),
+                    path_table_size: read_u32(&sector, 132),
+                    path_table_lba: read_u32(&sector, 140),
+                    root,
+                };
+                return Ok(Iso9660 { disc, volume });
+            }
+            lba += 1;
+        }
+    }
+
+    pub fn path_table(&mut self) -> io::Result<Vec<PathTableEntry>> {
+        let table = self.read_extent(self.volume.path_table_lba, self.volume.path_table_size)?;
+        let mut entries = Vec::new();
+        let mut offset = 0;
+
+        while offset + 8 <= table.len() {
+            let name_length = table[offset] as usize;
+            if name_length == 0 {
+                break;
+            }
+            let name = table
+                .get(offset + 8..offset + 8 + name_length)
+                .ok_or_else(|| invalid("bad path table"))?;
+            entries.push(PathTableEntry {
+                // the root is stored with a single zero byte name
+                name: if name == [0] {
+                    String::new()
+                } else {
+                    String::from_utf8_lossy(name).to_string()
+                },
+                lba: read_u32(&table, offset + 2),
+                parent: u16::from_le_bytes([table[offset + 6], table[offset + 7]]),
+            });
+            offset += 8 + name_length + name_length % 2;
+        }
+        Ok(entries)
+    }
+
+    pub fn read_dir(&mut self, dir: &DirectoryRecord) -> io::Result<Vec<DirectoryRecord>> {
+        let data = self.read_extent(dir.lba, dir.size)?;
+        let mut records = Vec::new();
+
+        // records never cross a sector boundary, a zero length pads to the next sector
+        for sector in data.chunks(DATA_SIZE) {
+            let mut offset = 0;
+            while offset < sector.len() && sector[offset] != 0 {
+                let length = sector[offset] as usize;
+                let record = DirectoryRecord::parse(&sector[offset..])
+                    .ok_or_else(|| invalid("bad directory record"))?;
+                records.push(record);
+                offset += length;
+            }
+        }
+        Ok(records)
+    }
+
+    // case insensitive lookup of a '/' or '\' separated path, "cdrom:\" prefixes are accepted
+    pub fn find(&mut self, path: &str) -> io::Result<Option<DirectoryRecord>> {
+        let path = path.trim_start_matches("cdrom:");
+        let mut current = self.volume.root.clone();
+
+        for component in path.split(['/', '\\']).filter(|c| !c.is_empty()) {
+            let component = component.split(';').next().unwrap_or_default();
+            if !current.is_directory() {
+                return Ok(None);
+            }
+            let entries = self.read_dir(&current)?;
+            match entries.into_iter().find(|e| e.name.eq_ignore_ascii_case(component)) {
+                Some(entry) => current = entry,
+                None => return Ok(None),
+            }
+        }
+        Ok(Some(current))
+    }
+
+    pub fn read_file(&mut self, file: &DirectoryRecord) -> io::Result<Vec<u8>> {
+        self.read_extent(file.lba, file.size)
+    }
+
+    // whole 2352 byte sectors, needed for XA audio and STR files whose sectors are Form2
+    pub fn read_file_raw(&mut self, file: &DirectoryRecord) -> io::Result<Vec<u8>> {
+        let mut data = Vec::with_capacity(file.sectors() as usize * SECTOR_SIZE);
+        for lba in file.lba..file.lba + file.sectors() {
+            data.extend_from_slice(&self.disc.read_sector(lba)?);
+        }
+        Ok(data)
+    }
+
+    fn read_extent(&mut self, lba: u32, size: u32) -> io::Result<Vec<u8>> {
+        let mut data = Vec::with_capacity(size as usize);
+        for lba in lba..lba + size.div_ceil(DATA_SIZE as u32) {
+            data.extend_from_slice(&self.disc.read_data(lba)?);
+        }
+        data.truncate(size as usize);
+        Ok(data)
+    }
+}
+
+fn read_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([data[offset], data[offset + 1], data[offset + 2], data[offset + 3]])
+}
+
+fn read_string(data: &[u8]) -> String {
+    String::from_utf8_lossy(data).trim_end().to_string()
+}
+
+fn invalid(message: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, message.to_string())
+}
@@ -0,0 +1,13 @@
+pub mod audio;
+pub mod disc;
+pub mod iso9660;
+pub mod xa;
+
+// the controller reports positions and track numbers in BCD
+pub fn to_bcd(value: u8) -> u8 {
+    ((value / 10) << 4) | (value % 10)
+}
+
+pub fn from_bcd(value: u8) -> u8 {
+    (value >> 4) * 10 + (value & 0xf)
+}
@@ -0,0 +1,344 @@
+// XA-ADPCM, the compressed audio interleaved with data on Mode2 Form2 sectors.
+// A sector holds 18 sound groups of 128 bytes, every group has 16 header bytes
+// followed by 28 words of samples split between 4 (8bit) or 8 (4bit) sound units.
+
+const SUBHEADER_OFFSET: usize = 16;
+const AUDIO_OFFSET: usize = 24;
+const SOUND_GROUPS: usize = 18;
+const SOUND_GROUP_SIZE: usize = 128;
+const SAMPLES_PER_UNIT: usize = 28;
+
+const POS_TABLE: [i32; 4] = [0, 60, 115, 98];
+const NEG_TABLE: [i32; 4] = [0, 0, -52, -55];
+
+pub const OUTPUT_RATE: u32 = 44100;
+
+pub mod submode {
+    pub const END_OF_RECORD: u8 = 0x01;
+    pub const VIDEO: u8 = 0x02;
+    pub const AUDIO: u8 = 0x04;
+    pub const DATA: u8 = 0x08;
+    pub const TRIGGER: u8 = 0x10;
+    pub const FORM2: u8 = 0x20;
+    pub const REAL_TIME: u8 = 0x40;
+    pub const END_OF_FILE: u8 = 0x80;
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Subheader {
+    pub file: u8,
+    pub channel: u8,
+    pub submode: u8,
+    pub coding: u8,
+}
+
+impl Subheader {
+    pub fn from_sector(sector: &[u8]) -> Subheader {
+        Subheader {
+            file: sector[SUBHEADER_OFFSET],
+            channel: sector[SUBHEADER_OFFSET + 1],
+            submode: sector[SUBHEADER_OFFSET + 2],
+            coding: sector[SUBHEADER_OFFSET + 3],
+        }
+    }
+
+    pub fn is_audio(&self) -> bool {
+        self.submode & submode::AUDIO != 0
+    }
+
+    pub fn is_video(&self) -> bool {
+        self.submode & submode::VIDEO != 0
+    }
+
+    pub fn is_form2(&self) -> bool {
+        self.submode & submode::FORM2 != 0
+    }
+
+    pub fn is_end_of_file(&self) -> bool {
+        self.submode & submode::END_OF_FILE != 0
+    }
+
+    pub fn stereo(&self) -> bool {
+        self.coding & 0x3 == 1
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        if (self.coding >> 2) & 0x3 == 1 { 18900 } else { 37800 }
+    }
+
+    pub fn bits_per_sample(&self) -> u32 {
+        if (self.coding >> 4) & 0x3 == 1 { 8 } else { 4 }
+    }
+}
+
+// ADPCM history of one channel
+#[derive(Copy, Clone, Default)]
+struct History {
+    old: i32,
+    older: i32,
+}
+
+#[derive(Clone)]
+pub struct XaDecoder {
+    history: [History; 2],
+}
+
+impl XaDecoder {
+    pub fn new() -> XaDecoder {
+        XaDecoder { history: [History::default(); 2] }
+    }
+
+    pub fn reset(&mut self) {
+        self.history = [History::default(); 2];
+    }
+
+    // decodes a whole sector at its native rate, mono sectors are duplicated to both channels
+    pub fn decode_sector(&mut self, sector: &[u8]) -> Vec<(i16, i16)> {
+        let header = Subheader::from_sector(sector);
+        let eight_bit = header.bits_per_sample() == 8;
+        let units = if eight_bit { 4 } else { 8 };
+
+        let mut left: Vec<i16> = Vec::with_capacity(SOUND_GROUPS * units * SAMPLES_PER_UNIT);
+        let mut right: Vec<i16> = Vec::with_capacity(left.capacity());
+
+        for group in 0..SOUND_GROUPS {
+            let start = AUDIO_OFFSET + group * SOUND_GROUP_SIZE;
+            let group = &sector[start..start + SOUND_GROUP_SIZE];
+
+            for unit in 0..units {
+                let (channel, output) =
+                    if header.stereo() && unit & 1 == 1 { (1, &mut right) } else { (0, &mut left) };
+                self.decode_unit(group, unit, eight_bit, channel, output);
+            }
+        }
+
+        if header.stereo() {
+            left.into_iter().zip(right).collect()
+        } else {
+            left.into_iter().map(|sample| (sample, sample)).collect()
+        }
+    }
+
+    fn decode_unit(
+        &mut self,
+        group: &[u8],
+        unit: usize,
+        eight_bit: bool,
+        channel: usize,
+        output: &mut Vec<i16>,
+    ) {
+        let header = group[4 + unit];
+        // shift values 13..15 are reserved and behave like 9
+        let shift = match header & 0xf {
+            shift @ 0..=12 => shift,
+            _ => 9,
+        };
+        let filter = ((header >> 4) & 0x3) as usize;
+        let history = &mut self.history[channel];
+
+        for n in 0..SAMPLES_PER_UNIT {
+            let raw = if eight_bit {
+                (group[16 + n * 4 + unit] as i16) << 8
+            } else {
+                let byte = group[16 + n * 4 + unit / 2];
+                (((byte >> ((unit & 1) * 4)) & 0xf) as i16) << 12
+            };
+
+            let sample = (raw >> shift) as i32
+                + (history.old * POS_TABLE[filter] + history.older * NEG_TABLE[filter] + 32) / 64;
+            let sample = sample.clamp(i16::MIN as i32, i16::MAX as i32);
+
+            history.older = history.old;
+            history.old = sample;
+            output.push(sample as i16);
+        }
+    }
+}
+
+impl Default for XaDecoder {
+    fn default() -> XaDecoder {
+        XaDecoder::new()
+    }
+}
+
+// Converts the 37.8/18.9 kHz XA stream to the SPU rate.
+// The hardware uses a 7-tap zigzag filter, linear interpolation is close enough to listen to.
+#[derive(Clone)]
+pub struct Resampler {
+    previous: (i16, i16),
+    // position between previous and the next input sample, in 1/OUTPUT_RATE units
+    phase: u32,
+}
+
+impl Resampler {
+    pub fn new() -> Resampler {
+        Resampler { previous: (0, 0), phase: 0 }
+    }
+
+    pub fn reset(&mut self) {
+        self.previous = (0, 0);
+        self.phase = 0;
+    }
+
+    pub fn resample(
+        &mut self,
+        input: &[(i16, i16)],
+        input_rate: u32,
+        output: &mut Vec<(i16, i16)>,
+    ) {
+        for &sample in input {
+            while self.phase < OUTPUT_RATE {
+                output.push((
+                    lerp(self.previous.0, sample.0, self.phase),
+                    lerp(self.previous.1, sample.1, self.phase),
+                ));
+                self.phase += input_rate;
+            }
+            self.phase -= OUTPUT_RATE;
+            self.previous = sample;
+        }
+    }
+}
+
+impl Default for Resampler {
+    fn default() -> Resampler {
+        Resampler::new()
+    }
+}
+
+fn lerp(from: i16, to: i16, phase: u32) -> i16 {
+    let from = from as i64;
+    let to = to as i64;
+    (from + (to - from) * phase as i64 / OUTPUT_RATE as i64) as i16
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cdrom::disc::SECTOR_SIZE;
+
+    const MONO_4BIT: u8 = 0x00;
+    const STEREO_4BIT: u8 = 0x01;
+    const MONO_8BIT: u8 = 0x10;
+
+    fn sector(coding: u8) -> Vec<u8> {
+        let mut sector = vec![0; SECTOR_SIZE];
+        sector[SUBHEADER_OFFSET..SUBHEADER_OFFSET + 4].copy_from_slice(&[
+            1,
+            2,
+            submode::AUDIO | submode::FORM2,
+            coding,
+        ]);
+        sector
+    }
+
+    // the nibbles of 4bit unit 0 in the first sound group
+    fn set_nibbles(sector: &mut [u8], unit_header: u8, nibbles: &[u8]) {
+        let group = &mut sector[AUDIO_OFFSET..AUDIO_OFFSET + SOUND_GROUP_SIZE];
+        group[4] = unit_header;
+        for (n, &nibble) in nibbles.iter().enumerate() {
+            group[16 + n * 4] = nibble;
+        }
+    }
+
+    #[test]
+    fn subheader_fields() {
+        let header = Subheader::from_sector(&sector(0x15));
+        assert_eq!((header.file, header.channel), (1, 2));
+        assert!(header.is_audio() && header.is_form2() && !header.is_video());
+        assert!(header.stereo());
+        assert_eq!(header.sample_rate(), 18900);
+        assert_eq!(header.bits_per_sample(), 8);
+        let header = Subheader::from_sector(&sector(MONO_4BIT));
+        assert!(!header.stereo());
+        assert_eq!(header.sample_rate(), 37800);
+        assert_eq!(header.bits_per_sample(), 4);
+    }
+
+    #[test]
+    fn nibbles_without_a_filter() {
+        let mut sector = sector(MONO_4BIT);
+        // shift 8: 0x1000 >> 8, 0x8000 >> 8, 0xf000 >> 8, 0x7000 >> 8
+        set_nibbles(&mut sector, 0x08, &[0x1, 0x8, 0xf, 0x7]);
+        let samples = XaDecoder::new().decode_sector(&sector);
+        let left: Vec<i16> = samples[..5].iter().map(|&(left, _)| left).collect();
+        assert_eq!(left, [16, -128, -16, 112, 0]);
+        assert!(samples.iter().all(|&(left, right)| left == right));
+    }
+
+    #[test]
+    fn nibbles_through_the_filters() {
+        let mut sector = sector(MONO_4BIT);
+        // filter 1 adds old * 60 / 64: 16, 992 / 64, 932 / 64
+        set_nibbles(&mut sector, 0x18, &[0x1, 0x0, 0x0]);
+        let samples = XaDecoder::new().decode_sector(&sector);
+        assert_eq!(samples[..3], [(16, 16), (15, 15), (14, 14)]);
+
+        // filter 2 adds (old * 115 - older * 52) / 64: 16, 1872 / 64, (29 * 115 - 16 * 52 + 32) / 64
+        set_nibbles(&mut sector, 0x28, &[0x1, 0x0, 0x0]);
+        let samples = XaDecoder::new().decode_sector(&sector);
+        assert_eq!(samples[..3], [(16, 16), (29, 29), (39, 39)]);
+
+        // shift 0 with a large history clamps
+        set_nibbles(&mut sector, 0x10, &[0x7, 0x7, 0x7]);
+        let samples = XaDecoder::new().decode_sector(&sector);
+        assert_eq!(samples[..3], [(28672, 28672), (32767, 32767), (32767, 32767)]);
+    }
+
+    #[test]
+    fn history_carries_over_between_sectors() {
+        let mut first = sector(MONO_4BIT);
+        // the last unit of the last group leaves 16 in the history
+        let last_group = AUDIO_OFFSET + (SOUND_GROUPS - 1) * SOUND_GROUP_SIZE;
+        first[last_group + 4 + 7] = 0x08;
+        first[last_group + 16 + 27 * 4 + 3] = 0x10;
+        let mut second = sector(MONO_4BIT);
+        set_nibbles(&mut second, 0x18, &[0x0]);
+
+        let mut decoder = XaDecoder::new();
+        assert_eq!(decoder.decode_sector(&first).last(), Some(&(16, 16)));
+        assert_eq!(decoder.decode_sector(&second)[0], (15, 15));
+        decoder.reset();
+        assert_eq!(decoder.decode_sector(&second)[0], (0, 0));
+    }
+
+    #[test]
+    fn samples_per_sector() {
+        let mut decoder = XaDecoder::new();
+        assert_eq!(decoder.decode_sector(&sector(MONO_4BIT)).len(), 18 * 8 * 28);
+        assert_eq!(decoder.decode_sector(&sector(STEREO_4BIT)).len(), 18 * 4 * 28);
+        assert_eq!(decoder.decode_sector(&sector(MONO_8BIT)).len(), 18 * 4 * 28);
+    }
+
+    #[test]
+    fn stereo_units_alternate() {
+        let mut sector = sector(STEREO_4BIT);
+        let group = &mut sector[AUDIO_OFFSET..AUDIO_OFFSET + SOUND_GROUP_SIZE];
+        // unit 0 left in the low nibbles, unit 1 right in the high ones
+        group[4] = 0x08;
+        group[5] = 0x08;
+        group[16] = 0x71;
+        let samples = XaDecoder::new().decode_sector(&sector);
+        assert_eq!(samples[0], (16, 112));
+        assert_eq!(samples[1], (0, 0));
+    }
+
+    #[test]
+    fn resampler_rates() {
+        let input = vec![(100, -100); 3780];
+        let mut output = Vec::new();
+        Resampler::new().resample(&input, 37800, &mut output);
+        assert_eq!(output.len(), 4410);
+        // ramps up from silence, then holds
+        assert_eq!(output[0], (0, 0));
+        assert!(output[1].0 > 0 && output[1].0 < 100);
+        assert!(output[10..].iter().all(|&sample| sample == (100, -100)));
+
+        // the phase carries over, two halves give the same as the whole
+        let mut resampler = Resampler::new();
+        let mut halves = Vec::new();
+        resampler.resample(&input[..1000], 37800, &mut halves);
+        resampler.resample(&input[1000..], 37800, &mut halves);
+        assert_eq!(halves, output);
+    }
+}
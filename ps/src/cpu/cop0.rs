@@ -18,6 +18,8 @@ pub struct COP0 {
 }
 // TODO figure out default regs initialization.
 
+crate::state::snapshot!(COP0 { status, cause, epc });
+
 impl COP0 {
     pub fn new() -> COP0 {
         COP0 { status: 0, cause: 0, epc: 0 }
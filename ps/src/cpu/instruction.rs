@@ -382,6 +382,21 @@ pub enum GTEOperation {
     ILLEGAL,
 }
 
+impl fmt::Display for Operation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use Operation::*;
+        match self {
+            RType(r_op) => r_op.fmt(f),
+            IType(i_op) => i_op.fmt(f),
+            JType(j_op) => j_op.fmt(f),
+            COP0(cop0_op) => cop0_op.fmt(f),
+            GTE(gte_op) => gte_op.fmt(f),
+            NOOP => write!(f, "noop"),
+            ILLEGAL => write!(f, "illegal"),
+        }
+    }
+}
+
 impl fmt::Display for GTEOperation {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         use GTEOperation::*;
@@ -1,10 +1,10 @@
 use super::cop0::{self, Exception};
 use super::instruction::*;
 use super::utils::register_name;
+use crate::exe::SHELL_ENTRY;
 use crate::map::{RAM_END, RAM_START};
 use crate::playstation::PlayStation;
 
-
 use crate::cpu::instruction::Operation::RType;
 use std::collections::btree_map::Values;
 use std::fmt;
@@ -39,6 +39,20 @@ pub struct Cpu {
     gte: [u32; 64],
 }
 
+crate::state::snapshot!(Cpu {
+    registers,
+    current_pc,
+    pc,
+    next_pc,
+    hi,
+    lo,
+    delay_slot,
+    branch_taken,
+    load_delay_slot,
+    cop0,
+    gte,
+});
+
 impl fmt::Display for Cpu {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let mut regs_str = String::new();
@@ -219,10 +233,20 @@ pub fn run_cycle(ps: &mut PlayStation) -> usize {
 
 //TODO Change to @param Instruction
 pub fn run_instruction(ps: &mut PlayStation) {
+    if ps.cpu.pc == SHELL_ENTRY
+        && let Some(exe) = ps.sideload.take()
+    {
+        exe.load(ps);
+    }
+
+    crate::kernel::intercept(ps);
+
     ps.cpu.current_pc = ps.cpu.pc;
 
     if ps.cpu.current_pc % 4 != 0 {
         exception(ps, Exception::AddressErrorLoad);
+        // still an instruction slot, the cycle count has to move for frames to end
+        ps.tick(1);
         return;
     }
 
@@ -239,8 +263,11 @@ pub fn run_instruction(ps: &mut PlayStation) {
 
     // Execute any pending loads
     execute_load_delay(ps);
+
+    // assume every instruction takes one cycle, same as run_cycle
+    ps.tick(1);
 }
-pub fn fetch(ps: &PlayStation, pc: u32) -> Instruction {
+pub fn fetch(ps: &mut PlayStation, pc: u32) -> Instruction {
     //use crate::playstation::mask_region;
     //TODO cache handling and Exceptions at unalinged addresess
     //println!("Fetching from address: 0x{:08X}, masked to 0x{:08X}", pc, mask_region(pc));
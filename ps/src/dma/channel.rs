@@ -1,24 +1,86 @@
 use modular_bitfield::bitfield;
 use modular_bitfield::prelude::{B1, B2, B3, B5, B6};
 
+use crate::state::{FromState, Snapshot, StateReader, StateWriter};
+
 pub struct Channel {
     //device: DMAPort,
-
     pub base_address: u32,
     pub block_control: u32,
 
     pub control_register: ChannelControlRegister,
 }
 
+impl Snapshot for Channel {
+    fn save(&self, state: &mut StateWriter) {
+        self.base_address.save(state);
+        self.block_control.save(state);
+        self.control().save(state);
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), String> {
+        self.base_address.load(state)?;
+        self.block_control.load(state)?;
+        self.control_register.register = u32::read(state)?;
+        Ok(())
+    }
+}
+
 impl Channel {
     pub fn new() -> Channel {
-        Channel{
+        Channel {
             base_address: 0,
             block_control: 0,
-            control_register: ChannelControlRegister{register: 0}
+            control_register: ChannelControlRegister { register: 0 },
+        }
+    }
+
+    pub fn control(&self) -> u32 {
+        unsafe { self.control_register.register }
+    }
+
+    // manual (sync mode 0) transfers also wait for the trigger bit
+    pub fn is_active(&self) -> bool {
+        let control = self.control();
+        let started = control & (1 << 24) != 0;
+        let triggered = control & (1 << 28) != 0;
+        started && (self.sync_mode() != 0 || triggered)
+    }
+
+    pub fn from_ram(&self) -> bool {
+        self.control() & 1 != 0
+    }
+
+    pub fn step(&self) -> u32 {
+        if self.control() & 2 != 0 { (-4i32) as u32 } else { 4 }
+    }
+
+    pub fn sync_mode(&self) -> u32 {
+        (self.control() >> 9) & 3
+    }
+
+    // number of words for manual and block transfers, linked lists end on their own
+    pub fn transfer_size(&self) -> u32 {
+        let block_size = self.block_control & 0xffff;
+        let block_count = self.block_control >> 16;
+        match self.sync_mode() {
+            0 => {
+                if block_size == 0 {
+                    0x10000
+                } else {
+                    block_size
+                }
+            }
+            _ => block_size * block_count,
         }
     }
+
+    // clears the start and trigger bits
+    pub fn finish(&mut self) {
+        self.control_register.register = self.control() & !((1 << 24) | (1 << 28));
+    }
 }
+#[derive(Copy, Clone, PartialEq, Debug)]
 pub enum DMAPort {
     MDECIN = 0,
     MDECOUT = 1,
@@ -27,7 +89,22 @@ pub enum DMAPort {
     SPU = 4,
     PIO = 5,
     OTC = 6,
-    Registers = 7
+    Registers = 7,
+}
+
+impl DMAPort {
+    pub fn from_channel(channel: usize) -> DMAPort {
+        match channel {
+            0 => DMAPort::MDECIN,
+            1 => DMAPort::MDECOUT,
+            2 => DMAPort::GPU,
+            3 => DMAPort::CDROM,
+            4 => DMAPort::SPU,
+            5 => DMAPort::PIO,
+            6 => DMAPort::OTC,
+            _ => DMAPort::Registers,
+        }
+    }
 }
 
 pub union ChannelControlRegister {
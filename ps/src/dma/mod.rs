@@ -1,6 +1,9 @@
 use crate::dma::channel::DMAPort::{CDROM, GPU, MDECIN, MDECOUT, OTC, PIO, Registers, SPU};
 use crate::dma::channel::{Channel, DMAPort};
+use crate::irq::Interrupt;
 use crate::map::DMA_REGISTERS_START;
+use crate::playstation::PlayStation;
+use crate::state::{FromState, Snapshot, StateReader, StateWriter};
 use modular_bitfield::bitfield;
 use modular_bitfield::prelude::*;
 
@@ -59,22 +62,31 @@ impl DMA {
             0..=6 => match reg {
                 0x0 => {
                     self.channels[channel].base_address = val;
-                    println!("DMA write base address {:08x} value: {:08x}, channel: {}", addr, val, channel);
-                },
+                    println!(
+                        "DMA write base address {:08x} value: {:08x}, channel: {}",
+                        addr, val, channel
+                    );
+                }
                 0x4 => {
                     self.channels[channel].block_control = val;
-                    println!("DMA write block control {:08x} value: {:08x}, channel: {}", addr, val, channel);
-                },
+                    println!(
+                        "DMA write block control {:08x} value: {:08x}, channel: {}",
+                        addr, val, channel
+                    );
+                }
                 0x8 => {
                     self.channels[channel].control_register.register = val;
-                    println!("DMA write control register {:08x} value: {:08x}, channel: {}",  addr,val, channel);
-                },
+                    println!(
+                        "DMA write control register {:08x} value: {:08x}, channel: {}",
+                        addr, val, channel
+                    );
+                }
                 _ => panic!("unhandled DMA write {:08x} value: {:08x}", addr, val),
             },
 
             7 => match reg {
                 0x0 => self.control.register = val,
-                0x4 => self.interrupt.register = val,
+                0x4 => self.write_interrupt(val),
                 _ => panic!("unhandled DMA write {:08x} value: {:08x}", addr, val),
             },
             _ => panic!("unhandled DMA write {:08x} value: {:08x}", addr, val),
@@ -89,6 +101,147 @@ impl DMA {
     }
 }
 
+impl Default for DMA {
+    fn default() -> DMA {
+        DMA::new()
+    }
+}
+
+impl Snapshot for DMA {
+    fn save(&self, state: &mut StateWriter) {
+        unsafe { self.control.register }.save(state);
+        unsafe { self.interrupt.register }.save(state);
+        self.channels.save(state);
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), String> {
+        self.control.register = u32::read(state)?;
+        self.interrupt.register = u32::read(state)?;
+        self.channels.load(state)
+    }
+}
+
+impl DMA {
+    // DICR: the channel flags (bits 24-30) are acknowledged by writing 1s
+    fn write_interrupt(&mut self, val: u32) {
+        let current = unsafe { self.interrupt.register };
+        let flags = current & 0x7f000000 & !(val & 0x7f000000);
+        self.interrupt.register = (val & 0x00ffffff) | flags;
+        self.update_master_flag();
+    }
+
+    // bit 31 is set by the force bit or by an enabled channel flag when the master enable is on,
+    // returns true on a rising edge which is when IRQ3 fires
+    fn update_master_flag(&mut self) -> bool {
+        let register = unsafe { self.interrupt.register };
+        let force = register & (1 << 15) != 0;
+        let master_enable = register & (1 << 23) != 0;
+        let enabled_flags = (register >> 16) & (register >> 24) & 0x7f;
+        let master_flag = force || (master_enable && enabled_flags != 0);
+
+        let rising = master_flag && register & (1 << 31) == 0;
+        self.interrupt.register =
+            if master_flag { register | (1 << 31) } else { register & !(1 << 31) };
+        rising
+    }
+
+    fn channel_enabled(&self, channel: usize) -> bool {
+        unsafe { self.control.register & (1 << (channel * 4 + 3)) != 0 }
+    }
+
+    // sets the channel's completion flag if its interrupt is enabled, true if IRQ3 should fire
+    fn channel_done(&mut self, channel: usize) -> bool {
+        let register = unsafe { self.interrupt.register };
+        if register & (1 << (16 + channel)) != 0 {
+            self.interrupt.register = register | (1 << (24 + channel));
+        }
+        self.update_master_flag()
+    }
+}
+
+// runs every enabled channel that was just started, transfers complete instantly
+pub fn run_transfers(ps: &mut PlayStation) {
+    for channel in 0..7 {
+        if ps.dma.channel_enabled(channel)
+            && ps.dma.channels[channel].is_active()
+            && ready(ps, channel)
+        {
+            transfer(ps, channel);
+        }
+    }
+}
+
+// MDECOUT is usually started before the decoder has anything, it waits for the decoded data
+fn ready(ps: &PlayStation, channel: usize) -> bool {
+    match DMAPort::from_channel(channel) {
+        MDECOUT => ps.mdec.has_output(),
+        _ => true,
+    }
+}
+
+fn transfer(ps: &mut PlayStation, channel: usize) {
+    let port = DMAPort::from_channel(channel);
+    if ps.dma.channels[channel].sync_mode() == 2 {
+        linked_list_transfer(ps, channel);
+    } else {
+        block_transfer(ps, channel, port);
+    }
+
+    ps.dma.channels[channel].finish();
+    if ps.dma.channel_done(channel) {
+        ps.irq.request(Interrupt::DMA);
+    }
+}
+
+fn block_transfer(ps: &mut PlayStation, channel: usize, port: DMAPort) {
+    let ch = &ps.dma.channels[channel];
+    let from_ram = ch.from_ram();
+    let step = ch.step();
+    let size = ch.transfer_size();
+    let sync_mode = ch.sync_mode();
+    let mut address = ch.base_address & 0x1ffffc;
+
+    for remaining in (0..size).rev() {
+        let word_address = address & 0x1ffffc;
+        if from_ram {
+            let word = ps.ram.read32(word_address);
+            match port {
+                SPU => ps.spu.dma_write(word),
+                MDECIN => ps.mdec.write_command(word),
+                _ => (),
+            }
+        } else {
+            let word = match port {
+                SPU => Some(ps.spu.dma_read()),
+                MDECOUT => Some(ps.mdec.read_data()),
+                // the ordering table is a linked list running backwards, the last entry is the end marker
+                OTC => {
+                    Some(if remaining == 0 { 0xffffff } else { address.wrapping_sub(4) & 0x1fffff })
+                }
+                // no device behind the port yet, RAM keeps what it had
+                _ => None,
+            };
+            if let Some(word) = word {
+                ps.ram.write32(word_address, word);
+            }
+        }
+        address = address.wrapping_add(step);
+    }
+
+    // slice mode leaves the registers pointing after the last block
+    if sync_mode == 1 {
+        let ch = &mut ps.dma.channels[channel];
+        ch.base_address = address & 0xffffff;
+        ch.block_control &= 0xffff;
+    }
+}
+
+// GPU command lists: every node has a header with the word count and the next node's address.
+// There is no GPU to take the commands yet, the list is dropped and the channel left at the end marker
+fn linked_list_transfer(ps: &mut PlayStation, channel: usize) {
+    ps.dma.channels[channel].base_address = 0xffffff;
+}
+
 fn dma_map(address: u32) -> (usize, u32) {
     let reg = address & 0xf; // last nibble
     let channel = (address >> 4) & 0x7;
@@ -0,0 +1,114 @@
+use super::symbols::{Symbol, SymbolKind, SymbolTable};
+use super::{Executable, Segment};
+
+pub const MAGIC: &[u8] = b"\x7fELF";
+
+const CLASS_32: u8 = 1;
+const DATA_LITTLE_ENDIAN: u8 = 1;
+const MACHINE_MIPS: u16 = 8;
+
+const PT_LOAD: u32 = 1;
+const SHT_SYMTAB: u32 = 2;
+const STT_OBJECT: u8 = 1;
+const STT_FUNC: u8 = 2;
+const SHN_UNDEF: u16 = 0;
+
+pub fn parse(data: &[u8]) -> Result<Executable, String> {
+    let elf = Elf { data };
+    if data.len() < 0x34 || !data.starts_with(MAGIC) {
+        return Err(String::from("not an ELF file"));
+    }
+    if data[4] != CLASS_32 || data[5] != DATA_LITTLE_ENDIAN || elf.half(0x12)? != MACHINE_MIPS {
+        return Err(String::from("not a 32-bit little-endian MIPS ELF"));
+    }
+
+    let entry = elf.word(0x18)?;
+    let ph_offset = elf.word(0x1c)? as usize;
+    let sh_offset = elf.word(0x20)? as usize;
+    let ph_size = elf.half(0x2a)? as usize;
+    let ph_count = elf.half(0x2c)? as usize;
+    let sh_size = elf.half(0x2e)? as usize;
+    let sh_count = elf.half(0x30)? as usize;
+
+    let mut segments = Vec::new();
+    for i in 0..ph_count {
+        let header = ph_offset + i * ph_size;
+        if elf.word(header)? != PT_LOAD {
+            continue;
+        }
+        let offset = elf.word(header + 4)? as usize;
+        let address = elf.word(header + 8)?;
+        let file_size = elf.word(header + 16)? as usize;
+        let memory_size = elf.word(header + 20)? as usize;
+
+        // the part of the segment that isn't in the file is zero filled (.bss)
+        let mut segment = elf.bytes(offset, file_size)?.to_vec();
+        segment.resize(memory_size.max(file_size), 0);
+        segments.push(Segment { address, data: segment });
+    }
+    if segments.is_empty() {
+        return Err(String::from("ELF has no loadable segments"));
+    }
+
+    let mut symbols = SymbolTable::new();
+    let mut gp = 0;
+    for i in 0..sh_count {
+        let section = sh_offset + i * sh_size;
+        if elf.word(section + 4)? != SHT_SYMTAB {
+            continue;
+        }
+        let strings_section = sh_offset + elf.word(section + 24)? as usize * sh_size;
+        let strings = elf.bytes(
+            elf.word(strings_section + 16)? as usize,
+            elf.word(strings_section + 20)? as usize,
+        )?;
+        let table =
+            elf.bytes(elf.word(section + 16)? as usize, elf.word(section + 20)? as usize)?;
+
+        for entry in table.chunks_exact(16) {
+            let entry = Elf { data: entry };
+            if entry.half(14)? == SHN_UNDEF {
+                continue;
+            }
+            let name_offset = entry.word(0)? as usize;
+            let name = strings.get(name_offset..).unwrap_or_default();
+            let name = String::from_utf8_lossy(name.split(|&b| b == 0).next().unwrap_or_default());
+            let address = entry.word(4)?;
+
+            // the linker script defines _gp for small data accesses, it has no type
+            if name == "_gp" {
+                gp = address;
+            }
+            let kind = match entry.data[12] & 0xf {
+                STT_FUNC => SymbolKind::Function,
+                STT_OBJECT => SymbolKind::Object,
+                _ => continue,
+            };
+            symbols.insert(Symbol { name: name.to_string(), address, size: entry.word(8)?, kind });
+        }
+    }
+
+    Ok(Executable { entry, gp, stack: None, segments, bss: None, symbols })
+}
+
+struct Elf<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Elf<'a> {
+    fn bytes(&self, offset: usize, size: usize) -> Result<&'a [u8], String> {
+        self.data
+            .get(offset..offset.checked_add(size).ok_or("ELF offset overflow")?)
+            .ok_or_else(|| format!("ELF truncated at 0x{:x}", offset))
+    }
+
+    fn half(&self, offset: usize) -> Result<u16, String> {
+        let bytes = self.bytes(offset, 2)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn word(&self, offset: usize) -> Result<u32, String> {
+        let bytes = self.bytes(offset, 4)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+}
@@ -0,0 +1,62 @@
+use crate::playstation::{PlayStation, mask_region};
+use symbols::SymbolTable;
+
+pub mod elf;
+pub mod psx;
+pub mod symbols;
+
+// the BIOS jumps here to start the shell once the kernel is set up,
+// that's the earliest point where an executable can replace it
+pub const SHELL_ENTRY: u32 = 0x80030000;
+
+pub struct Segment {
+    pub address: u32,
+    pub data: Vec<u8>,
+}
+
+pub struct Executable {
+    pub entry: u32,
+    pub gp: u32,
+    // initial $sp/$fp, None keeps the one the BIOS set up
+    pub stack: Option<u32>,
+    pub segments: Vec<Segment>,
+    // zero filled (address, size)
+    pub bss: Option<(u32, u32)>,
+    // only ELF files carry symbols
+    pub symbols: SymbolTable,
+}
+
+impl Executable {
+    pub fn parse(data: &[u8]) -> Result<Executable, String> {
+        if data.starts_with(psx::MAGIC) {
+            psx::parse(data)
+        } else if data.starts_with(elf::MAGIC) {
+            elf::parse(data)
+        } else {
+            Err(String::from("unknown executable format"))
+        }
+    }
+
+    // copies the executable into RAM and jumps to its entry point
+    pub fn load(&self, ps: &mut PlayStation) {
+        for segment in &self.segments {
+            for (i, &byte) in segment.data.iter().enumerate() {
+                ps.ram.write8(mask_region(segment.address.wrapping_add(i as u32)), byte);
+            }
+        }
+        if let Some((address, size)) = self.bss {
+            for i in 0..size {
+                ps.ram.write8(mask_region(address.wrapping_add(i)), 0);
+            }
+        }
+
+        ps.cpu.set_reg(self.gp, 28);
+        if let Some(stack) = self.stack {
+            ps.cpu.set_reg(stack, 29);
+            ps.cpu.set_reg(stack, 30);
+        }
+        ps.cpu.load_delay_slot = None;
+        ps.cpu.pc = self.entry;
+        ps.cpu.next_pc = self.entry.wrapping_add(4);
+    }
+}
@@ -0,0 +1,37 @@
+use super::symbols::SymbolTable;
+use super::{Executable, Segment};
+
+pub const MAGIC: &[u8] = b"PS-X EXE";
+// the payload starts after a whole 2048 byte header sector
+const HEADER_SIZE: usize = 0x800;
+
+pub fn parse(data: &[u8]) -> Result<Executable, String> {
+    if data.len() < HEADER_SIZE || !data.starts_with(MAGIC) {
+        return Err(String::from("not a PS-X EXE"));
+    }
+
+    let word = |offset: usize| {
+        u32::from_le_bytes([data[offset], data[offset + 1], data[offset + 2], data[offset + 3]])
+    };
+    let pc0 = word(0x10);
+    let gp0 = word(0x14);
+    let t_addr = word(0x18);
+    let t_size = word(0x1c) as usize;
+    let b_addr = word(0x28);
+    let b_size = word(0x2c);
+    let s_addr = word(0x30);
+    let s_size = word(0x34);
+
+    // some tools don't pad the last sector, take whatever is there
+    let end = (HEADER_SIZE + t_size).min(data.len());
+    let text = data[HEADER_SIZE..end].to_vec();
+
+    Ok(Executable {
+        entry: pc0,
+        gp: gp0,
+        stack: if s_addr != 0 { Some(s_addr.wrapping_add(s_size)) } else { None },
+        segments: vec![Segment { address: t_addr, data: text }],
+        bss: if b_size != 0 { Some((b_addr, b_size)) } else { None },
+        symbols: SymbolTable::new(),
+    })
+}
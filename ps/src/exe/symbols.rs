@@ -0,0 +1,66 @@
+use std::collections::BTreeMap;
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum SymbolKind {
+    Function,
+    Object,
+}
+
+#[derive(Clone, Debug)]
+pub struct Symbol {
+    pub name: String,
+    pub address: u32,
+    pub size: u32,
+    pub kind: SymbolKind,
+}
+
+#[derive(Clone, Default)]
+pub struct SymbolTable {
+    symbols: BTreeMap<u32, Symbol>,
+}
+
+impl SymbolTable {
+    pub fn new() -> SymbolTable {
+        SymbolTable { symbols: BTreeMap::new() }
+    }
+
+    pub fn insert(&mut self, symbol: Symbol) {
+        self.symbols.insert(symbol.address, symbol);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.symbols.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.symbols.len()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Symbol> {
+        self.symbols.values()
+    }
+
+    // symbol starting exactly at address
+    pub fn get(&self, address: u32) -> Option<&Symbol> {
+        self.symbols.get(&address)
+    }
+
+    // symbol covering address, zero sized symbols only match their own address
+    pub fn containing(&self, address: u32) -> Option<&Symbol> {
+        let (_, symbol) = self.symbols.range(..=address).next_back()?;
+        if address == symbol.address || address - symbol.address < symbol.size {
+            Some(symbol)
+        } else {
+            None
+        }
+    }
+
+    // "name" or "name+0x1c"
+    pub fn describe(&self, address: u32) -> Option<String> {
+        let symbol = self.containing(address)?;
+        match address - symbol.address {
+            0 => Some(symbol.name.clone()),
+            offset => Some(format!("{}+0x{:x}", symbol.name, offset)),
+        }
+    }
+}
@@ -1,18 +1,36 @@
+// the DUART of the dev boards, programs print through its channel A
+pub const DUART_STATUS_A: u32 = 0x1f802021;
+pub const DUART_TX_A: u32 = 0x1f802023;
+// the BIOS shows its boot progress there
+pub const POST: u32 = 0x1f802041;
+
+// TxRDY and TxEMT, the transmitter is always ready
+const DUART_READY: u8 = 0x0c;
+
 #[derive(Copy, Clone)]
 pub struct Expansion_Region_2 {
     post: u8,
 }
 
+crate::state::snapshot!(Expansion_Region_2 { post });
+
 impl Expansion_Region_2 {
     pub fn new() -> Expansion_Region_2 {
         Expansion_Region_2 { post: 0 }
     }
     pub fn write_byte(&mut self, addr: u32, byte: u8) {
-        if addr == 0x1f802041 {
+        if addr == POST {
             self.post = byte;
         }
     }
     pub fn read_byte(&self, addr: u32) -> u8 {
-        return 0;
+        match addr {
+            DUART_STATUS_A => DUART_READY,
+            POST => self.post,
+            _ => 0,
+        }
+    }
+    pub fn post(&self) -> u8 {
+        self.post
     }
 }
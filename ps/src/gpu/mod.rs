@@ -4,6 +4,8 @@ pub struct GPU {
     GPO: u32,
     GP1: u32,
 }
+crate::state::snapshot!(GPU { GPO, GP1 });
+
 impl GPU {
     pub fn new() -> Self {
         GPU { GPO: 0, GP1: 0 }
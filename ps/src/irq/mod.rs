@@ -1,6 +1,7 @@
 use crate::map;
 
-enum Interrupt {
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Interrupt {
     Vblank = 0,
     GPU = 1,
     CDROM = 2,
@@ -19,6 +20,8 @@ pub struct IRQController {
     pub mask: u32,   // Mask of enabled IRQs
 }
 
+crate::state::snapshot!(IRQController { status, mask });
+
 impl IRQController {
     pub fn new() -> IRQController {
         IRQController { status: 0, mask: 0 }
@@ -39,6 +42,11 @@ impl IRQController {
     pub fn set_mask(&mut self, value: u32) {
         self.mask = value;
     }
+    // a device raising its interrupt line sets the matching I_STAT bit
+    pub fn request(&mut self, interrupt: Interrupt) {
+        self.status |= 1 << interrupt as u32;
+    }
+
     pub fn interrupt_pending(&self) -> bool {
         return (self.status & self.mask) != 0;
     }
@@ -0,0 +1,357 @@
+use super::{KernelCall, Table};
+
+// how an argument is shown in the call log
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Arg {
+    Int,
+    Hex,
+    Char,
+    // zero terminated, file names are strings too
+    Str,
+    // event class, spec and mode of the event functions
+    Class,
+    Spec,
+    Mode,
+}
+
+use Arg::*;
+
+pub struct Function {
+    pub name: &'static str,
+    pub args: &'static [Arg],
+}
+
+const fn f(name: &'static str, args: &'static [Arg]) -> Function {
+    Function { name, args }
+}
+
+// the functions of the retail BIOS, by number. the gaps are SystemError stubs and
+// functions that only return 0
+const A: &[(u8, Function)] = &[
+    (0x00, f("FileOpen", &[Str, Hex])),
+    (0x01, f("FileSeek", &[Int, Int, Int])),
+    (0x02, f("FileRead", &[Int, Hex, Int])),
+    (0x03, f("FileWrite", &[Int, Hex, Int])),
+    (0x04, f("FileClose", &[Int])),
+    (0x05, f("FileIoctl", &[Int, Hex, Hex])),
+    (0x06, f("exit", &[Int])),
+    (0x07, f("FileGetDeviceFlag", &[Int])),
+    (0x08, f("FileGetc", &[Int])),
+    (0x09, f("FilePutc", &[Char, Int])),
+    (0x0a, f("todigit", &[Char])),
+    (0x0b, f("atof", &[Str])),
+    (0x0c, f("strtoul", &[Str, Hex, Int])),
+    (0x0d, f("strtol", &[Str, Hex, Int])),
+    (0x0e, f("abs", &[Int])),
+    (0x0f, f("labs", &[Int])),
+    (0x10, f("atoi", &[Str])),
+    (0x11, f("atol", &[Str])),
+    (0x12, f("atob", &[Str, Hex])),
+    (0x13, f("SaveState", &[Hex])),
+    (0x14, f("RestoreState", &[Hex, Hex])),
+    (0x15, f("strcat", &[Hex, Str])),
+    (0x16, f("strncat", &[Hex, Str, Int])),
+    (0x17, f("strcmp", &[Str, Str])),
+    (0x18, f("strncmp", &[Str, Str, Int])),
+    (0x19, f("strcpy", &[Hex, Str])),
+    (0x1a, f("strncpy", &[Hex, Str, Int])),
+    (0x1b, f("strlen", &[Str])),
+    (0x1c, f("index", &[Str, Char])),
+    (0x1d, f("rindex", &[Str, Char])),
+    (0x1e, f("strchr", &[Str, Char])),
+    (0x1f, f("strrchr", &[Str, Char])),
+    (0x20, f("strpbrk", &[Str, Str])),
+    (0x21, f("strspn", &[Str, Str])),
+    (0x22, f("strcspn", &[Str, Str])),
+    (0x23, f("strtok", &[Str, Str])),
+    (0x24, f("strstr", &[Str, Str])),
+    (0x25, f("toupper", &[Char])),
+    (0x26, f("tolower", &[Char])),
+    (0x27, f("bcopy", &[Hex, Hex, Int])),
+    (0x28, f("bzero", &[Hex, Int])),
+    (0x29, f("bcmp", &[Hex, Hex, Int])),
+    (0x2a, f("memcpy", &[Hex, Hex, Int])),
+    (0x2b, f("memset", &[Hex, Hex, Int])),
+    (0x2c, f("memmove", &[Hex, Hex, Int])),
+    (0x2d, f("memcmp", &[Hex, Hex, Int])),
+    (0x2e, f("memchr", &[Hex, Hex, Int])),
+    (0x2f, f("rand", &[])),
+    (0x30, f("srand", &[Hex])),
+    (0x31, f("qsort", &[Hex, Int, Int, Hex])),
+    (0x32, f("strtod", &[Str, Hex])),
+    (0x33, f("malloc", &[Int])),
+    (0x34, f("free", &[Hex])),
+    (0x35, f("lsearch", &[Hex, Hex, Hex, Int, Hex])),
+    (0x36, f("bsearch", &[Hex, Hex, Int, Int, Hex])),
+    (0x37, f("calloc", &[Int, Int])),
+    (0x38, f("realloc", &[Hex, Int])),
+    (0x39, f("InitHeap", &[Hex, Int])),
+    (0x3a, f("SystemErrorExit", &[Int])),
+    (0x3b, f("std_in_getchar", &[])),
+    (0x3c, f("std_out_putchar", &[Char])),
+    (0x3d, f("std_in_gets", &[Hex])),
+    (0x3e, f("std_out_puts", &[Str])),
+    (0x3f, f("printf", &[Str, Hex, Hex, Hex])),
+    (0x40, f("SystemErrorUnresolvedException", &[])),
+    (0x41, f("LoadExeHeader", &[Str, Hex])),
+    (0x42, f("LoadExeFile", &[Str, Hex])),
+    (0x43, f("DoExecute", &[Hex, Hex, Hex])),
+    (0x44, f("FlushCache", &[])),
+    (0x45, f("init_a0_b0_c0_vectors", &[])),
+    (0x46, f("GPU_dw", &[Int, Int, Int, Int, Hex])),
+    (0x47, f("gpu_send_dma", &[Int, Int, Int, Int, Hex])),
+    (0x48, f("SendGP1Command", &[Hex])),
+    (0x49, f("GPU_cw", &[Hex])),
+    (0x4a, f("GPU_cwp", &[Hex, Int])),
+    (0x4b, f("send_gpu_linked_list", &[Hex])),
+    (0x4c, f("gpu_abort_dma", &[])),
+    (0x4d, f("GetGPUStatus", &[])),
+    (0x4e, f("gpu_sync", &[])),
+    (0x4f, f("SystemError", &[])),
+    (0x50, f("SystemError", &[])),
+    (0x51, f("LoadAndExecute", &[Str, Hex, Hex])),
+    (0x52, f("GetSysSp", &[])),
+    (0x53, f("SystemError", &[])),
+    (0x54, f("CdInit", &[])),
+    (0x55, f("_bu_init", &[])),
+    (0x56, f("CdRemove", &[])),
+    (0x5b, f("dev_tty_init", &[])),
+    (0x5c, f("dev_tty_open", &[Hex, Str, Hex])),
+    (0x5d, f("dev_tty_in_out", &[Hex, Hex])),
+    (0x5e, f("dev_tty_ioctl", &[Hex, Hex, Hex])),
+    (0x5f, f("dev_cd_open", &[Hex, Str, Hex])),
+    (0x60, f("dev_cd_read", &[Hex, Hex, Int])),
+    (0x61, f("dev_cd_close", &[Hex])),
+    (0x62, f("dev_cd_firstfile", &[Hex, Str, Hex])),
+    (0x63, f("dev_cd_nextfile", &[Hex, Hex])),
+    (0x64, f("dev_cd_chdir", &[Hex, Str])),
+    (0x65, f("dev_card_open", &[Hex, Str, Hex])),
+    (0x66, f("dev_card_read", &[Hex, Hex, Int])),
+    (0x67, f("dev_card_write", &[Hex, Hex, Int])),
+    (0x68, f("dev_card_close", &[Hex])),
+    (0x69, f("dev_card_firstfile", &[Hex, Str, Hex])),
+    (0x6a, f("dev_card_nextfile", &[Hex, Hex])),
+    (0x6b, f("dev_card_erase", &[Hex, Str])),
+    (0x6c, f("dev_card_undelete", &[Hex, Str])),
+    (0x6d, f("dev_card_format", &[Hex])),
+    (0x6e, f("dev_card_rename", &[Hex, Str, Hex, Str])),
+    (0x6f, f("card_clear_error", &[])),
+    (0x70, f("_bu_init", &[])),
+    (0x71, f("CdInit", &[])),
+    (0x72, f("CdRemove", &[])),
+    (0x78, f("CdAsyncSeekL", &[Hex])),
+    (0x7c, f("CdAsyncGetStatus", &[Hex])),
+    (0x7e, f("CdAsyncReadSector", &[Int, Hex, Hex])),
+    (0x81, f("CdAsyncSetMode", &[Hex])),
+    (0x90, f("CdromIoIrqFunc1", &[])),
+    (0x91, f("CdromDmaIrqFunc1", &[])),
+    (0x92, f("CdromIoIrqFunc2", &[])),
+    (0x93, f("CdromDmaIrqFunc2", &[])),
+    (0x94, f("CdromGetInt5errCode", &[Hex, Hex])),
+    (0x95, f("CdInitSubFunc", &[])),
+    (0x96, f("AddCDROMDevice", &[])),
+    (0x97, f("AddMemCardDevice", &[])),
+    (0x98, f("AddDuartTtyDevice", &[])),
+    (0x99, f("AddDummyTtyDevice", &[])),
+    (0x9a, f("SystemError", &[])),
+    (0x9b, f("SystemError", &[])),
+    (0x9c, f("SetConf", &[Int, Int, Hex])),
+    (0x9d, f("GetConf", &[Hex, Hex, Hex])),
+    (0x9e, f("SetCdromIrqAutoAbort", &[Int, Int])),
+    (0x9f, f("SetMemSize", &[Int])),
+    (0xa0, f("WarmBoot", &[])),
+    (0xa1, f("SystemErrorBootOrDiskFailure", &[Char, Hex])),
+    (0xa2, f("EnqueueCdIntr", &[])),
+    (0xa3, f("DequeueCdIntr", &[])),
+    (0xa4, f("CdGetLbn", &[Str])),
+    (0xa5, f("CdReadSector", &[Int, Int, Hex])),
+    (0xa6, f("CdGetStatus", &[])),
+    (0xa7, f("bufs_cb_0", &[])),
+    (0xa8, f("bufs_cb_1", &[])),
+    (0xa9, f("bufs_cb_2", &[])),
+    (0xaa, f("bufs_cb_3", &[])),
+    (0xab, f("_card_info", &[Int])),
+    (0xac, f("_card_load", &[Int])),
+    (0xad, f("_card_auto", &[Int])),
+    (0xae, f("bufs_cb_4", &[])),
+    (0xaf, f("card_write_test", &[Int])),
+    (0xb0, f("SystemError", &[])),
+    (0xb1, f("SystemError", &[])),
+    (0xb2, f("ioabort_raw", &[Hex])),
+    (0xb3, f("SystemError", &[])),
+    (0xb4, f("GetSystemInfo", &[Int])),
+];
+
+const B: &[(u8, Function)] = &[
+    (0x00, f("alloc_kernel_memory", &[Int])),
+    (0x01, f("free_kernel_memory", &[Hex])),
+    (0x02, f("init_timer", &[Int, Hex, Hex])),
+    (0x03, f("get_timer", &[Int])),
+    (0x04, f("enable_timer_irq", &[Int])),
+    (0x05, f("disable_timer_irq", &[Int])),
+    (0x06, f("restart_timer", &[Int])),
+    (0x07, f("DeliverEvent", &[Class, Spec])),
+    (0x08, f("OpenEvent", &[Class, Spec, Mode, Hex])),
+    (0x09, f("CloseEvent", &[Hex])),
+    (0x0a, f("WaitEvent", &[Hex])),
+    (0x0b, f("TestEvent", &[Hex])),
+    (0x0c, f("EnableEvent", &[Hex])),
+    (0x0d, f("DisableEvent", &[Hex])),
+    (0x0e, f("OpenThread", &[Hex, Hex, Hex])),
+    (0x0f, f("CloseThread", &[Hex])),
+    (0x10, f("ChangeThread", &[Hex])),
+    (0x11, f("jump_to_00000000", &[])),
+    (0x12, f("InitPad", &[Hex, Int, Hex, Int])),
+    (0x13, f("StartPad", &[])),
+    (0x14, f("StopPad", &[])),
+    (0x15, f("OutdatedPadInitAndStart", &[Hex, Hex, Hex, Hex])),
+    (0x16, f("OutdatedPadGetButtons", &[])),
+    (0x17, f("ReturnFromException", &[])),
+    (0x18, f("SetDefaultExitFromException", &[])),
+    (0x19, f("SetCustomExitFromException", &[Hex])),
+    (0x20, f("UnDeliverEvent", &[Class, Spec])),
+    (0x24, f("jump_to_00000000", &[])),
+    (0x25, f("jump_to_00000000", &[])),
+    (0x26, f("jump_to_00000000", &[])),
+    (0x27, f("jump_to_00000000", &[])),
+    (0x28, f("jump_to_00000000", &[])),
+    (0x29, f("jump_to_00000000", &[])),
+    (0x30, f("jump_to_00000000", &[])),
+    (0x31, f("jump_to_00000000", &[])),
+    (0x32, f("FileOpen", &[Str, Hex])),
+    (0x33, f("FileSeek", &[Int, Int, Int])),
+    (0x34, f("FileRead", &[Int, Hex, Int])),
+    (0x35, f("FileWrite", &[Int, Hex, Int])),
+    (0x36, f("FileClose", &[Int])),
+    (0x37, f("FileIoctl", &[Int, Hex, Hex])),
+    (0x38, f("exit", &[Int])),
+    (0x39, f("FileGetDeviceFlag", &[Int])),
+    (0x3a, f("FileGetc", &[Int])),
+    (0x3b, f("FilePutc", &[Char, Int])),
+    (0x3c, f("std_in_getchar", &[])),
+    (0x3d, f("std_out_putchar", &[Char])),
+    (0x3e, f("std_in_gets", &[Hex])),
+    (0x3f, f("std_out_puts", &[Str])),
+    (0x40, f("chdir", &[Str])),
+    (0x41, f("FormatDevice", &[Str])),
+    (0x42, f("firstfile", &[Str, Hex])),
+    (0x43, f("nextfile", &[Hex])),
+    (0x44, f("FileRename", &[Str, Str])),
+    (0x45, f("FileDelete", &[Str])),
+    (0x46, f("FileUndelete", &[Str])),
+    (0x47, f("AddDevice", &[Hex])),
+    (0x48, f("RemoveDevice", &[Str])),
+    (0x49, f("PrintInstalledDevices", &[])),
+    (0x4a, f("InitCard", &[Int])),
+    (0x4b, f("StartCard", &[])),
+    (0x4c, f("StopCard", &[])),
+    (0x4d, f("_card_info_subfunc", &[Int])),
+    (0x4e, f("write_card_sector", &[Int, Int, Hex])),
+    (0x4f, f("read_card_sector", &[Int, Int, Hex])),
+    (0x50, f("allow_new_card", &[])),
+    (0x51, f("Krom2RawAdd", &[Hex])),
+    (0x53, f("Krom2Offset", &[Hex])),
+    (0x54, f("GetLastError", &[])),
+    (0x55, f("GetLastFileError", &[Int])),
+    (0x56, f("GetC0Table", &[])),
+    (0x57, f("GetB0Table", &[])),
+    (0x58, f("get_bu_callback_port", &[])),
+    (0x59, f("testdevice", &[Str])),
+    (0x5b, f("ChangeClearPad", &[Int])),
+    (0x5c, f("get_card_status", &[Int])),
+];
+
+const C: &[(u8, Function)] = &[
+    (0x00, f("EnqueueTimerAndVblankIrqs", &[Int])),
+    (0x01, f("EnqueueSyscallHandler", &[Int])),
+    (0x02, f("SysEnqIntRP", &[Int, Hex])),
+    (0x03, f("SysDeqIntRP", &[Int, Hex])),
+    (0x04, f("get_free_EvCB_slot", &[])),
+    (0x05, f("get_free_TCB_slot", &[])),
+    (0x06, f("ExceptionHandler", &[])),
+    (0x07, f("InstallExceptionHandlers", &[])),
+    (0x08, f("SysInitMemory", &[Hex, Int])),
+    (0x09, f("SysInitKernelVariables", &[])),
+    (0x0a, f("ChangeClearRCnt", &[Int, Int])),
+    (0x0b, f("SystemError", &[])),
+    (0x0c, f("InitDefInt", &[Int])),
+    (0x0d, f("SetIrqAutoAck", &[Int, Int])),
+    (0x0e, f("dev_sio_init", &[])),
+    (0x0f, f("dev_sio_open", &[])),
+    (0x10, f("dev_sio_in_out", &[])),
+    (0x11, f("dev_sio_ioctl", &[])),
+    (0x12, f("InstallDevices", &[Int])),
+    (0x13, f("FlushStdInOutPut", &[])),
+    (0x14, f("SystemError", &[])),
+    (0x15, f("tty_cdevinput", &[Hex, Char])),
+    (0x16, f("tty_cdevscan", &[])),
+    (0x17, f("tty_circgetc", &[Hex])),
+    (0x18, f("tty_circputc", &[Char, Hex])),
+    (0x19, f("ioabort", &[Str, Str])),
+    (0x1a, f("set_card_find_mode", &[Int])),
+    (0x1b, f("KernelRedirect", &[Int])),
+    (0x1c, f("AdjustA0Table", &[])),
+];
+
+pub fn function(call: KernelCall) -> Option<&'static Function> {
+    let table = match call.table {
+        Table::A => A,
+        Table::B => B,
+        Table::C => C,
+    };
+    table.iter().find(|(number, _)| *number == call.function).map(|(_, function)| function)
+}
+
+// event classes of OpenEvent and DeliverEvent
+pub fn event_class(class: u32) -> Option<&'static str> {
+    Some(match class {
+        0xf0000001 => "VBLANK",
+        0xf0000002 => "GPU",
+        0xf0000003 => "CDROM",
+        0xf0000004 => "DMA",
+        0xf0000005 => "RTC0",
+        0xf0000006 => "RTC1",
+        0xf0000008 => "CONTROLLER",
+        0xf0000009 => "SPU",
+        0xf000000a => "PIO",
+        0xf000000b => "SIO",
+        0xf0000010 => "EXCEPTION",
+        0xf0000011 => "MEMCARD",
+        0xf0000012 => "MEMCARD_LOW",
+        0xf2000000 => "RCNT0",
+        0xf2000001 => "RCNT1",
+        0xf2000002 => "RCNT2",
+        0xf2000003 => "RCNT3",
+        0xf4000001 => "LIBMCRD",
+        0xf4000002 => "LIBAPI",
+        _ => return None,
+    })
+}
+
+// event spec bits
+pub const EVENT_SPECS: [(u32, &str); 16] = [
+    (0x0001, "COUNTER_ZERO"),
+    (0x0002, "INTERRUPTED"),
+    (0x0004, "IO_END"),
+    (0x0008, "FILE_CLOSED"),
+    (0x0010, "COMMAND_ACK"),
+    (0x0020, "COMMAND_COMPLETE"),
+    (0x0040, "DATA_READY"),
+    (0x0080, "DATA_END"),
+    (0x0100, "TIMEOUT"),
+    (0x0200, "UNKNOWN_COMMAND"),
+    (0x0400, "READ_BUFFER_END"),
+    (0x0800, "WRITE_BUFFER_END"),
+    (0x1000, "INTERRUPT"),
+    (0x2000, "NEW_DEVICE"),
+    (0x4000, "SYSCALL"),
+    (0x8000, "ERROR"),
+];
+
+pub fn event_mode(mode: u32) -> Option<&'static str> {
+    match mode {
+        0x1000 => Some("CALLBACK"),
+        0x2000 => Some("NO_CALLBACK"),
+        _ => None,
+    }
+}
@@ -0,0 +1,176 @@
+use std::collections::VecDeque;
+use std::fmt;
+
+use super::functions::{self, Arg, EVENT_SPECS};
+use super::{KernelCall, read_string};
+use crate::map::{RAM_END, RAM_START};
+use crate::playstation::{PlayStation, mask_region};
+
+// strings longer than that are cut in the log
+const SHOWN_STRING: usize = 64;
+// calls that never return (exit, ReturnFromException, ChangeThread) would pile up forever
+const PENDING_LIMIT: usize = 64;
+
+pub struct Call {
+    pub cycle: u64,
+    // where the call returns to
+    pub ra: u32,
+    pub call: KernelCall,
+    // the function with its decoded arguments
+    pub text: String,
+    // v0 once the call returned
+    pub result: Option<u32>,
+}
+
+impl fmt::Display for Call {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:>12}  {:08x}  {} {}", self.cycle, self.ra, self.call, self.text)?;
+        match self.result {
+            Some(result) => write!(f, " = {:08x}", result),
+            None => Ok(()),
+        }
+    }
+}
+
+// The A0, B0 and C0 calls with their arguments and what they returned, the oldest go once there are
+// more than limit of them.
+pub struct KernelLog {
+    calls: VecDeque<Call>,
+    limit: usize,
+    // calls ever logged, including the ones that went
+    logged: u64,
+    // return address and number of the calls that haven't returned yet, innermost last
+    pending: Vec<(u32, u64)>,
+}
+
+impl KernelLog {
+    pub fn new(limit: usize) -> KernelLog {
+        KernelLog { calls: VecDeque::new(), limit, logged: 0, pending: Vec::new() }
+    }
+
+    pub fn calls(&self) -> &VecDeque<Call> {
+        &self.calls
+    }
+
+    // calls ever logged, the frontends watch it to notice new ones
+    pub fn logged(&self) -> u64 {
+        self.logged
+    }
+
+    pub fn clear(&mut self) {
+        self.calls.clear();
+        self.pending.clear();
+    }
+
+    // the calls in progress belong to a run that was left, they won't return
+    pub fn forget_pending(&mut self) {
+        self.pending.clear();
+    }
+
+    // called before every instruction
+    pub fn step(&mut self, ps: &PlayStation) {
+        let pc = ps.cpu.pc;
+        // a return skips the calls made inside the one returning that never came back
+        if let Some(position) = self.pending.iter().rposition(|&(ra, _)| ra == pc) {
+            let (_, number) = self.pending[position];
+            self.pending.truncate(position);
+            let first = self.logged - self.calls.len() as u64;
+            if let Some(call) =
+                number.checked_sub(first).and_then(|index| self.calls.get_mut(index as usize))
+            {
+                call.result = Some(ps.cpu.registers[2]);
+            }
+        }
+
+        let Some(call) = KernelCall::at(ps) else {
+            return;
+        };
+        let ra = ps.cpu.registers[31];
+        self.calls.push_back(Call {
+            cycle: ps.cycles,
+            ra,
+            call,
+            text: describe(ps, call),
+            result: None,
+        });
+        if self.calls.len() > self.limit {
+            self.calls.pop_front();
+        }
+        self.pending.push((ra, self.logged));
+        if self.pending.len() > PENDING_LIMIT {
+            self.pending.remove(0);
+        }
+        self.logged += 1;
+    }
+}
+
+// "printf("score %d\n", 0x2a, 0x0, 0x0)", unknown functions show their register arguments
+fn describe(ps: &PlayStation, call: KernelCall) -> String {
+    let Some(function) = functions::function(call) else {
+        let registers = &ps.cpu.registers[4..8];
+        return format!(
+            "?({:#x}, {:#x}, {:#x}, {:#x})",
+            registers[0], registers[1], registers[2], registers[3]
+        );
+    };
+    let arguments: Vec<String> = function
+        .args
+        .iter()
+        .enumerate()
+        .map(|(i, &arg)| format_argument(ps, arg, argument(ps, i)))
+        .collect();
+    format!("{}({})", function.name, arguments.join(", "))
+}
+
+// a0-a3, the others are on the stack above the space reserved for those four
+fn argument(ps: &PlayStation, i: usize) -> u32 {
+    if i < 4 {
+        return ps.cpu.registers[4 + i];
+    }
+    let address = mask_region(ps.cpu.registers[29].wrapping_add(0x10 + 4 * (i as u32 - 4)));
+    match address {
+        RAM_START..=RAM_END => ps.ram.read32(address & !3),
+        _ => 0,
+    }
+}
+
+fn format_argument(ps: &PlayStation, arg: Arg, value: u32) -> String {
+    match arg {
+        Arg::Int => (value as i32).to_string(),
+        Arg::Hex => format!("{:#x}", value),
+        Arg::Char => match value as u8 {
+            byte if value <= 0xff && (byte.is_ascii_graphic() || byte == b' ') => {
+                format!("'{}'", byte as char)
+            }
+            _ => format!("{:#x}", value),
+        },
+        Arg::Str if value == 0 => "NULL".to_string(),
+        Arg::Str => {
+            let text = read_string(ps, value);
+            let mut shown: String = text
+                .iter()
+                .take(SHOWN_STRING)
+                .flat_map(|&byte| std::ascii::escape_default(byte))
+                .map(char::from)
+                .collect();
+            if text.len() > SHOWN_STRING {
+                shown.push_str("...");
+            }
+            format!("\"{}\"", shown)
+        }
+        Arg::Class => functions::event_class(value).map_or(format!("{:#x}", value), str::to_string),
+        Arg::Spec => {
+            let names: Vec<&str> = EVENT_SPECS
+                .iter()
+                .filter(|(bit, _)| value & bit != 0)
+                .map(|(_, name)| *name)
+                .collect();
+            if names.is_empty() || value & !0xffff != 0 {
+                format!("{:#x}", value)
+            } else {
+                names.join("|")
+            }
+        }
+        Arg::Mode => functions::event_mode(value).map_or(format!("{:#x}", value), str::to_string),
+    }
+}
@@ -0,0 +1,92 @@
+pub mod functions;
+pub mod log;
+
+use std::fmt;
+
+use crate::map::{BIOS_END, BIOS_START, RAM_END, RAM_START};
+use crate::playstation::{PlayStation, mask_region};
+
+// strings longer than that are cut, a bad pointer shouldn't print all of RAM
+const STRING_LIMIT: usize = 4096;
+
+// The kernel functions are called by jumping to 0xa0, 0xb0 or 0xc0 with the function number in $t1,
+// each address has its own table of functions.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Table {
+    A,
+    B,
+    C,
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct KernelCall {
+    pub table: Table,
+    pub function: u8,
+}
+
+impl KernelCall {
+    // the call the CPU is about to make, when its pc is at one of the entry points
+    pub fn at(ps: &PlayStation) -> Option<KernelCall> {
+        let table = match mask_region(ps.cpu.pc) {
+            0xa0 => Table::A,
+            0xb0 => Table::B,
+            0xc0 => Table::C,
+            _ => return None,
+        };
+        Some(KernelCall { table, function: ps.cpu.registers[9] as u8 })
+    }
+}
+
+impl fmt::Display for KernelCall {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}({:02X}h)", self.table, self.function)
+    }
+}
+
+// called before every instruction, picks up what the kernel is asked to print and logs the calls
+pub fn intercept(ps: &mut PlayStation) {
+    if let Some(mut log) = ps.kernel_log.take() {
+        log.step(ps);
+        ps.kernel_log = Some(log);
+    }
+    if ps.tty.muted_until == Some(ps.cpu.pc) {
+        ps.tty.muted_until = None;
+    }
+    let Some(call) = KernelCall::at(ps) else {
+        return;
+    };
+    if ps.tty.muted_until.is_some() {
+        return;
+    }
+
+    let a0 = ps.cpu.registers[4];
+    match (call.table, call.function) {
+        // std_out_putchar(char)
+        (Table::A, 0x3c) | (Table::B, 0x3d) => ps.tty.push(a0 as u8),
+        // std_out_puts(src)
+        (Table::A, 0x3e) | (Table::B, 0x3f) => {
+            let text = read_string(ps, a0);
+            ps.tty.push_str(&text);
+            ps.tty.muted_until = Some(ps.cpu.registers[31]);
+        }
+        _ => {}
+    }
+}
+
+// a zero terminated string in RAM or the BIOS, without going through the bus
+pub fn read_string(ps: &PlayStation, address: u32) -> Vec<u8> {
+    let mut text = Vec::new();
+    for offset in 0..STRING_LIMIT as u32 {
+        let address = mask_region(address.wrapping_add(offset));
+        let byte = match address {
+            RAM_START..=RAM_END => ps.ram.read8(address),
+            BIOS_START..=BIOS_END => ps.bios.read8(address),
+            _ => break,
+        };
+        if byte == 0 {
+            break;
+        }
+        text.push(byte);
+    }
+    text
+}
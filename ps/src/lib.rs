@@ -1,14 +1,24 @@
 pub mod bios;
+pub mod cdrom;
 pub mod cpu;
+pub mod exe;
 //pub mod expansion_region;
 pub mod expansion_region2;
 pub mod irq;
+pub mod kernel;
 pub mod map;
+pub mod mdec;
+pub mod movie;
 pub mod playstation;
 pub mod ram;
+pub mod sio;
 pub mod spu;
+pub mod state;
 
 pub mod timers;
+pub mod trace;
+pub mod tty;
+pub mod wav;
 
 pub mod dma;
 
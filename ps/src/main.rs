@@ -1,62 +1,521 @@
+use ps::cdrom::disc::Disc;
+use ps::cdrom::iso9660::{DirectoryRecord, Iso9660};
 use ps::cpu::mipsr3000::run_instruction;
-use ps::playstation::PlayStation;
-use std::collections::HashSet;
-use std::fs::File;
-use std::io::{BufWriter, Write};
+use ps::exe::Executable;
+use ps::kernel::log::KernelLog;
+use ps::movie::{Input, Movie};
+use ps::playstation::{self, BusError, CYCLES_PER_FRAME, PlayStation};
+use ps::sio::memory_card::MemoryCard;
+use ps::state;
+use ps::trace::{Filter, Format, Tracer};
+use ps::wav::WavWriter;
+use std::io;
+use std::ops::{Range, RangeInclusive};
+use std::panic::{self, AssertUnwindSafe};
 use std::path::Path;
+use std::process::exit;
 use std::{env, fs};
+
+// ps [executable] [options], a headless run for scripts and CI
+const USAGE: &str = "usage: ps [executable] [--bios <file>] [--disc <image>] [--card1 <file>] [--card2 <file>]\n          \
+                     [--state <file>] [--frames [start..]end from power on or the state] [--instructions <count>]\n          \
+                     [--wav <output> | --record <movie> | --play <movie>]\n          \
+                     [--trace <file>] [--trace-format full|pc|registers] [--trace-start <pc>] [--trace-stop <pc>]\n          \
+                     [--trace-pc <first>..<last>] [--trace-window <start>..<end>] [--trace-address <first>[..<last>]]\n          \
+                     [--dump-ram <file>] [--save-state <file>] [--tty <file>|-] [--kernel-trace <file>|-]\n       \
+                     ps iso <image> ls [path] | paths | extract <path> <output>";
+
+// besides 1 for files that can't be used or a desynced movie and 2 for bad arguments
+const EXIT_PANIC: i32 = 3;
+const EXIT_BUS_ERROR: i32 = 4;
+
+// BIOS calls kept for --kernel-trace, the oldest go after that many
+const KERNEL_TRACE_LIMIT: usize = 1_000_000;
+
+struct Options {
+    bios: String,
+    executable: Option<String>,
+    disc: Option<String>,
+    cards: [Option<String>; 2],
+    state: Option<String>,
+    frames: Range<usize>,
+    instructions: Option<u64>,
+    wav: Option<String>,
+    record: Option<String>,
+    play: Option<String>,
+    trace: Option<String>,
+    trace_format: Format,
+    trace_filter: Filter,
+    trace_start: Option<u32>,
+    trace_stop: Option<u32>,
+    dump_ram: Option<String>,
+    tty: Option<String>,
+    kernel_trace: Option<String>,
+    save_state: Option<String>,
+}
+
 fn main() {
     //env::set_var("RUST_BACKTRACE", "1");
 
-    let bios = fs::read("./binaries/SCPH1001.BIN").unwrap().into_boxed_slice();
+    playstation::install_panic_hook();
+    let args: Vec<String> = env::args().collect();
+    if args.get(1).map(String::as_str) == Some("iso") {
+        iso_command(&args[2..]);
+        return;
+    }
+
+    let options = parse_options(&args[1..]).unwrap_or_else(|e| {
+        eprintln!("{}\n{}", e, USAGE);
+        exit(2);
+    });
+
+    let bios = fs::read(&options.bios).unwrap_or_else(|e| {
+        eprintln!("can't read {}: {}", options.bios, e);
+        exit(1);
+    });
+    let mut ps = PlayStation::new(bios.into_boxed_slice());
+    if options.kernel_trace.is_some() {
+        ps.kernel_log = Some(KernelLog::new(KERNEL_TRACE_LIMIT));
+    }
+
+    // .mcr/.mcd images, created formatted when they don't exist
+    for (port, path) in options.cards.iter().enumerate() {
+        if let Some(path) = path {
+            let card = MemoryCard::open(Path::new(path)).unwrap_or_else(|e| {
+                eprintln!("{}: {}", path, e);
+                exit(1);
+            });
+            ps.sio0.insert_memory_card(port, Some(Box::new(card)));
+        }
+    }
+
+    // there is no CD-ROM drive yet, the image only goes into the movie hashes
+    let disc = options.disc.as_ref().map(|path| {
+        Disc::open(Path::new(path)).unwrap_or_else(|e| {
+            eprintln!("can't open {}: {}", path, e);
+            exit(1);
+        });
+        state::hash_file(Path::new(path)).unwrap_or_else(|e| {
+            eprintln!("can't read {}: {}", path, e);
+            exit(1);
+        })
+    });
+
+    // boots the BIOS and runs the executable instead of the shell
+    let executable = options.executable.as_ref().map(|path| {
+        let data = fs::read(path).unwrap_or_else(|e| {
+            eprintln!("can't read {}: {}", path, e);
+            exit(1);
+        });
+        let exe = Executable::parse(&data).unwrap_or_else(|e| {
+            eprintln!("{}: {}", path, e);
+            exit(1);
+        });
+        ps.sideload(exe);
+        data
+    });
+
+    // picks up where the state was saved, the executable is already in it
+    if let Some(path) = &options.state {
+        let data = fs::read(path).unwrap_or_else(|e| {
+            eprintln!("can't read {}: {}", path, e);
+            exit(1);
+        });
+        ps.load_state(&data).unwrap_or_else(|e| {
+            eprintln!("{}: {}", path, e);
+            exit(1);
+        });
+    }
 
-    let mut ps = PlayStation::new(bios);
-    //println!("{:08x}", ps.read_word(0xbfc06f0c));
+    // the dumps are written whatever happens, a crash or a desync is when they're needed most
+    let result = panic::catch_unwind(AssertUnwindSafe(|| {
+        if let Some(path) = &options.play {
+            play_movie(&mut ps, Path::new(path), executable.as_deref(), disc)
+        } else if let Some(path) = &options.wav {
+            record_audio(&mut ps, Path::new(path), options.frames.clone())
+        } else if let Some(path) = &options.record {
+            record_movie(&mut ps, Path::new(path), options.frames.end, executable.as_deref(), disc)
+        } else {
+            run(&mut ps, &options)
+        }
+    }));
+
+    if let Some(path) = &options.dump_ram {
+        write_file(path, &ps.ram.data);
+    }
+    // "-" for stdout
+    match options.tty.as_deref() {
+        Some("-") => print!("{}", ps.tty.text()),
+        Some(path) => write_file(path, ps.tty.text().as_bytes()),
+        None => {}
+    }
+    if let (Some(path), Some(log)) = (&options.kernel_trace, &ps.kernel_log) {
+        let text: String = log.calls().iter().map(|call| format!("{}\n", call)).collect();
+        match path.as_str() {
+            "-" => print!("{}", text),
+            path => write_file(path, text.as_bytes()),
+        }
+    }
+    if let Some(path) = &options.save_state {
+        write_file(path, &ps.save_state());
+    }
+
+    match result {
+        Ok(Ok(())) => {}
+        Ok(Err(e)) => {
+            eprintln!("{}", e);
+            exit(1);
+        }
+        // the panic message is already out
+        Err(payload) => {
+            eprintln!("stopped at pc {:08x} after {} cycles", ps.cpu.pc, ps.cycles);
+            exit(if payload.is::<BusError>() { EXIT_BUS_ERROR } else { EXIT_PANIC });
+        }
+    }
+}
+
+fn parse_options(args: &[String]) -> Result<Options, String> {
+    let mut options = Options {
+        bios: "./binaries/SCPH1001.BIN".to_string(),
+        executable: None,
+        disc: None,
+        cards: [None, None],
+        state: None,
+        frames: 0..600,
+        instructions: None,
+        wav: None,
+        record: None,
+        play: None,
+        trace: None,
+        trace_format: Format::Full,
+        trace_filter: Filter::default(),
+        trace_start: None,
+        trace_stop: None,
+        dump_ram: None,
+        tty: None,
+        kernel_trace: None,
+        save_state: None,
+    };
+
+    let mut rest = args.iter();
+    while let Some(arg) = rest.next() {
+        if !arg.starts_with("--") {
+            if options.executable.is_some() {
+                return Err(format!("more than one executable, {} is extra", arg));
+            }
+            options.executable = Some(arg.clone());
+            continue;
+        }
+        let mut value = || rest.next().cloned().ok_or_else(|| format!("{} needs a value", arg));
+        match arg.as_str() {
+            "--bios" => options.bios = value()?,
+            "--disc" => options.disc = Some(value()?),
+            "--card1" => options.cards[0] = Some(value()?),
+            "--card2" => options.cards[1] = Some(value()?),
+            "--state" => options.state = Some(value()?),
+            "--frames" => {
+                let range = value()?;
+                options.frames = parse_frames(&range).ok_or(format!("bad frame range {}", range))?
+            }
+            "--instructions" => {
+                let count = value()?;
+                options.instructions =
+                    Some(count.parse().map_err(|_| format!("bad instruction count {}", count))?)
+            }
+            "--wav" => options.wav = Some(value()?),
+            "--record" => options.record = Some(value()?),
+            "--play" => options.play = Some(value()?),
+            "--trace" => options.trace = Some(value()?),
+            "--trace-format" => {
+                let name = value()?;
+                options.trace_format =
+                    Format::parse(&name).ok_or(format!("unknown trace format {}", name))?
+            }
+            "--trace-pc" => options.trace_filter.pc = Some(parse_address_range(&value()?)?),
+            "--trace-window" => {
+                let window = value()?;
+                let (start, end) =
+                    window.split_once("..").ok_or(format!("bad instruction window {}", window))?;
+                let bad = |_| format!("bad instruction window {}", window);
+                options.trace_filter.instructions =
+                    Some(start.parse().map_err(bad)?..end.parse().map_err(bad)?)
+            }
+            "--trace-address" => {
+                options.trace_filter.address = Some(parse_address_range(&value()?)?)
+            }
+            "--trace-start" => options.trace_start = Some(parse_address(&value()?)?),
+            "--trace-stop" => options.trace_stop = Some(parse_address(&value()?)?),
+            "--dump-ram" => options.dump_ram = Some(value()?),
+            "--tty" => options.tty = Some(value()?),
+            "--kernel-trace" => options.kernel_trace = Some(value()?),
+            "--save-state" => options.save_state = Some(value()?),
+            _ => return Err(format!("unknown option {}", arg)),
+        }
+    }
 
-    let output_path = "pc_trace_rust.txt";
-    let file = File::create(output_path).unwrap();
-    let mut writer = BufWriter::new(file);
+    let modes =
+        [&options.wav, &options.record, &options.play].iter().filter(|mode| mode.is_some()).count();
+    if modes > 1 {
+        return Err("--wav, --record and --play don't go together".to_string());
+    }
+    // a movie starts from its own state
+    if options.play.is_some() && options.state.is_some() {
+        return Err("--play can't start from --state".to_string());
+    }
+    // those only drive the plain run, the others go frame by frame
+    let stepping = options.instructions.is_some() || options.trace.is_some();
+    if modes > 0 && stepping {
+        return Err("--instructions and --trace only go with a plain run".to_string());
+    }
+    let filtered = options.trace_filter.pc.is_some()
+        || options.trace_filter.instructions.is_some()
+        || options.trace_filter.address.is_some();
+    if options.trace.is_none()
+        && (options.trace_start.is_some() || options.trace_stop.is_some() || filtered)
+    {
+        return Err("the trace options need --trace".to_string());
+    }
+    // only the audio has anything to leave out before the start
+    if options.frames.start > 0 && options.wav.is_none() {
+        return Err("--frames with a start only goes with --wav".to_string());
+    }
+    Ok(options)
+}
 
-    println!("Starting emulation trace...");
-    const START_PC: u32 = 0xbfc0127b;
+// hex, with or without 0x
+fn parse_address(text: &str) -> Result<u32, String> {
+    let digits = text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")).unwrap_or(text);
+    u32::from_str_radix(digits, 16).map_err(|_| format!("bad address {}", text))
+}
 
-    // Tracking variables
-    let mut recording = false;
-    let mut instruction_count = 0;
-    let mut recorded_count = 0;
-    let mut visited_pcs = HashSet::new();
+// "first..last" both included, or a single address
+fn parse_address_range(text: &str) -> Result<RangeInclusive<u32>, String> {
+    match text.split_once("..") {
+        Some((first, last)) => Ok(parse_address(first)?..=parse_address(last)?),
+        None => parse_address(text).map(|address| address..=address),
+    }
+}
 
-    // Execute instructions until we've recorded enough after the target PC
-    while instruction_count < 1_000_000 && recorded_count < 5000 {
-        let current_pc = ps.cpu.pc;
+// Runs frames.end frames or the instruction count, whichever comes first. The trace starts at
+// the start pc and the run ends at the stop pc, the filters pick what goes in between.
+fn run(ps: &mut PlayStation, options: &Options) -> Result<(), String> {
+    let mut tracer = match &options.trace {
+        Some(path) => Some(
+            Tracer::create(Path::new(path), options.trace_format, options.trace_filter.clone())
+                .map_err(|e| format!("can't create {}: {}", path, e))?,
+        ),
+        None => None,
+    };
+    let end =
+        (ps.cycles / CYCLES_PER_FRAME as u64 + options.frames.end as u64) * CYCLES_PER_FRAME as u64;
+    let limit = options.instructions.unwrap_or(u64::MAX);
+    let trace_error = |e: io::Error| format!("can't write the trace: {}", e);
 
-        // Start recording once we hit our target PC
-        if current_pc == START_PC {
-            recording = true;
-            println!("Found target PC 0xBFC003CC at instruction #{}", instruction_count);
+    let mut tracing = options.trace_start.is_none();
+    let mut instructions = 0u64;
+    while ps.cycles < end && instructions < limit {
+        match &mut tracer {
+            Some(tracer) => {
+                tracing |= options.trace_start == Some(ps.cpu.pc);
+                if tracing && options.trace_stop == Some(ps.cpu.pc) || tracer.finished() {
+                    break;
+                }
+                if tracing {
+                    tracer.step(ps).map_err(trace_error)?;
+                } else {
+                    run_instruction(ps);
+                }
+            }
+            None => run_instruction(ps),
         }
+        instructions += 1;
+    }
+
+    if let Some(mut tracer) = tracer {
+        tracer.flush().map_err(trace_error)?;
+        println!("{} lines traced", tracer.lines());
+    }
+    println!("{} instructions, {} cycles, pc {:08x}", instructions, ps.cycles, ps.cpu.pc);
+    Ok(())
+}
 
-        // Record this PC if we're in recording mode
-        if recording {
-            writeln!(writer, "{}: 0x{:08X}", recorded_count, current_pc);
-            recorded_count += 1;
-            visited_pcs.insert(current_pc);
+// nobody is holding the pads, a boot sequence to check later runs against
+fn record_movie(
+    ps: &mut PlayStation,
+    path: &Path,
+    frames: usize,
+    executable: Option<&[u8]>,
+    disc: Option<u64>,
+) -> Result<(), String> {
+    let mut movie = Movie::new(ps, executable, disc);
+    for _ in 0..frames {
+        if ps.cycles.is_multiple_of(CYCLES_PER_FRAME as u64) {
+            movie.record(ps, Input::default());
         }
+        ps.run_next_frame();
+    }
+    movie.finish(ps);
+    movie.save(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    println!(
+        "{} frames -> {} (state {:016x})",
+        movie.inputs.len(),
+        path.display(),
+        movie.final_state
+    );
+    Ok(())
+}
 
-        // Execute the instruction
-        run_instruction(&mut ps);
-        instruction_count += 1;
+fn play_movie(
+    ps: &mut PlayStation,
+    path: &Path,
+    executable: Option<&[u8]>,
+    disc: Option<u64>,
+) -> Result<(), String> {
+    let movie = Movie::open(path)
+        .and_then(|movie| {
+            movie.check(ps, executable, disc)?;
+            movie.play(ps)?;
+            Ok(movie)
+        })
+        .map_err(|e| format!("{}: {}", path.display(), e))?;
+    println!(
+        "{}: {} frames, ends in state {:016x}",
+        path.display(),
+        movie.inputs.len(),
+        movie.final_state
+    );
+    Ok(())
+}
 
-        // Break if we've been running too long without finding the target
-        /*if !recording && instruction_count > 100_000 {
-            writeln!(writer, "Couldn't find target PC after 100,000 instructions");
-            break;
-        }*/
+fn write_file(path: &str, data: &[u8]) {
+    fs::write(path, data).unwrap_or_else(|e| {
+        eprintln!("can't write {}: {}", path, e);
+        exit(1);
+    });
+}
+
+// "600" or "60..600", frames counted from where the run starts, power on or the --state
+fn parse_frames(range: &str) -> Option<Range<usize>> {
+    let (start, end) = match range.split_once("..") {
+        Some((start, end)) => (start.parse().ok()?, end.parse().ok()?),
+        None => (0, range.parse().ok()?),
+    };
+    if start < end { Some(start..end) } else { None }
+}
+
+// runs until the end of the range, the samples of the frames before its start are dropped
+fn record_audio(ps: &mut PlayStation, path: &Path, frames: Range<usize>) -> Result<(), String> {
+    let mut wav =
+        WavWriter::create(path).map_err(|e| format!("can't create {}: {}", path.display(), e))?;
+
+    let result = (0..frames.end).try_for_each(|frame| {
+        ps.run_next_frame();
+        let samples = ps.take_audio();
+        if frame >= frames.start { wav.write_samples(&samples) } else { Ok(()) }
+    });
+
+    let samples = wav.samples();
+    result.and_then(|_| wav.finish()).map_err(|e| format!("{}: {}", path.display(), e))?;
+    println!(
+        "frames {}..{} -> {} ({} samples, {:.2}s)",
+        frames.start,
+        frames.end,
+        path.display(),
+        samples,
+        samples as f64 / 44100.0
+    );
+    Ok(())
+}
+
+// ps iso <image> ls [path]
+// ps iso <image> paths
+// ps iso <image> extract <path> <output>
+fn iso_command(args: &[String]) {
+    const USAGE: &str = "usage: ps iso <image> ls [path] | paths | extract <path> <output>";
+    if args.len() < 2 {
+        eprintln!("{}", USAGE);
+        exit(2);
     }
 
-    // Write summary
-    writeln!(writer, "\n------ EXECUTION SUMMARY ------");
-    writeln!(writer, "Instructions executed until target: {}", instruction_count - recorded_count);
-    writeln!(writer, "Instructions recorded after target: {}", recorded_count);
-    writeln!(writer, "Unique PC addresses visited after target: {}", visited_pcs.len());
+    let mut disc = Disc::open(Path::new(&args[0])).unwrap_or_else(|e| {
+        eprintln!("can't open {}: {}", args[0], e);
+        exit(1);
+    });
+    let mut iso = Iso9660::open(&mut disc).unwrap_or_else(|e| {
+        eprintln!("{}: {}", args[0], e);
+        exit(1);
+    });
+
+    let find = |iso: &mut Iso9660, path: &str| -> DirectoryRecord {
+        match iso.find(path) {
+            Ok(Some(record)) => record,
+            Ok(None) => {
+                eprintln!("{}: not found", path);
+                exit(1);
+            }
+            Err(e) => {
+                eprintln!("{}: {}", path, e);
+                exit(1);
+            }
+        }
+    };
+
+    let result = match (args[1].as_str(), &args[2..]) {
+        ("ls", rest) => {
+            let dir = find(&mut iso, rest.first().map_or("/", String::as_str));
+            println!("volume {} ({} sectors)", iso.volume.volume_id, iso.volume.volume_space_size);
+            iso.read_dir(&dir).map(|entries| {
+                for entry in entries.iter().filter(|e| e.name != "." && e.name != "..") {
+                    let attributes = entry.xa.map_or(0, |xa| xa.attributes);
+                    println!(
+                        "{} {:04x} {:>8} {:04}-{:02}-{:02} {:>6}  {}",
+                        if entry.is_directory() { 'd' } else { '-' },
+                        attributes,
+                        entry.size,
+                        entry.date.year,
+                        entry.date.month,
+                        entry.date.day,
+                        entry.lba,
+                        entry.name
+                    );
+                }
+            })
+        }
+        ("paths", _) => iso.path_table().map(|table| {
+            for (i, entry) in table.iter().enumerate() {
+                println!(
+                    "{:>4} parent {:>4} lba {:>6}  {}",
+                    i + 1,
+                    entry.parent,
+                    entry.lba,
+                    entry.name
+                );
+            }
+        }),
+        ("extract", [path, output]) => {
+            let file = find(&mut iso, path);
+            // XA audio and movies only make sense as whole sectors
+            let raw = file.xa.is_some_and(|xa| xa.is_raw());
+            let data = if raw { iso.read_file_raw(&file) } else { iso.read_file(&file) };
+            data.and_then(|data| {
+                println!(
+                    "{} -> {} ({} bytes{})",
+                    path,
+                    output,
+                    data.len(),
+                    if raw { ", raw sectors" } else { "" }
+                );
+                fs::write(output, data)
+            })
+        }
+        _ => {
+            eprintln!("{}", USAGE);
+            exit(2);
+        }
+    };
+
+    if let Err(e) = result {
+        eprintln!("{}", e);
+        exit(1);
+    }
 }
@@ -3,13 +3,21 @@ pub const BIOS_SIZE: u32 = 512 * 1024;
 pub const BIOS_END: u32 = BIOS_START + BIOS_SIZE - 1;
 
 pub const SPU_START: u32 = 0x1F801C00;
-pub const SPU_SIZE: u32 = 0xFFF;
+pub const SPU_SIZE: u32 = 0x400;
 pub const SPU_END: u32 = SPU_START + SPU_SIZE - 1;
 
 pub const EXPANSION_REGION_2_START: u32 = 0x1F802000;
 pub const EXPANSION_REGION_2_SIZE: u32 = 8 * 1024;
 pub const EXPANSION_REGION_2_END: u32 = EXPANSION_REGION_2_START + EXPANSION_REGION_2_SIZE - 1;
 
+pub const SIO0_START: u32 = 0x1F801040;
+pub const SIO0_SIZE: u32 = 0x10;
+pub const SIO0_END: u32 = SIO0_START + SIO0_SIZE - 1;
+
+pub const MDEC_START: u32 = 0x1F801820;
+pub const MDEC_SIZE: u32 = 0x8;
+pub const MDEC_END: u32 = MDEC_START + MDEC_SIZE - 1;
+
 pub const IRQ_STATUS_REG: u32 = 0x1F801070;
 pub const IRQ_MASK_REG: u32 = 0x1F801074;
 
@@ -0,0 +1,430 @@
+use crate::map;
+use crate::state::{FromState, Snapshot, StateReader, StateWriter};
+use std::collections::VecDeque;
+
+// MDEC0 (0x1F801820): command/parameter writes, decoded data reads
+// MDEC1 (0x1F801824): control writes, status reads
+const MDEC_DATA: u32 = 0x0;
+const MDEC_CONTROL: u32 = 0x4;
+
+// run-length codes are read in zigzag order, ZAGZIG maps them back to the 8x8 block
+const ZIGZAG: [usize; 64] = [
+    0, 1, 5, 6, 14, 15, 27, 28, 2, 4, 7, 13, 16, 26, 29, 42, 3, 8, 12, 17, 25, 30, 41, 43, 9, 11,
+    18, 24, 31, 40, 44, 53, 10, 19, 23, 32, 39, 45, 52, 54, 20, 22, 33, 38, 46, 51, 55, 60, 21, 34,
+    37, 47, 50, 56, 59, 61, 35, 36, 48, 49, 57, 58, 62, 63,
+];
+pub const ZAGZIG: [usize; 64] = {
+    let mut zagzig = [0; 64];
+    let mut i = 0;
+    while i < 64 {
+        zagzig[ZIGZAG[i]] = i;
+        i += 1;
+    }
+    zagzig
+};
+
+// blocks are padded with this end of block code
+const END_OF_BLOCK: u16 = 0xfe00;
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Depth {
+    Bpp4 = 0,
+    Bpp8 = 1,
+    Bpp24 = 2,
+    Bpp15 = 3,
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+enum Command {
+    Idle,
+    Decode,
+    SetQuantTables { chroma: bool },
+    SetScaleTable,
+    // commands 0 and 4-7 only eat their parameters
+    Unknown,
+}
+
+pub struct MDEC {
+    luma_quant: [u8; 64],
+    chroma_quant: [u8; 64],
+    scale: [i16; 64],
+    command: Command,
+    // parameter words left for the current command
+    remaining: u32,
+    // parameters of the current command as halfwords, executed once they are all in
+    input: Vec<u16>,
+    output: VecDeque<u32>,
+    depth: Depth,
+    signed: bool,
+    set_bit15: bool,
+    // MDEC1 bits 30 and 29, DMA0 and DMA1 requests
+    data_in_enabled: bool,
+    data_out_enabled: bool,
+}
+
+impl Snapshot for Command {
+    fn save(&self, state: &mut StateWriter) {
+        let index: u8 = match self {
+            Command::Idle => 0,
+            Command::Decode => 1,
+            Command::SetQuantTables { chroma: false } => 2,
+            Command::SetQuantTables { chroma: true } => 3,
+            Command::SetScaleTable => 4,
+            Command::Unknown => 5,
+        };
+        index.save(state);
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), String> {
+        *self = match u8::read(state)? {
+            0 => Command::Idle,
+            1 => Command::Decode,
+            2 => Command::SetQuantTables { chroma: false },
+            3 => Command::SetQuantTables { chroma: true },
+            4 => Command::SetScaleTable,
+            5 => Command::Unknown,
+            index => return Err(format!("bad MDEC command {}", index)),
+        };
+        Ok(())
+    }
+}
+
+crate::state::snapshot_enum!(Depth { Bpp4, Bpp8, Bpp24, Bpp15 });
+crate::state::snapshot!(MDEC {
+    luma_quant,
+    chroma_quant,
+    scale,
+    command,
+    remaining,
+    input,
+    output,
+    depth,
+    signed,
+    set_bit15,
+    data_in_enabled,
+    data_out_enabled,
+});
+
+impl MDEC {
+    pub fn new() -> MDEC {
+        MDEC {
+            luma_quant: [0; 64],
+            chroma_quant: [0; 64],
+            scale: [0; 64],
+            command: Command::Idle,
+            remaining: 0,
+            input: Vec::new(),
+            output: VecDeque::new(),
+            depth: Depth::Bpp4,
+            signed: false,
+            set_bit15: false,
+            data_in_enabled: false,
+            data_out_enabled: false,
+        }
+    }
+
+    // MDEC0 reads take the word out of the output FIFO
+    pub fn read32(&mut self, address: u32) -> u32 {
+        match address - map::MDEC_START {
+            MDEC_DATA => self.read_data(),
+            _ => self.peek32(address),
+        }
+    }
+
+    // what a read would return without taking the word, for debuggers and traces
+    pub fn peek32(&self, address: u32) -> u32 {
+        match address - map::MDEC_START {
+            MDEC_DATA => self.output.front().copied().unwrap_or(0),
+            MDEC_CONTROL => self.status(),
+            _ => panic!("unhandled MDEC read {:08x}", address),
+        }
+    }
+
+    pub fn write32(&mut self, address: u32, value: u32) {
+        match address - map::MDEC_START {
+            MDEC_DATA => self.write_command(value),
+            MDEC_CONTROL => self.write_control(value),
+            _ => panic!("unhandled MDEC write {:08x} value: {:08x}", address, value),
+        }
+    }
+
+    fn is_busy(&self) -> bool {
+        self.remaining > 0
+    }
+
+    // DMA1 request, macroblocks are decoded once all of the command's parameters are in
+    pub fn has_output(&self) -> bool {
+        !self.output.is_empty()
+    }
+
+    pub fn read_data(&mut self) -> u32 {
+        self.output.pop_front().unwrap_or(0)
+    }
+
+    fn status(&self) -> u32 {
+        let mut status = 0;
+        status |= (!self.has_output() as u32) << 31;
+        status |= (self.is_busy() as u32) << 29;
+        status |= (self.data_in_enabled as u32) << 28;
+        status |= ((self.data_out_enabled && self.has_output()) as u32) << 27;
+        status |= (self.depth as u32) << 25;
+        status |= (self.signed as u32) << 24;
+        status |= (self.set_bit15 as u32) << 23;
+        status | (self.remaining.wrapping_sub(1) & 0xffff)
+    }
+
+    fn write_control(&mut self, value: u32) {
+        if value & (1 << 31) != 0 {
+            self.command = Command::Idle;
+            self.remaining = 0;
+            self.input.clear();
+            self.output.clear();
+            self.depth = Depth::Bpp4;
+            self.signed = false;
+            self.set_bit15 = false;
+        }
+        self.data_in_enabled = value & (1 << 30) != 0;
+        self.data_out_enabled = value & (1 << 29) != 0;
+    }
+
+    pub fn write_command(&mut self, word: u32) {
+        if self.remaining == 0 {
+            self.start_command(word);
+        } else {
+            self.input.push(word as u16);
+            self.input.push((word >> 16) as u16);
+            self.remaining -= 1;
+        }
+
+        if self.remaining == 0 && self.command != Command::Idle {
+            self.execute();
+        }
+    }
+
+    fn start_command(&mut self, word: u32) {
+        self.input.clear();
+        self.depth = match (word >> 27) & 0x3 {
+            0 => Depth::Bpp4,
+            1 => Depth::Bpp8,
+            2 => Depth::Bpp24,
+            _ => Depth::Bpp15,
+        };
+        self.signed = word & (1 << 26) != 0;
+        self.set_bit15 = word & (1 << 25) != 0;
+
+        let (command, words) = match word >> 29 {
+            1 => (Command::Decode, word & 0xffff),
+            2 if word & 1 != 0 => (Command::SetQuantTables { chroma: true }, 32),
+            2 => (Command::SetQuantTables { chroma: false }, 16),
+            3 => (Command::SetScaleTable, 32),
+            _ => (Command::Unknown, word & 0xffff),
+        };
+        self.command = command;
+        self.remaining = words;
+    }
+
+    fn execute(&mut self) {
+        let input = std::mem::take(&mut self.input);
+        match self.command {
+            Command::Decode => self.decode(&input),
+            Command::SetQuantTables { chroma } => {
+                let bytes: Vec<u8> =
+                    input.iter().flat_map(|halfword| halfword.to_le_bytes()).collect();
+                self.luma_quant.copy_from_slice(&bytes[..64]);
+                if chroma {
+                    self.chroma_quant.copy_from_slice(&bytes[64..128]);
+                }
+            }
+            Command::SetScaleTable => {
+                for (entry, &halfword) in self.scale.iter_mut().zip(input.iter()) {
+                    *entry = halfword as i16;
+                }
+            }
+            Command::Unknown | Command::Idle => (),
+        }
+        self.command = Command::Idle;
+    }
+
+    fn decode(&mut self, input: &[u16]) {
+        let mut stream = input.iter().copied();
+        loop {
+            match self.depth {
+                Depth::Bpp4 | Depth::Bpp8 => {
+                    let Some(y) = self.decode_block(&mut stream, true) else { break };
+                    self.output_mono(&y);
+                }
+                Depth::Bpp15 | Depth::Bpp24 => {
+                    // Cr, Cb, then the four luma blocks of the 16x16 macroblock
+                    let mut blocks = [[0; 64]; 6];
+                    for (i, block) in blocks.iter_mut().enumerate() {
+                        match self.decode_block(&mut stream, i >= 2) {
+                            Some(decoded) => *block = decoded,
+                            None => return,
+                        }
+                    }
+                    self.output_color(&blocks);
+                }
+            }
+        }
+    }
+
+    // run-length decoding and dequantisation of one 8x8 block followed by the IDCT
+    fn decode_block(
+        &self,
+        stream: &mut impl Iterator<Item = u16>,
+        luma: bool,
+    ) -> Option<[i32; 64]> {
+        let quant = if luma { &self.luma_quant } else { &self.chroma_quant };
+        let mut block = [0i32; 64];
+
+        let mut code = stream.find(|&code| code != END_OF_BLOCK)?;
+        let q_scale = ((code >> 10) & 0x3f) as i32;
+        let mut k = 0;
+        let mut value = signed10(code) * quant[0] as i32;
+        loop {
+            if q_scale == 0 {
+                value = signed10(code) * 2;
+            }
+            value = value.clamp(-0x400, 0x3ff);
+            // a scale of 0 stores the coefficients without zigzag
+            if q_scale > 0 {
+                block[ZAGZIG[k]] = value;
+            } else {
+                block[k] = value;
+            }
+
+            code = stream.next().unwrap_or(END_OF_BLOCK);
+            if code == END_OF_BLOCK {
+                break;
+            }
+            k += ((code >> 10) & 0x3f) as usize + 1;
+            if k > 63 {
+                break;
+            }
+            value = (signed10(code) * quant[k] as i32 * q_scale + 4) / 8;
+        }
+
+        self.idct(&mut block);
+        Some(block)
+    }
+
+    // two 1D passes with the uploaded scale table (a scaled cosine matrix)
+    fn idct(&self, block: &mut [i32; 64]) {
+        let mut temp = [0i32; 64];
+        idct_pass(block, &mut temp, &self.scale);
+        idct_pass(&temp, block, &self.scale);
+    }
+
+    fn output_mono(&mut self, y: &[i32; 64]) {
+        let bytes: Vec<u8> = y.iter().map(|&luma| self.to_unsigned(luma)).collect();
+        match self.depth {
+            Depth::Bpp8 => {
+                for word in bytes.chunks(4) {
+                    self.output.push_back(u32::from_le_bytes([word[0], word[1], word[2], word[3]]));
+                }
+            }
+            _ => {
+                // 8 pixels per word, the first one in the low nibble
+                for word in bytes.chunks(8) {
+                    let packed = word
+                        .iter()
+                        .enumerate()
+                        .fold(0, |packed, (i, &pixel)| packed | ((pixel as u32 >> 4) << (i * 4)));
+                    self.output.push_back(packed);
+                }
+            }
+        }
+    }
+
+    fn output_color(&mut self, blocks: &[[i32; 64]; 6]) {
+        let (cr, cb) = (&blocks[0], &blocks[1]);
+        let mut pixels = [[0u8; 3]; 256];
+
+        for (i, y) in blocks[2..].iter().enumerate() {
+            let (xx, yy) = ((i & 1) * 8, (i >> 1) * 8);
+            for py in 0..8 {
+                for px in 0..8 {
+                    let chroma = (px + xx) / 2 + ((py + yy) / 2) * 8;
+                    let (r, b) = (cr[chroma], cb[chroma]);
+                    // fixed point 1.402 R, -0.3437 B - 0.7143 R and 1.772 B
+                    let g = (-88 * b - 183 * r) >> 8;
+                    let r = (359 * r) >> 8;
+                    let b = (454 * b) >> 8;
+
+                    let luma = y[px + py * 8];
+                    let rgb = [luma + r, luma + g, luma + b].map(|c| self.to_unsigned(c));
+                    pixels[(px + xx) + (py + yy) * 16] = rgb;
+                }
+            }
+        }
+
+        match self.depth {
+            Depth::Bpp24 => {
+                let bytes: Vec<u8> = pixels.iter().flatten().copied().collect();
+                for word in bytes.chunks(4) {
+                    self.output.push_back(u32::from_le_bytes([word[0], word[1], word[2], word[3]]));
+                }
+            }
+            _ => {
+                let bit15 = (self.set_bit15 as u16) << 15;
+                let halfwords: Vec<u16> = pixels
+                    .iter()
+                    .map(|&[r, g, b]| {
+                        (r as u16 >> 3) | ((g as u16 >> 3) << 5) | ((b as u16 >> 3) << 10) | bit15
+                    })
+                    .collect();
+                for pair in halfwords.chunks(2) {
+                    self.output.push_back(pair[0] as u32 | ((pair[1] as u32) << 16));
+                }
+            }
+        }
+    }
+
+    // pixels are clamped to -128..127, unsigned output moves them to 0..255
+    fn to_unsigned(&self, value: i32) -> u8 {
+        let value = value.clamp(-128, 127) as i8 as u8;
+        if self.signed { value } else { value ^ 0x80 }
+    }
+}
+
+impl Default for MDEC {
+    fn default() -> MDEC {
+        MDEC::new()
+    }
+}
+
+fn idct_pass(src: &[i32; 64], dst: &mut [i32; 64], scale: &[i16; 64]) {
+    for x in 0..8 {
+        for y in 0..8 {
+            let mut sum = 0;
+            for z in 0..8 {
+                sum += src[y + z * 8] * (scale[x + z * 8] as i32 / 8);
+            }
+            dst[x + y * 8] = (sum + 0xfff) / 0x2000;
+        }
+    }
+}
+
+fn signed10(code: u16) -> i32 {
+    (((code & 0x3ff) << 6) as i16 >> 6) as i32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn peeking_leaves_the_word_in_the_fifo() {
+        let mut mdec = MDEC::new();
+        mdec.output.extend([0x1234, 0x5678]);
+        let data = map::MDEC_START + MDEC_DATA;
+        let status = map::MDEC_START + MDEC_CONTROL;
+
+        assert_eq!(mdec.peek32(data), 0x1234);
+        assert_eq!(mdec.peek32(data), 0x1234);
+        assert_eq!(mdec.read32(data), 0x1234);
+        assert_eq!(mdec.read32(data), 0x5678);
+        assert_eq!(mdec.peek32(status) >> 31, 1);
+        assert_eq!(mdec.read32(data), 0);
+    }
+}
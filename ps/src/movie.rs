@@ -0,0 +1,378 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::cpu::mipsr3000::run_instruction;
+use crate::playstation::{CYCLES_PER_FRAME, PlayStation};
+use crate::state::{self, FromState, Snapshot, StateReader, StateWriter};
+
+// Input movie layout, little endian, built from the save state pieces:
+//   "PSXMOVIE", u32 version
+//   BIOS hash, disc hash, executable hash (u64, the last two optional)
+//   memory card hashes for both slots (optional u64, none when the slot is empty)
+//   initial save state (optional, none means power on), end cycle, final state hash
+//   input count, then per frame both pads: buttons u16, left stick x y, right stick x y
+pub const MAGIC: &[u8; 8] = b"PSXMOVIE";
+pub const VERSION: u32 = 2;
+
+const FRAME: u64 = CYCLES_PER_FRAME as u64;
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Pad {
+    // a combination of the controller button constants
+    pub buttons: u16,
+    pub left: (u8, u8),
+    pub right: (u8, u8),
+}
+
+impl Pad {
+    pub fn new() -> Pad {
+        Pad { buttons: 0, left: (0x80, 0x80), right: (0x80, 0x80) }
+    }
+}
+
+impl Default for Pad {
+    fn default() -> Pad {
+        Pad::new()
+    }
+}
+
+crate::state::snapshot!(Pad { buttons, left, right });
+
+// what both controller ports held during a frame
+#[derive(Copy, Clone, PartialEq, Debug, Default)]
+pub struct Input {
+    pub pads: [Pad; 2],
+}
+
+crate::state::snapshot!(Input { pads });
+
+impl Input {
+    pub fn apply(&self, ps: &mut PlayStation) {
+        for (port, pad) in self.pads.iter().enumerate() {
+            ps.sio0.set_buttons(port, pad.buttons);
+            ps.sio0.set_sticks(port, pad.left, pad.right);
+        }
+    }
+}
+
+// The input of a run, replayed from the same start it ends in the same state.
+// inputs[i] is applied when the frame first_frame() + i starts, frames start on
+// multiples of CYCLES_PER_FRAME since power on.
+pub struct Movie {
+    pub bios: u64,
+    pub disc: Option<u64>,
+    pub executable: Option<u64>,
+    // a power on movie depends on what was on the cards, a state already holds them
+    pub cards: [Option<u64>; 2],
+    pub initial_state: Option<Vec<u8>>,
+    pub inputs: Vec<Input>,
+    // cycle count and state hash where the recording stopped
+    pub end_cycle: u64,
+    pub final_state: u64,
+}
+
+impl Movie {
+    // starts recording from the machine as it is, power on if it hasn't run yet.
+    // the executable is the one waiting to be sideloaded and the disc the hash_file of its image
+    pub fn new(ps: &PlayStation, executable: Option<&[u8]>, disc: Option<u64>) -> Movie {
+        Movie {
+            bios: state::hash(&ps.bios.data),
+            disc,
+            executable: executable.map(state::hash),
+            cards: card_hashes(ps),
+            initial_state: if ps.cycles == 0 { None } else { Some(ps.save_state()) },
+            inputs: Vec::new(),
+            end_cycle: ps.cycles,
+            final_state: 0,
+        }
+    }
+
+    pub fn start_cycle(&self) -> u64 {
+        match &self.initial_state {
+            Some(data) => state_cycles(data),
+            None => 0,
+        }
+    }
+
+    // the first frame that starts after the movie does, or with it
+    pub fn first_frame(&self) -> u64 {
+        self.start_cycle().div_ceil(FRAME)
+    }
+
+    // called by recording frontends when a frame starts, applies the input and keeps it
+    pub fn record(&mut self, ps: &mut PlayStation, input: Input) {
+        input.apply(ps);
+        self.inputs.push(input);
+    }
+
+    // the machine went back in time, drops the input of the frames that will be played again.
+    // false when it went back before the movie started
+    pub fn truncate(&mut self, cycle: u64) -> bool {
+        if cycle < self.start_cycle() {
+            return false;
+        }
+        let frames = cycle.div_ceil(FRAME).saturating_sub(self.first_frame());
+        self.inputs.truncate(frames as usize);
+        true
+    }
+
+    pub fn finish(&mut self, ps: &PlayStation) {
+        self.end_cycle = ps.cycles;
+        self.final_state = state::hash(&ps.save_state());
+    }
+
+    // the machine must be fresh with the recorded executable sideloaded, if there was one
+    pub fn check(
+        &self,
+        ps: &PlayStation,
+        executable: Option<&[u8]>,
+        disc: Option<u64>,
+    ) -> Result<(), String> {
+        if self.bios != state::hash(&ps.bios.data) {
+            return Err("the movie was recorded with a different BIOS".to_string());
+        }
+        if self.disc != disc {
+            return Err("the movie was recorded with a different disc".to_string());
+        }
+        // a state has the executable in RAM and the cards' contents already
+        if self.initial_state.is_some() {
+            return Ok(());
+        }
+        if self.executable != executable.map(state::hash) {
+            return Err("the movie was recorded with a different executable".to_string());
+        }
+        for (slot, (recorded, inserted)) in self.cards.iter().zip(card_hashes(ps)).enumerate() {
+            if *recorded != inserted {
+                // the card file keeps whatever the game saved while recording
+                return Err(format!(
+                    "the movie was recorded with a different memory card in slot {}, replay \
+                     with a copy of the card from before the recording",
+                    slot + 1
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    // replays the whole movie, Err if it doesn't end in the recorded state
+    pub fn play(&self, ps: &mut PlayStation) -> Result<(), String> {
+        if let Some(data) = &self.initial_state {
+            ps.load_state(data)?;
+        }
+
+        let first_frame = self.first_frame();
+        while ps.cycles < self.end_cycle {
+            if ps.cycles.is_multiple_of(FRAME) {
+                let frame = ps.cycles / FRAME;
+                if let Some(input) = self.inputs.get((frame - first_frame) as usize) {
+                    input.apply(ps);
+                }
+            }
+            // whole frames while they fit, the end can be in the middle of one
+            if ps.cycles.is_multiple_of(FRAME) && ps.cycles + FRAME <= self.end_cycle {
+                ps.run_next_frame();
+            } else {
+                run_instruction(ps);
+            }
+        }
+
+        let hash = state::hash(&ps.save_state());
+        if ps.cycles != self.end_cycle || hash != self.final_state {
+            return Err(format!(
+                "desynced: ended at cycle {} with state {:016x}, the recording ended at {} with {:016x}",
+                ps.cycles, hash, self.end_cycle, self.final_state
+            ));
+        }
+        Ok(())
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let mut movie = StateWriter::raw();
+        movie.write_bytes(MAGIC);
+        VERSION.save(&mut movie);
+        self.bios.save(&mut movie);
+        self.disc.save(&mut movie);
+        self.executable.save(&mut movie);
+        self.cards.save(&mut movie);
+        self.initial_state.is_some().save(&mut movie);
+        if let Some(data) = &self.initial_state {
+            data.len().save(&mut movie);
+            movie.write_bytes(data);
+        }
+        self.end_cycle.save(&mut movie);
+        self.final_state.save(&mut movie);
+        self.inputs.save(&mut movie);
+        fs::write(path, movie.finish())
+    }
+
+    pub fn open(path: &Path) -> Result<Movie, String> {
+        let data = fs::read(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        Movie::parse(&data).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    pub fn parse(data: &[u8]) -> Result<Movie, String> {
+        let mut movie = StateReader::new(data);
+        if movie.read_bytes(MAGIC.len()).ok() != Some(&MAGIC[..]) {
+            return Err("not an input movie".to_string());
+        }
+        let version = u32::read(&mut movie)?;
+        if version != VERSION {
+            return Err(format!(
+                "movie version {} is not supported, expected {}",
+                version, VERSION
+            ));
+        }
+        let bios = u64::read(&mut movie)?;
+        let disc = Option::read(&mut movie)?;
+        let executable = Option::read(&mut movie)?;
+        let cards = <[Option<u64>; 2]>::read(&mut movie)?;
+        let initial_state = match bool::read(&mut movie)? {
+            true => {
+                let length = usize::read(&mut movie)?;
+                Some(movie.read_bytes(length)?.to_vec())
+            }
+            false => None,
+        };
+        let end_cycle = u64::read(&mut movie)?;
+        let final_state = u64::read(&mut movie)?;
+        let inputs = Vec::read(&mut movie)?;
+        movie.finish()?;
+        Ok(Movie { bios, disc, executable, cards, initial_state, inputs, end_cycle, final_state })
+    }
+}
+
+// the state of whatever sits in each memory card slot, the card's data and its FLAG
+fn card_hashes(ps: &PlayStation) -> [Option<u64>; 2] {
+    ps.sio0.ports.each_ref().map(|port| {
+        port.memory_card.as_ref().map(|card| {
+            let mut state = StateWriter::raw();
+            card.save(&mut state);
+            state::hash(&state.finish())
+        })
+    })
+}
+
+// the cycle count of a save state, without loading it
+fn state_cycles(data: &[u8]) -> u64 {
+    let Ok(mut state) = StateReader::open(data) else {
+        return 0;
+    };
+    while let Ok((tag, mut section)) = state.next_section() {
+        if &tag == b"CLK " {
+            return u64::read(&mut section).unwrap_or(0);
+        }
+    }
+    0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::map::BIOS_SIZE;
+    use crate::sio::memory_card::MemoryCard;
+
+    // counts up a word in RAM forever, like the state tests' machine
+    fn machine() -> PlayStation {
+        let mut bios = vec![0; BIOS_SIZE as usize];
+        let program: [u32; 4] = [0x25080001, 0xac080100, 0x0bf00000, 0];
+        for (i, word) in program.iter().enumerate() {
+            bios[i * 4..i * 4 + 4].copy_from_slice(&word.to_le_bytes());
+        }
+        PlayStation::new(bios.into_boxed_slice())
+    }
+
+    fn input(buttons: u16) -> Input {
+        let mut input = Input::default();
+        input.pads[0].buttons = buttons;
+        input.pads[1].right = (0x10, 0xf0);
+        input
+    }
+
+    // three frames with a different button each
+    fn record(ps: &mut PlayStation) -> Movie {
+        let mut movie = Movie::new(ps, Some(b"exe"), Some(7));
+        while movie.inputs.len() < 3 || !ps.cycles.is_multiple_of(FRAME) {
+            if ps.cycles.is_multiple_of(FRAME) {
+                let buttons = 1 << movie.inputs.len();
+                movie.record(ps, input(buttons));
+            }
+            run_instruction(ps);
+        }
+        movie.finish(ps);
+        movie
+    }
+
+    #[test]
+    fn save_parse_round_trip() {
+        let mut ps = machine();
+        ps.sio0.insert_memory_card(1, Some(Box::new(MemoryCard::new())));
+        let mut movie = record(&mut ps);
+        movie.initial_state = Some(vec![1, 2, 3]);
+
+        let path = std::env::temp_dir().join(format!("ps-movie-{}", std::process::id()));
+        movie.save(&path).unwrap();
+        let parsed = Movie::open(&path);
+        let data = fs::read(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        let parsed = parsed.unwrap();
+
+        assert_eq!(parsed.bios, movie.bios);
+        assert_eq!(parsed.disc, Some(7));
+        assert_eq!(parsed.executable, movie.executable);
+        assert_eq!(parsed.cards, movie.cards);
+        assert!(parsed.cards[0].is_none() && parsed.cards[1].is_some());
+        assert_eq!(parsed.initial_state, Some(vec![1, 2, 3]));
+        assert_eq!(parsed.inputs, movie.inputs);
+        assert_eq!(parsed.end_cycle, 3 * FRAME);
+        assert_eq!(parsed.final_state, movie.final_state);
+
+        assert!(Movie::parse(&data[..data.len() - 1]).is_err());
+        assert!(Movie::parse(b"PSXMOVIE").is_err());
+        assert!(Movie::parse(&data[1..]).is_err());
+    }
+
+    #[test]
+    fn power_on_record_and_play() {
+        let mut ps = machine();
+        ps.sio0.insert_memory_card(0, Some(Box::new(MemoryCard::new())));
+        let movie = record(&mut ps);
+        assert!(movie.initial_state.is_none());
+
+        let mut replay = machine();
+        replay.sio0.insert_memory_card(0, Some(Box::new(MemoryCard::new())));
+        movie.check(&replay, Some(b"exe"), Some(7)).unwrap();
+        movie.play(&mut replay).unwrap();
+        assert_eq!(replay.save_state(), ps.save_state());
+
+        let fresh = machine();
+        assert!(movie.check(&fresh, Some(b"exe"), Some(7)).is_err());
+        let mut other_card = machine();
+        other_card.sio0.insert_memory_card(0, Some(Box::new(MemoryCard::new())));
+        other_card.sio0.insert_memory_card(1, Some(Box::new(MemoryCard::new())));
+        assert!(movie.check(&other_card, Some(b"exe"), Some(7)).is_err());
+        assert!(movie.check(&replay, Some(b"other"), Some(7)).is_err());
+        assert!(movie.check(&replay, Some(b"exe"), None).is_err());
+    }
+
+    #[test]
+    fn record_from_a_state_and_play() {
+        let mut ps = machine();
+        for _ in 0..1000 {
+            run_instruction(&mut ps);
+        }
+        let mut movie = record(&mut ps);
+        assert_eq!(movie.start_cycle(), 1000);
+        assert_eq!(movie.first_frame(), 1);
+
+        let mut replay = machine();
+        movie.check(&replay, None, Some(7)).unwrap();
+        movie.play(&mut replay).unwrap();
+        assert_eq!(replay.save_state(), ps.save_state());
+
+        // nothing reads the pad, only the last buttons end up in the state
+        movie.inputs[2].pads[0].buttons = 0;
+        let error = movie.play(&mut machine()).unwrap_err();
+        assert!(error.starts_with("desynced"), "{}", error);
+    }
+}
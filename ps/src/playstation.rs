@@ -1,13 +1,37 @@
 use core::panic;
+use std::any::Any;
+use std::cell::RefCell;
+use std::fmt;
 
 use crate::dma::DMA;
+use crate::exe::Executable;
+use crate::exe::symbols::SymbolTable;
+use crate::kernel::log::KernelLog;
+use crate::state::rewind::RewindBuffer;
+use crate::state::{self, FromState, Snapshot, StateReader, StateWriter};
+use crate::trace::BusAccess;
 use crate::{
-    bios::BIOS, cpu::mipsr3000, expansion_region2::Expansion_Region_2, gpu::GPU,
-    irq::IRQController, map, ram::Ram, spu::SPU,
+    bios::BIOS,
+    cpu::mipsr3000,
+    expansion_region2::{self, Expansion_Region_2},
+    gpu::GPU,
+    irq::{IRQController, Interrupt},
+    map,
+    mdec::MDEC,
+    ram::Ram,
+    sio::SIO0,
+    spu::SPU,
+    tty::Tty,
 };
 
 pub const CYCLES_PER_FRAME: usize = 564480;
 
+// sections a save state must have, anything else in it is skipped
+const STATE_SECTIONS: [&[u8; 4]; 11] = [
+    b"BIOS", b"CLK ", b"CPU ", b"RAM ", b"BUS ", b"DMA ", b"IRQ ", b"GPU ", b"SPU ", b"SIO0",
+    b"MDEC",
+];
+
 /*pub trait Addressable {
     fn read8(&self, data: &Box<[u8]>, addr: u32) -> u8 {
         let arr: [u8; 1] = [data[addr as usize]; 1];
@@ -42,8 +66,25 @@ pub struct PlayStation {
     pub gpu: GPU,
     pub spu: SPU,
     pub irq: IRQController,
+    // controllers and memory cards
+    pub sio0: SIO0,
+    // macroblock decoder for FMVs
+    pub mdec: MDEC,
+    // executable waiting for the BIOS to reach the shell entry point
+    pub sideload: Option<Executable>,
+    // names for addresses, filled from the sideloaded executable
+    pub symbols: SymbolTable,
+    // CPU cycles since power on
+    pub cycles: u64,
+    // a state per frame to go back to, None unless enabled
+    pub rewind: Option<RewindBuffer>,
+    // the bus accesses of the instruction being traced, None unless tracing
+    pub bus_log: Option<RefCell<Vec<BusAccess>>>,
+    // what the BIOS and programs printed
+    pub tty: Tty,
+    // the BIOS calls made, None unless enabled
+    pub kernel_log: Option<KernelLog>,
     //cdrom: CDROM
-    //mdec: MDEC,
     //gpu: Gpu,
     //irq
     //dma
@@ -64,16 +105,180 @@ impl PlayStation {
             //exp1: Expansion_Region::new(),
             spu: SPU::new(),
             irq: IRQController::new(),
+            sio0: SIO0::new(),
+            mdec: MDEC::new(),
+            sideload: None,
+            symbols: SymbolTable::new(),
+            cycles: 0,
+            rewind: None,
+            bus_log: None,
+            tty: Tty::new(),
+            kernel_log: None,
         }
     }
+
+    // boots the BIOS as usual but runs the executable instead of the shell
+    pub fn sideload(&mut self, mut exe: Executable) {
+        self.symbols = std::mem::take(&mut exe.symbols);
+        self.sideload = Some(exe);
+    }
+    // runs to the start of the next frame. frames start on multiples of CYCLES_PER_FRAME since
+    // power on, so a run picked up from a state saved mid-frame keeps the frames of the original
     pub fn run_next_frame(&mut self) {
-        let mut total_cycles: usize = 0;
+        self.checkpoint();
+        let end = (self.cycles / CYCLES_PER_FRAME as u64 + 1) * CYCLES_PER_FRAME as u64;
+        while self.cycles < end {
+            mipsr3000::run_instruction(self);
+        }
+    }
+
+    // steps the devices that run off the CPU clock
+    pub fn tick(&mut self, cycles: usize) {
+        self.cycles += cycles as u64;
+        self.spu.tick(cycles);
+        if self.spu.take_interrupt() {
+            self.irq.request(Interrupt::SPU);
+        }
+        self.sio0.tick(cycles);
+        if self.sio0.take_interrupt() {
+            self.irq.request(Interrupt::ControllerMemCard);
+        }
+    }
+
+    // Everything the emulated machine is made of, see state/mod.rs for the layout.
+    // the BIOS is only identified by its hash, the executable and symbols are left out.
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut state = StateWriter::new();
+        state.section(b"BIOS", |s| state::hash(&self.bios.data).save(s));
+        state.section(b"CLK ", |s| self.cycles.save(s));
+        state.section(b"CPU ", |s| self.cpu.save(s));
+        state.section(b"RAM ", |s| self.ram.save(s));
+        state.section(b"BUS ", |s| {
+            self.mem_ctrl.save(s);
+            self.mem_ctrl_2.save(s);
+            self.cache_ctrl.save(s);
+            self.exp2.save(s);
+        });
+        state.section(b"DMA ", |s| self.dma.save(s));
+        state.section(b"IRQ ", |s| self.irq.save(s));
+        state.section(b"GPU ", |s| self.gpu.save(s));
+        state.section(b"SPU ", |s| self.spu.save(s));
+        state.section(b"SIO0", |s| self.sio0.save(s));
+        state.section(b"MDEC", |s| self.mdec.save(s));
+        state.finish()
+    }
 
-        while total_cycles < CYCLES_PER_FRAME {
-            let cycles: usize = mipsr3000::run_cycle(self);
-            // assume every instruction takes one cycle, I don't know if its accurate, will fix later.
-            total_cycles += cycles;
+    // a state that fails to load leaves the machine as it was
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), String> {
+        let backup = self.save_state();
+        self.apply_state(data).inspect_err(|_| {
+            self.apply_state(&backup).expect("the machine's own state loads");
+        })?;
+
+        // a pending sideload would restart the executable once the BIOS reaches the shell again
+        self.sideload = None;
+        self.spu.take_output();
+        // a puts in progress belongs to the run that was left
+        self.tty.muted_until = None;
+        if let Some(log) = &mut self.kernel_log {
+            log.forget_pending();
+        }
+        // the states to rewind to belong to the run that was left
+        if let Some(rewind) = &mut self.rewind {
+            rewind.clear();
         }
+        Ok(())
+    }
+
+    fn apply_state(&mut self, data: &[u8]) -> Result<(), String> {
+        let mut state = StateReader::open(data)?;
+        let mut missing = STATE_SECTIONS.to_vec();
+        while !state.is_empty() {
+            let (tag, mut section) = state.next_section()?;
+            missing.retain(|&expected| *expected != tag);
+            let s = &mut section;
+            let result = match &tag {
+                b"BIOS" if u64::read(s)? != state::hash(&self.bios.data) => {
+                    Err("the state was saved with a different BIOS".to_string())
+                }
+                b"BIOS" => Ok(()),
+                b"CLK " => self.cycles.load(s),
+                b"CPU " => self.cpu.load(s),
+                b"RAM " => self.ram.load(s),
+                b"BUS " => (|| {
+                    self.mem_ctrl.load(s)?;
+                    self.mem_ctrl_2.load(s)?;
+                    self.cache_ctrl.load(s)?;
+                    self.exp2.load(s)
+                })(),
+                b"DMA " => self.dma.load(s),
+                b"IRQ " => self.irq.load(s),
+                b"GPU " => self.gpu.load(s),
+                b"SPU " => self.spu.load(s),
+                b"SIO0" => self.sio0.load(s),
+                b"MDEC" => self.mdec.load(s),
+                _ => continue,
+            };
+            let name = String::from_utf8_lossy(&tag).trim_end().to_string();
+            result
+                .and_then(|_| section.finish())
+                .map_err(|e| format!("{} section: {}", name, e))?;
+        }
+
+        match missing.first() {
+            Some(tag) => Err(format!(
+                "the state has no {} section",
+                String::from_utf8_lossy(*tag).trim_end()
+            )),
+            None => Ok(()),
+        }
+    }
+
+    // budget in bytes for the states kept to rewind, they are taken once a frame
+    pub fn enable_rewind(&mut self, budget: usize) {
+        self.rewind = Some(RewindBuffer::new(budget));
+    }
+
+    // takes a rewind state if a frame's worth of cycles passed since the last one,
+    // frontends that don't go through run_next_frame call it before every frame or step
+    pub fn checkpoint(&mut self) {
+        let Some(rewind) = &self.rewind else {
+            return;
+        };
+        if rewind.newest_cycle().is_none_or(|cycle| self.cycles >= cycle + CYCLES_PER_FRAME as u64)
+        {
+            let state = self.save_state();
+            self.rewind.as_mut().unwrap().push(&state, self.cycles);
+        }
+    }
+
+    // back to the start of the frame `frames` frames ago, 1 being the current one
+    pub fn rewind(&mut self, frames: usize) -> Result<(), String> {
+        let state = self.rewind.as_mut().and_then(|rewind| rewind.rewind(frames));
+        self.restore(state)
+    }
+
+    // back to the newest rewind state taken at or before the cycle, the caller runs forward from there
+    pub fn rewind_to(&mut self, cycle: u64) -> Result<(), String> {
+        let state = self.rewind.as_mut().and_then(|rewind| rewind.rewind_to(cycle));
+        self.restore(state)
+    }
+
+    fn restore(&mut self, state: Option<Vec<u8>>) -> Result<(), String> {
+        let state = state.ok_or("no rewind state that far back")?;
+        // the same run, an executable still waiting for the shell keeps waiting
+        // and the older states stay around
+        let sideload = self.sideload.take();
+        let rewind = self.rewind.take();
+        let result = self.load_state(&state);
+        self.sideload = sideload;
+        self.rewind = rewind;
+        result
+    }
+
+    // the SPU mix (voices, reverb and CD input) produced since the last call, 44.1 kHz stereo
+    pub fn take_audio(&mut self) -> Vec<(i16, i16)> {
+        self.spu.take_output()
     }
 
     pub fn run(&mut self) {
@@ -82,53 +287,105 @@ impl PlayStation {
         }
     }
 
-    pub fn read8(&self, address: u32) -> u8 {
+    fn log_access(&self, access: BusAccess) {
+        if let Some(log) = &self.bus_log {
+            log.borrow_mut().push(access);
+        }
+    }
+
+    // CPU reads, registers like JOY_DATA and MDEC0 hand out the next value of their FIFO
+    pub fn read8(&mut self, address: u32) -> u8 {
         use map::*;
         let phys_address = mask_region(address);
-        match phys_address {
+        let byte = match phys_address {
+            SIO0_START..=SIO0_END => self.sio0.read(phys_address) as u8,
+            _ => self
+                .peek8(address)
+                .unwrap_or_else(|| BusError::raise(self.cpu.pc, address, "read8")),
+        };
+        self.log_access(BusAccess { address, value: byte as u32, size: 1, write: false });
+        byte
+    }
+
+    pub fn read16(&mut self, address: u32) -> u16 {
+        use map::*;
+        let phys_address = mask_region(address);
+        let halfword = match phys_address {
+            SIO0_START..=SIO0_END => self.sio0.read(phys_address) as u16,
+            IRQ_STATUS_REG => {
+                println!("IRQ Status read16 at pc: {:08x} address {:08x}", self.cpu.pc, address);
+                0
+            }
+            IRQ_MASK_REG => {
+                println!("IRQ Status read16 at pc: {:08x} address {:08x}", self.cpu.pc, address);
+                0
+            }
+            _ => self
+                .peek16(address)
+                .unwrap_or_else(|| BusError::raise(self.cpu.pc, address, "read16")),
+        };
+        self.log_access(BusAccess { address, value: halfword as u32, size: 2, write: false });
+        halfword
+    }
+
+    pub fn read32(&mut self, address: u32) -> u32 {
+        use map::*;
+        let phys_address = mask_region(address);
+        let result = match phys_address {
+            SIO0_START..=SIO0_END => self.sio0.read(phys_address),
+            MDEC_START..=MDEC_END => self.mdec.read32(phys_address),
+            _ => self
+                .peek32(address)
+                .unwrap_or_else(|| BusError::raise(self.cpu.pc, address, "read32")),
+        };
+        self.log_access(BusAccess { address, value: result, size: 4, write: false });
+        result
+    }
+
+    // what a read would return without taking anything out of a FIFO or logging the access, for
+    // the debugger's views. None when nothing is mapped there
+    pub fn peek8(&self, address: u32) -> Option<u8> {
+        use map::*;
+        let phys_address = mask_region(address);
+        let byte = match phys_address {
             RAM_START..=RAM_END => self.ram.read8(phys_address),
             BIOS_START..=BIOS_END => self.bios.read8(phys_address),
+            SIO0_START..=SIO0_END => self.sio0.peek(phys_address) as u8,
+            EXPANSION_REGION_2_START..=EXPANSION_REGION_2_END => self.exp2.read_byte(phys_address),
             /*EXPANSION_REGION_1_START..=EXPANSION_REGION_1_END => {
                 panic!("Unimplemeted Exp1 Access");
                 0xff
             }*/
             0x1f000084 => 0x00,
-            _ => panic!(
-                "unimplemented Bus addressing at pc read8: {:08x} {:08x}",
-                self.cpu.pc, address
-            ),
-        }
+            _ => return None,
+        };
+        Some(byte)
     }
 
-    pub fn read16(&self, address: u32) -> u16 {
+    pub fn peek16(&self, address: u32) -> Option<u16> {
         use map::*;
         let phys_address = mask_region(address);
-        match phys_address {
+        let halfword = match phys_address {
             RAM_START..=RAM_END => self.ram.read16(phys_address),
             SPU_START..=SPU_END => self.spu.read_halfword(phys_address),
-            IRQ_STATUS_REG => {
-                println!("IRQ Status read16 at pc: {:08x} address {:08x}", self.cpu.pc, address);
-                return 0;
-            }
-            IRQ_MASK_REG => {
-                println!("IRQ Status read16 at pc: {:08x} address {:08x}", self.cpu.pc, address);
-                return 0;
-            }
+            SIO0_START..=SIO0_END => self.sio0.peek(phys_address) as u16,
+            IRQ_STATUS_REG | IRQ_MASK_REG => 0,
             // Other cases...
-            _ => panic!(
-                "unimplemented Bus addressing at pc read16: {:08x} {:08x}",
-                self.cpu.pc, address
-            ),
-        }
+            _ => return None,
+        };
+        Some(halfword)
     }
-    pub fn read32(&self, address: u32) -> u32 {
+
+    pub fn peek32(&self, address: u32) -> Option<u32> {
         use map::*;
         let phys_address = mask_region(address);
-        let result = match phys_address {
+        let word = match phys_address {
             RAM_START..=RAM_END => self.ram.read32(phys_address),
             BIOS_START..=BIOS_END => self.bios.read32(phys_address),
             IRQ_STATUS_REG => self.irq.get_status(),
             IRQ_MASK_REG => self.irq.get_mask(),
+            SIO0_START..=SIO0_END => self.sio0.peek(phys_address),
+            MDEC_START..=MDEC_END => self.mdec.peek32(phys_address),
             DMA_REGISTERS_START..=DMA_REGISTERS_END => {
                 //println!("DMA read32 at pc {:08x}, address {:08x}", self.cpu.pc, phys_address);
                 unsafe { self.dma.read32(phys_address) }
@@ -139,41 +396,40 @@ impl PlayStation {
             }
 
             // Other cases...
-            _ => panic!(
-                "unimplemented Bus addressing at pc read32: {:08x} {:08x}",
-                self.cpu.pc, address
-            ),
+            _ => return None,
         };
-
-        result
+        Some(word)
     }
     pub fn write8(&mut self, address: u32, byte: u8) {
         use map::*;
+        self.log_access(BusAccess { address, value: byte as u32, size: 1, write: true });
         let phys_address = mask_region(address);
         match phys_address {
             0x00000500 => panic!("at pc {}", self.cpu.pc),
             RAM_START..=RAM_END => self.ram.write8(phys_address, byte),
 
             SPU_START..=SPU_END => self.spu.write_byte(address, byte),
+            SIO0_START..=SIO0_END => self.sio0.write(phys_address, byte as u32),
 
-            /*EXPANSION_REGION_2_START..=EXPANSION_REGION_2_END => {
-                println!("EXPANSION_REGION_2 Write");
+            EXPANSION_REGION_2_START..=EXPANSION_REGION_2_END => {
+                if phys_address == expansion_region2::DUART_TX_A {
+                    self.tty.push(byte);
+                }
                 self.exp2.write_byte(phys_address, byte);
-            }*/
-            _ => panic!(
-                "unimplemented Bus addressing at pc write8: {:08x} {:08x}",
-                self.cpu.pc, address
-            ),
+            }
+            _ => BusError::raise(self.cpu.pc, address, "write8"),
         }
     }
 
     pub fn write16(&mut self, halfword: u16, address: u32) {
         use map::*;
+        self.log_access(BusAccess { address, value: halfword as u32, size: 2, write: true });
         let phys_address = mask_region(address);
         match phys_address {
             RAM_START..=RAM_END => self.ram.write16(phys_address, halfword),
 
             SPU_START..=SPU_END => self.spu.write_halfword(phys_address, halfword),
+            SIO0_START..=SIO0_END => self.sio0.write(phys_address, halfword as u32),
 
             TIMERS_START..=TIMERS_END => println!(
                 "Timers Write16, at pc: {:08x} address {:08x} value {:08x}",
@@ -189,15 +445,13 @@ impl PlayStation {
                 "IRQ Mask Write16, at pc: {:08x} address {:08x} value {:08x}",
                 self.cpu.pc, address, halfword
             ),
-            _ => panic!(
-                "unimplemented Bus addressing at pc write16: {:08x} {:08x}",
-                self.cpu.pc, address
-            ),
+            _ => BusError::raise(self.cpu.pc, address, "write16"),
         }
     }
 
     pub fn write32(&mut self, word: u32, address: u32) {
         use map::*;
+        self.log_access(BusAccess { address, value: word, size: 4, write: true });
         let phys_address = mask_region(address);
 
         match phys_address {
@@ -217,11 +471,20 @@ impl PlayStation {
             CACHE_CONTROL_START => self.cache_ctrl = word,
             IRQ_MASK_REG => self.irq.set_mask(word),
             IRQ_STATUS_REG => self.irq.acknowledge(word),
+            SIO0_START..=SIO0_END => self.sio0.write(phys_address, word),
+            MDEC_START..=MDEC_END => {
+                self.mdec.write32(phys_address, word);
+                crate::dma::run_transfers(self);
+            }
 
             DMA_REGISTERS_START..=DMA_REGISTERS_END => {
-                println!("DMA write32 at pc {:08x}, address {:08x} value: {:08x}", self.cpu.pc, phys_address, word);
+                println!(
+                    "DMA write32 at pc {:08x}, address {:08x} value: {:08x}",
+                    self.cpu.pc, phys_address, word
+                );
                 self.dma.write32(phys_address, word);
-            },
+                crate::dma::run_transfers(self);
+            }
             GPU_REGISTERS_START..=GPU_REGISTERS_END => println!(
                 "GPU write32 at pc {:08x}, address {:08x} value: {:08x}",
                 self.cpu.pc, phys_address, word
@@ -232,12 +495,7 @@ impl PlayStation {
             ),
 
             // Other cases...
-            _ => {
-                panic!(
-                    "unimplemented Bus addressing at pc write32: {:08x} {:08x}",
-                    self.cpu.pc, address
-                )
-            }
+            _ => BusError::raise(self.cpu.pc, address, "write32"),
         }
     }
 }
@@ -249,6 +507,57 @@ const REGION_MASK: [u32; 8] = [
     0x1fffffff, // KSEG2
     0xffffffff, 0xffffffff,
 ];
+// What the bus panics with when nothing answers at an address, so frontends can tell these
+// apart from other panics
+#[derive(Debug)]
+pub struct BusError {
+    pub pc: u32,
+    pub address: u32,
+    // read8, write32...
+    pub access: &'static str,
+}
+
+impl BusError {
+    #[cold]
+    #[track_caller]
+    fn raise(pc: u32, address: u32, access: &'static str) -> ! {
+        std::panic::panic_any(BusError { pc, address, access })
+    }
+}
+
+impl fmt::Display for BusError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "unimplemented Bus addressing at pc {}: {:08x} {:08x}",
+            self.access, self.pc, self.address
+        )
+    }
+}
+
+// the text of a caught panic, bus errors included
+pub fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(error) = payload.downcast_ref::<BusError>() {
+        error.to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        payload.downcast_ref::<&str>().map_or("?".to_string(), |message| message.to_string())
+    }
+}
+
+// the default panic hook prints "Box<dyn Any>" for payloads that aren't strings
+pub fn install_panic_hook() {
+    let default = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| match info.payload().downcast_ref::<BusError>() {
+        Some(error) => match info.location() {
+            Some(location) => eprintln!("panicked at {}:\n{}", location, error),
+            None => eprintln!("{}", error),
+        },
+        None => default(info),
+    }));
+}
+
 pub fn mask_region(addr: u32) -> u32 {
     // Index address space in 512MB chunks
     let index = (addr >> 29) as usize;
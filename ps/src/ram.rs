@@ -21,6 +21,8 @@ pub struct Ram {
     }
 }*/
 //impl Addressable for Ram {}
+crate::state::snapshot!(Ram { data });
+
 impl Ram {
     pub fn new() -> Ram {
         // Use the actual size you want (2MB standard, 8MB for expanded)
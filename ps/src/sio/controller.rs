@@ -0,0 +1,295 @@
+use super::SioDevice;
+
+// Pad commands, after the 0x01 address byte:
+//   byte 1: command / controller ID (0x41 digital, 0x73 analog, 0xF3 config mode)
+//   byte 2: 0x00 / 0x5A
+//   byte 3..: parameters / buttons (active low) then the analog sticks RX, RY, LX, LY
+
+// pressed button bits for set_buttons, in wire order
+pub mod button {
+    pub const SELECT: u16 = 1 << 0;
+    pub const L3: u16 = 1 << 1;
+    pub const R3: u16 = 1 << 2;
+    pub const START: u16 = 1 << 3;
+    pub const UP: u16 = 1 << 4;
+    pub const RIGHT: u16 = 1 << 5;
+    pub const DOWN: u16 = 1 << 6;
+    pub const LEFT: u16 = 1 << 7;
+    pub const L2: u16 = 1 << 8;
+    pub const R2: u16 = 1 << 9;
+    pub const L1: u16 = 1 << 10;
+    pub const R1: u16 = 1 << 11;
+    pub const TRIANGLE: u16 = 1 << 12;
+    pub const CIRCLE: u16 = 1 << 13;
+    pub const CROSS: u16 = 1 << 14;
+    pub const SQUARE: u16 = 1 << 15;
+}
+
+const ID_DIGITAL: u8 = 0x41;
+const ID_ANALOG: u8 = 0x73;
+const ID_CONFIG: u8 = 0xF3;
+
+const CMD_POLL: u8 = 0x42;
+const CMD_CONFIG: u8 = 0x43;
+const CMD_SET_MODE: u8 = 0x44;
+const CMD_GET_STATUS: u8 = 0x45;
+const CMD_ACTUATOR_INFO: u8 = 0x46;
+const CMD_COMBINATION_INFO: u8 = 0x47;
+const CMD_MODE_INFO: u8 = 0x4C;
+const CMD_RUMBLE_MAPPING: u8 = 0x4D;
+
+pub trait Controller: SioDevice {
+    // pressed buttons, a combination of the button constants
+    fn set_buttons(&mut self, buttons: u16);
+    // left and right stick (x, y), centered at 0x80 with 0x00 up/left, ignored by pads without sticks
+    fn set_sticks(&mut self, _left: (u8, u8), _right: (u8, u8)) {}
+    // small (on/off) and large motor levels the game asked for
+    fn rumble(&self) -> (u8, u8) {
+        (0, 0)
+    }
+}
+
+// SCPH-1080, only answers the poll command
+pub struct DigitalPad {
+    buttons: u16,
+    position: usize,
+}
+
+impl DigitalPad {
+    pub fn new() -> DigitalPad {
+        DigitalPad { buttons: 0, position: 0 }
+    }
+}
+
+impl Default for DigitalPad {
+    fn default() -> DigitalPad {
+        DigitalPad::new()
+    }
+}
+
+crate::state::snapshot!(DigitalPad { buttons, position });
+
+impl SioDevice for DigitalPad {
+    fn name(&self) -> &'static str {
+        "a digital pad"
+    }
+
+    fn reset(&mut self) {
+        self.position = 0;
+    }
+
+    fn exchange(&mut self, byte: u8) -> (u8, bool) {
+        let position = self.position;
+        self.position += 1;
+        match position {
+            0 => (0xff, true),
+            1 if byte == CMD_POLL => (ID_DIGITAL, true),
+            1 => (0xff, false),
+            2 => (0x5a, true),
+            3 => (!self.buttons as u8, true),
+            4 => ((!self.buttons >> 8) as u8, false),
+            _ => (0xff, false),
+        }
+    }
+}
+
+impl Controller for DigitalPad {
+    fn set_buttons(&mut self, buttons: u16) {
+        self.buttons = buttons;
+    }
+}
+
+// SCPH-1200, digital or analog mode plus the config mode games use to lock the mode and map the motors
+pub struct DualShock {
+    buttons: u16,
+    // RX, RY, LX, LY like on the wire
+    sticks: [u8; 4],
+    analog: bool,
+    // the analog button is ignored while the game locked the mode
+    locked: bool,
+    config: bool,
+    // which poll parameter drives which motor: 0x00 small, 0x01 large, 0xFF none
+    rumble_mapping: [u8; 6],
+    motors: (u8, u8),
+    command: u8,
+    position: usize,
+    params: [u8; 6],
+}
+
+impl DualShock {
+    pub fn new() -> DualShock {
+        DualShock {
+            buttons: 0,
+            sticks: [0x80; 4],
+            analog: false,
+            locked: false,
+            config: false,
+            rumble_mapping: [0xff; 6],
+            motors: (0, 0),
+            command: 0,
+            position: 0,
+            params: [0; 6],
+        }
+    }
+
+    pub fn is_analog(&self) -> bool {
+        self.analog
+    }
+
+    // the analog button on the pad
+    pub fn set_analog(&mut self, analog: bool) {
+        if !self.locked {
+            self.analog = analog;
+        }
+    }
+
+    fn id(&self) -> u8 {
+        if self.config {
+            ID_CONFIG
+        } else if self.analog {
+            ID_ANALOG
+        } else {
+            ID_DIGITAL
+        }
+    }
+
+    // parameter bytes after the 0x5A
+    fn length(&self) -> usize {
+        if self.config || self.analog { 6 } else { 2 }
+    }
+
+    fn accepts(&self, command: u8) -> bool {
+        if self.config {
+            (0x40..=0x4f).contains(&command)
+        } else {
+            command == CMD_POLL || command == CMD_CONFIG
+        }
+    }
+
+    fn data(&self, index: usize) -> u8 {
+        let poll = || {
+            let buttons = !self.buttons;
+            [
+                buttons as u8,
+                (buttons >> 8) as u8,
+                self.sticks[0],
+                self.sticks[1],
+                self.sticks[2],
+                self.sticks[3],
+            ]
+        };
+        match (self.config, self.command) {
+            (false, _) => poll()[index],
+            (true, CMD_POLL) => poll()[index],
+            (true, CMD_GET_STATUS) => [0x01, 0x02, self.analog as u8, 0x02, 0x01, 0x00][index],
+            (true, CMD_ACTUATOR_INFO) => match self.params[0] {
+                0 => [0x00, 0x00, 0x01, 0x02, 0x00, 0x0a][index],
+                1 => [0x00, 0x00, 0x01, 0x01, 0x01, 0x14][index],
+                _ => 0x00,
+            },
+            (true, CMD_COMBINATION_INFO) => [0x00, 0x00, 0x02, 0x00, 0x01, 0x00][index],
+            (true, CMD_MODE_INFO) => match (index, self.params[0]) {
+                (3, 0) => 0x04,
+                (3, 1) => 0x07,
+                _ => 0x00,
+            },
+            (true, CMD_RUMBLE_MAPPING) => self.rumble_mapping[index],
+            (true, _) => 0x00,
+        }
+    }
+
+    // the command is complete, apply what the parameters asked for
+    fn execute(&mut self) {
+        match self.command {
+            CMD_POLL => {
+                let mut motors = (0, 0);
+                for (mapping, value) in self.rumble_mapping.iter().zip(self.params) {
+                    match mapping {
+                        0x00 => motors.0 = if value & 1 != 0 { 0xff } else { 0 },
+                        0x01 => motors.1 = value,
+                        _ => (),
+                    }
+                }
+                self.motors = motors;
+            }
+            CMD_CONFIG => self.config = self.params[0] == 1,
+            CMD_SET_MODE if self.config => {
+                self.analog = self.params[0] == 1;
+                self.locked = self.params[1] == 3;
+            }
+            CMD_RUMBLE_MAPPING if self.config => self.rumble_mapping = self.params,
+            _ => (),
+        }
+    }
+}
+
+impl Default for DualShock {
+    fn default() -> DualShock {
+        DualShock::new()
+    }
+}
+
+crate::state::snapshot!(DualShock {
+    buttons,
+    sticks,
+    analog,
+    locked,
+    config,
+    rumble_mapping,
+    motors,
+    command,
+    position,
+    params,
+});
+
+impl SioDevice for DualShock {
+    fn name(&self) -> &'static str {
+        "a DualShock"
+    }
+
+    fn reset(&mut self) {
+        self.position = 0;
+        self.params = [0; 6];
+    }
+
+    fn exchange(&mut self, byte: u8) -> (u8, bool) {
+        let position = self.position;
+        self.position += 1;
+        let reply = match position {
+            0 => 0xff,
+            1 => {
+                if !self.accepts(byte) {
+                    return (0xff, false);
+                }
+                self.command = byte;
+                self.id()
+            }
+            2 => 0x5a,
+            _ if position < 3 + self.length() => {
+                self.params[position - 3] = byte;
+                self.data(position - 3)
+            }
+            _ => return (0xff, false),
+        };
+
+        let last = position == 2 + self.length();
+        if last {
+            self.execute();
+        }
+        (reply, !last)
+    }
+}
+
+impl Controller for DualShock {
+    fn set_buttons(&mut self, buttons: u16) {
+        self.buttons = buttons;
+    }
+
+    fn set_sticks(&mut self, left: (u8, u8), right: (u8, u8)) {
+        self.sticks = [right.0, right.1, left.0, left.1];
+    }
+
+    fn rumble(&self) -> (u8, u8) {
+        self.motors
+    }
+}
@@ -0,0 +1,328 @@
+use super::SioDevice;
+use crate::state::{Snapshot, StateReader, StateWriter};
+use std::fs::{self, OpenOptions};
+use std::io::{self, Error, ErrorKind, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+// 1024 sectors (frames) of 128 bytes, the raw layout of .mcr/.mcd files
+pub const CARD_SIZE: usize = 128 * 1024;
+pub const SECTOR_SIZE: usize = 128;
+pub const SECTOR_COUNT: u16 = (CARD_SIZE / SECTOR_SIZE) as u16;
+
+const CMD_READ: u8 = b'R';
+const CMD_WRITE: u8 = b'W';
+const CMD_GET_ID: u8 = b'S';
+
+// FLAG bits, bit 3 is set until the first write after the card was inserted
+const FLAG_ERROR: u8 = 0x04;
+const FLAG_FRESH: u8 = 0x08;
+
+const END_GOOD: u8 = b'G';
+const END_BAD_CHECKSUM: u8 = b'N';
+const END_BAD_SECTOR: u8 = 0xff;
+
+// Commands after the 0x81 address byte, position 1 is the command byte:
+//   read:   5A 5D, sector MSB LSB, 5C 5D, sector MSB LSB, 128 data, checksum, 47
+//   write:  5A 5D, sector MSB LSB, 128 data, checksum, 5C 5D, 47/4E/FF
+//   get ID: 5A 5D 5C 5D 04 00 00 80
+// the checksum is the XOR of the sector bytes and the data
+pub struct MemoryCard {
+    data: Box<[u8]>,
+    // written sectors go straight to the file
+    path: Option<PathBuf>,
+    // a state load changed the data, the file no longer matches it
+    stale: bool,
+    flag: u8,
+    command: u8,
+    position: usize,
+    sector: u16,
+    checksum: u8,
+    // last byte received, the card echoes it while receiving a write
+    previous: u8,
+    buffer: [u8; SECTOR_SIZE],
+}
+
+impl MemoryCard {
+    // a formatted card that isn't saved anywhere
+    pub fn new() -> MemoryCard {
+        MemoryCard::from_data(format(), None)
+    }
+
+    // loads a raw card image, or creates a formatted one if the file doesn't exist
+    pub fn open(path: &Path) -> io::Result<MemoryCard> {
+        let data = match fs::read(path) {
+            Ok(data) if data.len() == CARD_SIZE => data.into_boxed_slice(),
+            Ok(data) => {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!("memory card images are {} bytes, got {}", CARD_SIZE, data.len()),
+                ));
+            }
+            Err(e) if e.kind() == ErrorKind::NotFound => {
+                let data = format();
+                fs::write(path, &data)?;
+                data
+            }
+            Err(e) => return Err(e),
+        };
+        Ok(MemoryCard::from_data(data, Some(path.to_path_buf())))
+    }
+
+    fn from_data(data: Box<[u8]>, path: Option<PathBuf>) -> MemoryCard {
+        MemoryCard {
+            data,
+            path,
+            stale: false,
+            flag: FLAG_FRESH,
+            command: 0,
+            position: 0,
+            sector: 0,
+            checksum: 0,
+            previous: 0,
+            buffer: [0; SECTOR_SIZE],
+        }
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    fn read_byte(&mut self, position: usize) -> (u8, bool) {
+        let sector = self.sector as usize * SECTOR_SIZE;
+        match position {
+            8 => (self.sector.to_be_bytes()[0], true),
+            9 => (self.sector.to_be_bytes()[1], true),
+            10..=137 => {
+                let byte = self.data[sector + position - 10];
+                self.checksum ^= byte;
+                (byte, true)
+            }
+            138 => (self.checksum, true),
+            139 => (END_GOOD, false),
+            _ => (0xff, false),
+        }
+    }
+
+    fn write_byte(&mut self, position: usize, byte: u8) -> (u8, bool) {
+        match position {
+            6..=133 => {
+                self.buffer[position - 6] = byte;
+                self.checksum ^= byte;
+                (self.previous, true)
+            }
+            134 => {
+                // the checksum byte, checked at the end
+                self.checksum ^= byte;
+                (self.previous, true)
+            }
+            135 => (0x5c, true),
+            136 => (0x5d, true),
+            137 => (self.commit(), false),
+            _ => (0xff, false),
+        }
+    }
+
+    fn commit(&mut self) -> u8 {
+        if self.sector >= SECTOR_COUNT {
+            self.flag |= FLAG_ERROR;
+            return END_BAD_SECTOR;
+        }
+        // data and checksum XOR to zero when the checksum is right
+        if self.checksum != 0 {
+            self.flag |= FLAG_ERROR;
+            return END_BAD_CHECKSUM;
+        }
+
+        let offset = self.sector as usize * SECTOR_SIZE;
+        self.data[offset..offset + SECTOR_SIZE].copy_from_slice(&self.buffer);
+        self.flag &= !(FLAG_FRESH | FLAG_ERROR);
+        let saved = if self.stale { self.write_back() } else { self.persist(offset) };
+        if let Err(e) = saved {
+            eprintln!("memory card: can't save sector {:03x}: {}", self.sector, e);
+        }
+        END_GOOD
+    }
+
+    fn persist(&self, offset: usize) -> io::Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let mut file = OpenOptions::new().write(true).open(path)?;
+        file.seek(SeekFrom::Start(offset as u64))?;
+        file.write_all(&self.data[offset..offset + SECTOR_SIZE])
+    }
+}
+
+impl Default for MemoryCard {
+    fn default() -> MemoryCard {
+        MemoryCard::new()
+    }
+}
+
+// the contents are part of the state, a game that read the directory before the state was
+// saved expects to find the same card. loading only changes the card in memory, rewinding must
+// not lose saves on disk. the file catches up on the next written sector or write_back
+impl Snapshot for MemoryCard {
+    fn save(&self, state: &mut StateWriter) {
+        self.data.save(state);
+        self.flag.save(state);
+        self.command.save(state);
+        self.position.save(state);
+        self.sector.save(state);
+        self.checksum.save(state);
+        self.previous.save(state);
+        self.buffer.save(state);
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), String> {
+        let previous_data = self.data.clone();
+        let loaded = self.data.load(state);
+        self.stale |= self.data != previous_data;
+        loaded?;
+        self.flag.load(state)?;
+        self.command.load(state)?;
+        self.position.load(state)?;
+        self.sector.load(state)?;
+        self.checksum.load(state)?;
+        self.previous.load(state)?;
+        self.buffer.load(state)
+    }
+}
+
+impl SioDevice for MemoryCard {
+    fn name(&self) -> &'static str {
+        "a memory card"
+    }
+
+    fn reset(&mut self) {
+        self.position = 0;
+    }
+
+    // rewrites the whole file if a state load left it behind the data
+    fn write_back(&mut self) -> io::Result<()> {
+        if let (Some(path), true) = (&self.path, self.stale) {
+            fs::write(path, &self.data)?;
+        }
+        self.stale = false;
+        Ok(())
+    }
+
+    fn exchange(&mut self, byte: u8) -> (u8, bool) {
+        let position = self.position;
+        self.position += 1;
+
+        let reply = match position {
+            0 => (0xff, true),
+            1 => {
+                self.command = byte;
+                match byte {
+                    CMD_READ | CMD_WRITE | CMD_GET_ID => (self.flag, true),
+                    _ => (self.flag, false),
+                }
+            }
+            2 => (0x5a, true),
+            3 => (0x5d, true),
+            _ => match (self.command, position) {
+                (CMD_GET_ID, 4..=9) => {
+                    let id = [0x5c, 0x5d, 0x04, 0x00, 0x00, 0x80][position - 4];
+                    (id, position < 9)
+                }
+                (CMD_READ | CMD_WRITE, 4) => {
+                    self.sector = (byte as u16) << 8;
+                    (0x00, true)
+                }
+                (CMD_READ | CMD_WRITE, 5) => {
+                    self.sector |= byte as u16;
+                    self.checksum = (self.sector >> 8) as u8 ^ self.sector as u8;
+                    (self.previous, true)
+                }
+                (CMD_READ, 6) => (0x5c, true),
+                // an invalid sector ends the read with FF FF instead of the confirmed address
+                (CMD_READ, 7) if self.sector >= SECTOR_COUNT => (0x5d, true),
+                (CMD_READ, 8) if self.sector >= SECTOR_COUNT => (0xff, true),
+                (CMD_READ, 9) if self.sector >= SECTOR_COUNT => (0xff, false),
+                (CMD_READ, 7) => (0x5d, true),
+                (CMD_READ, _) => self.read_byte(position),
+                (CMD_WRITE, _) => self.write_byte(position, byte),
+                _ => (0xff, false),
+            },
+        };
+        self.previous = byte;
+        reply
+    }
+}
+
+// an empty card: header frame, 15 free directory frames, an empty broken sector list and the write test frame
+pub fn format() -> Box<[u8]> {
+    let mut data = vec![0; CARD_SIZE].into_boxed_slice();
+    data[0..2].copy_from_slice(b"MC");
+    for frame in 1..16 {
+        let entry = &mut data[frame * SECTOR_SIZE..(frame + 1) * SECTOR_SIZE];
+        entry[0] = 0xa0;
+        entry[8..10].copy_from_slice(&[0xff, 0xff]);
+    }
+    for frame in 16..36 {
+        let entry = &mut data[frame * SECTOR_SIZE..(frame + 1) * SECTOR_SIZE];
+        entry[0..4].copy_from_slice(&[0xff; 4]);
+        entry[8..10].copy_from_slice(&[0xff, 0xff]);
+    }
+    for frame in 0..36 {
+        let entry = &mut data[frame * SECTOR_SIZE..(frame + 1) * SECTOR_SIZE];
+        entry[SECTOR_SIZE - 1] = entry[..SECTOR_SIZE - 1].iter().fold(0, |sum, byte| sum ^ byte);
+    }
+    data.copy_within(0..SECTOR_SIZE, 63 * SECTOR_SIZE);
+    data
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_sector(card: &mut MemoryCard, sector: u16, data: &[u8; SECTOR_SIZE]) -> u8 {
+        let [msb, lsb] = sector.to_be_bytes();
+        let checksum = data.iter().fold(msb ^ lsb, |sum, byte| sum ^ byte);
+        let mut command = vec![0x81, CMD_WRITE, 0, 0, msb, lsb];
+        command.extend_from_slice(data);
+        command.extend_from_slice(&[checksum, 0, 0, 0]);
+        card.reset();
+        let mut reply = 0;
+        for byte in command {
+            reply = card.exchange(byte).0;
+        }
+        reply
+    }
+
+    #[test]
+    fn states_only_change_the_file_when_asked() {
+        let path = std::env::temp_dir().join(format!("ps-card-{}", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let mut card = MemoryCard::open(&path).unwrap();
+        let mut writer = StateWriter::raw();
+        card.save(&mut writer);
+        let before = writer.finish();
+
+        assert_eq!(write_sector(&mut card, 40, &[0x11; SECTOR_SIZE]), END_GOOD);
+        let saved = fs::read(&path).unwrap();
+        assert_eq!(saved, &*card.data);
+
+        // going back to the state leaves the save on disk
+        card.load(&mut StateReader::new(&before)).unwrap();
+        assert_eq!(fs::read(&path).unwrap(), saved);
+        assert_eq!(&card.data[..], &format()[..]);
+
+        // the next written sector brings the whole file up to date
+        assert_eq!(write_sector(&mut card, 41, &[0x22; SECTOR_SIZE]), END_GOOD);
+        assert_eq!(fs::read(&path).unwrap(), &*card.data);
+        assert!(card.data[40 * SECTOR_SIZE..41 * SECTOR_SIZE].iter().all(|&byte| byte == 0));
+
+        card.load(&mut StateReader::new(&before)).unwrap();
+        card.write_back().unwrap();
+        let written = fs::read(&path);
+        fs::remove_file(&path).unwrap();
+        assert_eq!(written.unwrap(), &format()[..]);
+    }
+}
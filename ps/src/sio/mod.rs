@@ -0,0 +1,417 @@
+use crate::map;
+use crate::state::{FromState, Snapshot, StateReader, StateWriter};
+use controller::{Controller, DigitalPad};
+use std::collections::VecDeque;
+use std::io;
+
+pub mod controller;
+pub mod memory_card;
+
+// register offsets from SIO0_START
+const JOY_DATA: u32 = 0x0;
+const JOY_STAT: u32 = 0x4;
+const JOY_MODE: u32 = 0x8;
+const JOY_CTRL: u32 = 0xA;
+const JOY_BAUD: u32 = 0xE;
+
+// JOY_CTRL bits
+const CTRL_TX_ENABLE: u16 = 1 << 0;
+const CTRL_SELECT: u16 = 1 << 1;
+const CTRL_ACKNOWLEDGE: u16 = 1 << 4;
+const CTRL_RESET: u16 = 1 << 6;
+const CTRL_ACK_IRQ_ENABLE: u16 = 1 << 12;
+const CTRL_PORT: u16 = 1 << 13;
+
+const RX_FIFO_SIZE: usize = 8;
+// cycles between the end of a byte and the device pulling /ACK low
+const ACK_DELAY: usize = 338;
+
+// the first byte of every command addresses one of the two devices sharing a port
+pub const CONTROLLER_ADDRESS: u8 = 0x01;
+pub const MEMORY_CARD_ADDRESS: u8 = 0x81;
+
+// something plugged into a controller port or memory card slot
+pub trait SioDevice: Snapshot {
+    // what it is, save states only load into the same kind of device
+    fn name(&self) -> &'static str;
+    // the port was deselected, the next byte starts a new command
+    fn reset(&mut self);
+    // replies to one byte of the command, true if the device acknowledges it and expects another one
+    fn exchange(&mut self, byte: u8) -> (u8, bool);
+    // saves what is only in memory to the device's file, if it has one
+    fn write_back(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+pub struct Port {
+    pub controller: Option<Box<dyn Controller>>,
+    pub memory_card: Option<Box<dyn SioDevice>>,
+}
+
+impl Port {
+    pub fn new() -> Port {
+        Port { controller: None, memory_card: None }
+    }
+
+    fn reset(&mut self) {
+        if let Some(controller) = &mut self.controller {
+            controller.reset();
+        }
+        if let Some(memory_card) = &mut self.memory_card {
+            memory_card.reset();
+        }
+    }
+}
+
+impl Default for Port {
+    fn default() -> Port {
+        Port::new()
+    }
+}
+
+// a device is saved with its name, a state can't be loaded with something else plugged in
+fn save_device(device: &Option<Box<impl SioDevice + ?Sized>>, state: &mut StateWriter) {
+    device.as_ref().map_or("nothing", |device| device.name()).to_string().save(state);
+    if let Some(device) = device {
+        device.save(state);
+    }
+}
+
+fn load_device(
+    device: &mut Option<Box<impl SioDevice + ?Sized>>,
+    state: &mut StateReader,
+) -> Result<(), String> {
+    let name = String::read(state)?;
+    match device {
+        Some(device) if device.name() == name => device.load(state),
+        None if name == "nothing" => Ok(()),
+        _ => Err(format!(
+            "the state has {} plugged in where there is {}",
+            name,
+            device.as_ref().map_or("nothing", |device| device.name())
+        )),
+    }
+}
+
+impl Snapshot for Port {
+    fn save(&self, state: &mut StateWriter) {
+        save_device(&self.controller, state);
+        save_device(&self.memory_card, state);
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), String> {
+        load_device(&mut self.controller, state)?;
+        load_device(&mut self.memory_card, state)
+    }
+}
+
+// device talking for the rest of the command
+#[derive(Copy, Clone, PartialEq, Debug)]
+enum Target {
+    Controller,
+    MemoryCard,
+    // unknown address, missing device or the device stopped acknowledging
+    Nobody,
+}
+
+pub struct SIO0 {
+    pub ports: [Port; 2],
+    mode: u16,
+    control: u16,
+    baud: u16,
+    rx_fifo: VecDeque<u8>,
+    // byte being shifted out and the cycles left until the reply is in
+    tx: u8,
+    transfer_timer: Option<usize>,
+    ack_timer: Option<usize>,
+    // JOY_STAT bit 7, /ACK input level
+    ack_level: bool,
+    // JOY_STAT bit 9, cleared by JOY_CTRL bit 4
+    irq_flag: bool,
+    irq_pending: bool,
+    // None until the address byte of the current command was sent
+    target: Option<Target>,
+}
+
+impl Snapshot for SIO0 {
+    fn save(&self, state: &mut StateWriter) {
+        self.ports.save(state);
+        self.mode.save(state);
+        self.control.save(state);
+        self.baud.save(state);
+        self.rx_fifo.save(state);
+        self.tx.save(state);
+        self.transfer_timer.save(state);
+        self.ack_timer.save(state);
+        self.ack_level.save(state);
+        self.irq_flag.save(state);
+        self.irq_pending.save(state);
+        let target: u8 = match self.target {
+            None => 0,
+            Some(Target::Controller) => 1,
+            Some(Target::MemoryCard) => 2,
+            Some(Target::Nobody) => 3,
+        };
+        target.save(state);
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), String> {
+        self.ports.load(state)?;
+        self.mode.load(state)?;
+        self.control.load(state)?;
+        self.baud.load(state)?;
+        self.rx_fifo.load(state)?;
+        self.tx.load(state)?;
+        self.transfer_timer.load(state)?;
+        self.ack_timer.load(state)?;
+        self.ack_level.load(state)?;
+        self.irq_flag.load(state)?;
+        self.irq_pending.load(state)?;
+        self.target = match u8::read(state)? {
+            0 => None,
+            1 => Some(Target::Controller),
+            2 => Some(Target::MemoryCard),
+            3 => Some(Target::Nobody),
+            target => return Err(format!("bad SIO0 target {}", target)),
+        };
+        Ok(())
+    }
+}
+
+impl SIO0 {
+    pub fn new() -> SIO0 {
+        // a digital pad in port 1 like a stock console
+        let mut ports = [Port::new(), Port::new()];
+        ports[0].controller = Some(Box::new(DigitalPad::new()));
+        SIO0 {
+            ports,
+            mode: 0,
+            control: 0,
+            baud: 0,
+            rx_fifo: VecDeque::new(),
+            tx: 0,
+            transfer_timer: None,
+            ack_timer: None,
+            ack_level: false,
+            irq_flag: false,
+            irq_pending: false,
+            target: None,
+        }
+    }
+
+    // port is 0 or 1, None unplugs the controller
+    pub fn plug_controller(&mut self, port: usize, controller: Option<Box<dyn Controller>>) {
+        self.ports[port].controller = controller;
+    }
+
+    pub fn controller(&mut self, port: usize) -> Option<&mut (dyn Controller + 'static)> {
+        self.ports[port].controller.as_deref_mut()
+    }
+
+    pub fn set_buttons(&mut self, port: usize, buttons: u16) {
+        if let Some(controller) = self.controller(port) {
+            controller.set_buttons(buttons);
+        }
+    }
+
+    pub fn set_sticks(&mut self, port: usize, left: (u8, u8), right: (u8, u8)) {
+        if let Some(controller) = self.controller(port) {
+            controller.set_sticks(left, right);
+        }
+    }
+
+    // cards can be swapped at any time, the new one reports itself as fresh until its first write
+    pub fn insert_memory_card(&mut self, port: usize, card: Option<Box<dyn SioDevice>>) {
+        self.ports[port].memory_card = card;
+        // a command in progress on the pulled card is cut off
+        let selected_port = ((self.control & CTRL_PORT) != 0) as usize;
+        if port == selected_port && self.target == Some(Target::MemoryCard) {
+            self.target = Some(Target::Nobody);
+        }
+    }
+
+    // JOY_DATA reads take the byte out of the RX FIFO
+    pub fn read(&mut self, address: u32) -> u32 {
+        match address - map::SIO0_START {
+            JOY_DATA => self.rx_fifo.pop_front().unwrap_or(0xff) as u32,
+            _ => self.peek(address),
+        }
+    }
+
+    // what a read would return without taking anything out of the FIFO, for debuggers and traces
+    pub fn peek(&self, address: u32) -> u32 {
+        let offset = address - map::SIO0_START;
+        match offset {
+            JOY_DATA => self.rx_fifo.front().copied().unwrap_or(0xff) as u32,
+            JOY_STAT => self.status(),
+            JOY_MODE => self.mode as u32,
+            JOY_CTRL => self.control as u32,
+            JOY_BAUD => self.baud as u32,
+            // the upper half of JOY_STAT holds the baud timer
+            0x6 => self.status() >> 16,
+            // the middle of a register or unused
+            _ => 0,
+        }
+    }
+
+    pub fn write(&mut self, address: u32, value: u32) {
+        let offset = address - map::SIO0_START;
+        match offset {
+            JOY_DATA => self.start_transfer(value as u8),
+            JOY_MODE => self.mode = value as u16,
+            JOY_CTRL => self.write_control(value as u16),
+            JOY_BAUD => self.baud = value as u16,
+            // JOY_STAT is read only and the rest is unused
+            _ => (),
+        }
+    }
+
+    fn status(&self) -> u32 {
+        let mut status = 1; // TX buffer empty, the byte moves to the shifter right away
+        status |= (!self.rx_fifo.is_empty() as u32) << 1;
+        status |= (self.transfer_timer.is_none() as u32) << 2;
+        status |= (self.ack_level as u32) << 7;
+        status |= (self.irq_flag as u32) << 9;
+        status
+    }
+
+    fn write_control(&mut self, value: u16) {
+        let previous = self.control;
+        self.control = value & !(CTRL_ACKNOWLEDGE | CTRL_RESET);
+
+        if value & CTRL_ACKNOWLEDGE != 0 {
+            self.irq_flag = false;
+        }
+        if value & CTRL_RESET != 0 {
+            self.mode = 0;
+            self.control = 0;
+            self.baud = 0;
+            self.rx_fifo.clear();
+            self.transfer_timer = None;
+            self.ack_timer = None;
+            self.ack_level = false;
+        }
+
+        // dropping /JOYn or switching ports ends the command
+        let selection = CTRL_SELECT | CTRL_PORT;
+        if self.control & selection != previous & selection || self.control & CTRL_SELECT == 0 {
+            self.target = None;
+            for port in self.ports.iter_mut() {
+                port.reset();
+            }
+        }
+    }
+
+    // JOY_MODE bits 1-0 pick the baud reload factor
+    fn transfer_cycles(&self) -> usize {
+        let factor = match self.mode & 0x3 {
+            2 => 16,
+            3 => 64,
+            _ => 1,
+        };
+        (self.baud as usize * factor * 8).max(1)
+    }
+
+    fn start_transfer(&mut self, byte: u8) {
+        if self.control & CTRL_TX_ENABLE == 0 {
+            return;
+        }
+        self.tx = byte;
+        self.ack_level = false;
+        self.ack_timer = None;
+        self.transfer_timer = Some(self.transfer_cycles());
+    }
+
+    fn exchange(&mut self, byte: u8) -> (u8, bool) {
+        if self.control & CTRL_SELECT == 0 {
+            return (0xff, false);
+        }
+
+        let target = self.target.unwrap_or(match byte {
+            CONTROLLER_ADDRESS => Target::Controller,
+            MEMORY_CARD_ADDRESS => Target::MemoryCard,
+            _ => Target::Nobody,
+        });
+        let port = &mut self.ports[((self.control & CTRL_PORT) != 0) as usize];
+        let device = match target {
+            Target::Controller => port.controller.as_deref_mut().map(|c| c as &mut dyn SioDevice),
+            Target::MemoryCard => port.memory_card.as_deref_mut(),
+            Target::Nobody => None,
+        };
+
+        let (reply, ack) = match device {
+            Some(device) => device.exchange(byte),
+            None => (0xff, false),
+        };
+        self.target = Some(if ack { target } else { Target::Nobody });
+        (reply, ack)
+    }
+
+    pub fn tick(&mut self, cycles: usize) {
+        if let Some(remaining) = self.transfer_timer {
+            if remaining > cycles {
+                self.transfer_timer = Some(remaining - cycles);
+            } else {
+                self.transfer_timer = None;
+                let (reply, ack) = self.exchange(self.tx);
+                let rx_fifo = &mut self.rx_fifo;
+                if rx_fifo.len() == RX_FIFO_SIZE {
+                    rx_fifo.pop_back();
+                }
+                rx_fifo.push_back(reply);
+                if ack {
+                    self.ack_timer = Some(ACK_DELAY);
+                }
+            }
+        }
+
+        if let Some(remaining) = self.ack_timer {
+            if remaining > cycles {
+                self.ack_timer = Some(remaining - cycles);
+            } else {
+                self.ack_timer = None;
+                self.ack_level = true;
+                if self.control & CTRL_ACK_IRQ_ENABLE != 0 && !self.irq_flag {
+                    self.irq_flag = true;
+                    self.irq_pending = true;
+                }
+            }
+        }
+    }
+
+    // true once for every IRQ7 the port raised
+    pub fn take_interrupt(&mut self) -> bool {
+        std::mem::take(&mut self.irq_pending)
+    }
+}
+
+impl Default for SIO0 {
+    fn default() -> SIO0 {
+        SIO0::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn peeking_leaves_the_reply_in_the_fifo() {
+        let mut sio0 = SIO0::new();
+        let data = map::SIO0_START + JOY_DATA;
+        sio0.write(map::SIO0_START + JOY_CTRL, (CTRL_TX_ENABLE | CTRL_SELECT) as u32);
+        sio0.write(data, CONTROLLER_ADDRESS as u32);
+        sio0.tick(100_000);
+        sio0.write(data, 0x42);
+        sio0.tick(100_000);
+
+        assert_eq!(sio0.peek(data), 0xff);
+        assert_eq!(sio0.peek(data), 0xff);
+        assert_eq!(sio0.read(data), 0xff);
+        // the digital pad's ID
+        assert_eq!(sio0.peek(data), 0x41);
+        assert_eq!(sio0.read(data), 0x41);
+        assert_eq!(sio0.peek(map::SIO0_START + JOY_STAT) & 2, 0);
+    }
+}
@@ -0,0 +1,211 @@
+// Envelopes shared by the voice ADSR and the volume sweeps. Every phase adds a step
+// to the level after waiting a number of 44.1 kHz ticks, both derived from a shift and step value:
+//   cycles = 1 << max(0, shift - 11)
+//   step   = step << max(0, 11 - shift)
+// exponential increases slow down by 4 above 0x6000, exponential decreases scale with the level.
+
+const MAX_LEVEL: i32 = 0x7fff;
+
+#[derive(Copy, Clone)]
+pub struct Envelope {
+    pub exponential: bool,
+    pub decreasing: bool,
+    pub shift: u8,
+    // +7..+4 when increasing, -8..-5 when decreasing
+    pub step: i32,
+}
+
+impl Envelope {
+    pub fn new(exponential: bool, decreasing: bool, shift: u8, step: u8) -> Envelope {
+        let step = if decreasing { -8 + step as i32 } else { 7 - step as i32 };
+        Envelope { exponential, decreasing, shift, step }
+    }
+
+    // applies one step, returns the new level and how many ticks to wait for the next one
+    pub fn advance(&self, level: i32) -> (i32, u32) {
+        let shift = self.shift as i32;
+        let mut cycles = 1 << (shift - 11).max(0);
+        let mut step = self.step << (11 - shift).max(0);
+
+        if self.exponential && !self.decreasing && level > 0x6000 {
+            cycles *= 4;
+        }
+        if self.exponential && self.decreasing {
+            step = step * level / 0x8000;
+        }
+
+        ((level + step).clamp(0, MAX_LEVEL), cycles as u32)
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum AdsrPhase {
+    Attack,
+    Decay,
+    Sustain,
+    Release,
+    Off,
+}
+
+#[derive(Copy, Clone)]
+pub struct Adsr {
+    // 0x1F801C08 (low) and 0x1F801C0A (high)
+    pub register: u32,
+    pub phase: AdsrPhase,
+    pub level: i16,
+    counter: u32,
+}
+
+crate::state::snapshot_enum!(AdsrPhase { Attack, Decay, Sustain, Release, Off });
+crate::state::snapshot!(Adsr { register, phase, level, counter });
+
+impl Adsr {
+    pub fn new() -> Adsr {
+        Adsr { register: 0, phase: AdsrPhase::Off, level: 0, counter: 0 }
+    }
+
+    pub fn key_on(&mut self) {
+        self.phase = AdsrPhase::Attack;
+        self.level = 0;
+        self.counter = 0;
+    }
+
+    pub fn key_off(&mut self) {
+        if self.phase != AdsrPhase::Off {
+            self.phase = AdsrPhase::Release;
+            self.counter = 0;
+        }
+    }
+
+    // loop end without repeat, the voice is silenced immediately
+    pub fn mute(&mut self) {
+        self.phase = AdsrPhase::Release;
+        self.level = 0;
+        self.counter = 0;
+    }
+
+    fn low(&self) -> u32 {
+        self.register & 0xffff
+    }
+
+    fn high(&self) -> u32 {
+        self.register >> 16
+    }
+
+    fn sustain_level(&self) -> i32 {
+        (((self.low() & 0xf) as i32 + 1) * 0x800).min(MAX_LEVEL)
+    }
+
+    fn envelope(&self) -> Option<Envelope> {
+        let (low, high) = (self.low(), self.high());
+        match self.phase {
+            AdsrPhase::Attack => Some(Envelope::new(
+                low & 0x8000 != 0,
+                false,
+                ((low >> 10) & 0x1f) as u8,
+                ((low >> 8) & 0x3) as u8,
+            )),
+            AdsrPhase::Decay => Some(Envelope::new(true, true, ((low >> 4) & 0xf) as u8, 0)),
+            AdsrPhase::Sustain => Some(Envelope::new(
+                high & 0x8000 != 0,
+                high & 0x4000 != 0,
+                ((high >> 8) & 0x1f) as u8,
+                ((high >> 6) & 0x3) as u8,
+            )),
+            AdsrPhase::Release => {
+                Some(Envelope::new(high & 0x20 != 0, true, (high & 0x1f) as u8, 0))
+            }
+            AdsrPhase::Off => None,
+        }
+    }
+
+    pub fn tick(&mut self) {
+        let envelope = match self.envelope() {
+            Some(envelope) => envelope,
+            None => return,
+        };
+
+        if self.counter > 0 {
+            self.counter -= 1;
+            if self.counter > 0 {
+                return;
+            }
+        }
+        let (level, cycles) = envelope.advance(self.level as i32);
+        self.level = level as i16;
+        self.counter = cycles;
+
+        match self.phase {
+            AdsrPhase::Attack if level == MAX_LEVEL => self.set_phase(AdsrPhase::Decay),
+            AdsrPhase::Decay if level <= self.sustain_level() => self.set_phase(AdsrPhase::Sustain),
+            AdsrPhase::Release if level == 0 => self.set_phase(AdsrPhase::Off),
+            _ => (),
+        }
+    }
+
+    fn set_phase(&mut self, phase: AdsrPhase) {
+        self.phase = phase;
+        self.counter = 0;
+    }
+}
+
+impl Default for Adsr {
+    fn default() -> Adsr {
+        Adsr::new()
+    }
+}
+
+// Voice and main volume registers, either a fixed volume or a sweep:
+//   bit 15 = 0: volume/2 in bits 14-0
+//   bit 15 = 1: bit 14 exponential, bit 13 decreasing, bit 12 negative phase, bits 6-2 shift, bits 1-0 step
+#[derive(Copy, Clone)]
+pub struct Volume {
+    pub register: u16,
+    pub level: i16,
+    counter: u32,
+}
+
+crate::state::snapshot!(Volume { register, level, counter });
+
+impl Volume {
+    pub fn new() -> Volume {
+        Volume { register: 0, level: 0, counter: 0 }
+    }
+
+    pub fn set(&mut self, value: u16) {
+        self.register = value;
+        self.counter = 0;
+        if value & 0x8000 == 0 {
+            self.level = (value << 1) as i16;
+        }
+    }
+
+    pub fn tick(&mut self) {
+        let value = self.register;
+        if value & 0x8000 == 0 {
+            return;
+        }
+
+        if self.counter > 0 {
+            self.counter -= 1;
+            if self.counter > 0 {
+                return;
+            }
+        }
+        let envelope = Envelope::new(
+            value & 0x4000 != 0,
+            value & 0x2000 != 0,
+            ((value >> 2) & 0x1f) as u8,
+            (value & 0x3) as u8,
+        );
+        let (magnitude, cycles) = envelope.advance((self.level as i32).abs());
+        self.level = if value & 0x1000 != 0 { -magnitude } else { magnitude } as i16;
+        self.counter = cycles;
+    }
+}
+
+impl Default for Volume {
+    fn default() -> Volume {
+        Volume::new()
+    }
+}
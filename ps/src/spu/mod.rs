@@ -1,23 +1,210 @@
 use crate::map;
+use adsr::Volume;
+use reverb::Reverb;
+use std::collections::VecDeque;
+use voice::Voice;
+
+pub mod adsr;
+mod gauss;
+pub mod reverb;
+pub mod voice;
+
+pub const RAM_SIZE: usize = 512 * 1024;
+pub const VOICE_COUNT: usize = 24;
+// 33.8688 MHz / 44100 Hz
+pub const CYCLES_PER_SAMPLE: usize = 768;
+// samples nobody collected are dropped after a second
+const OUTPUT_CAPACITY: usize = 44100;
+
+// register offsets from SPU_START
+const MAIN_VOLUME_LEFT: u32 = 0x180;
+const MAIN_VOLUME_RIGHT: u32 = 0x182;
+const KEY_ON_LOW: u32 = 0x188;
+const KEY_ON_HIGH: u32 = 0x18A;
+const KEY_OFF_LOW: u32 = 0x18C;
+const KEY_OFF_HIGH: u32 = 0x18E;
+const PITCH_MOD_LOW: u32 = 0x190;
+const NOISE_MODE_LOW: u32 = 0x194;
+const REVERB_MODE_LOW: u32 = 0x198;
+const ENDX_LOW: u32 = 0x19C;
+const ENDX_HIGH: u32 = 0x19E;
+const IRQ_ADDRESS: u32 = 0x1A4;
+const TRANSFER_ADDRESS: u32 = 0x1A6;
+const TRANSFER_FIFO: u32 = 0x1A8;
+const SPUCNT: u32 = 0x1AA;
+const SPUSTAT: u32 = 0x1AE;
+const CD_VOLUME_LEFT: u32 = 0x1B0;
+const CD_VOLUME_RIGHT: u32 = 0x1B2;
+const CURRENT_MAIN_VOLUME_LEFT: u32 = 0x1B8;
+const CURRENT_MAIN_VOLUME_RIGHT: u32 = 0x1BA;
+const VOICE_VOLUMES_START: u32 = 0x200;
+const VOICE_VOLUMES_END: u32 = 0x25F;
+
+const RAM_MASK: u32 = RAM_SIZE as u32 - 1;
+// the manual write FIFO holds 32 halfwords
+const FIFO_SIZE: usize = 32;
+
+// the first 4 KiB of sound RAM hold four 1 KiB capture buffers: CD left, CD right, voice 1, voice 3
+const CAPTURE_CD_LEFT: u32 = 0x000;
+const CAPTURE_CD_RIGHT: u32 = 0x400;
+const CAPTURE_VOICE1: u32 = 0x800;
+const CAPTURE_VOICE3: u32 = 0xC00;
+const CAPTURE_SAMPLES: u32 = 0x200;
+
+// SPUCNT bits 5-4
+const TRANSFER_STOP: u16 = 0;
+const TRANSFER_MANUAL: u16 = 1;
+const TRANSFER_DMA_WRITE: u16 = 2;
+const TRANSFER_DMA_READ: u16 = 3;
+
 pub struct SPU {
-    pub registers: [u16; 256], // SPU has 512 bytes of registers (256 halfwords)
+    pub registers: [u16; 512], // SPU has 1KB of registers (512 halfwords), most of them are read back as written
+    pub voices: [Voice; VOICE_COUNT],
+    pub main_volume: [Volume; 2],
+    pub ram: Box<[u8]>,
+    // byte address in sound RAM of the next transfer, set from the 0x1A6 register (in 8 byte units)
+    transfer_address: u32,
+    fifo: VecDeque<u16>,
+    // halfword index into the capture buffers, SPUSTAT bit 11 tells which half is being written
+    capture_index: u32,
+    // SPUSTAT bit 6, set when the IRQ address is accessed and cleared by turning off SPUCNT bit 6
+    irq_flag: bool,
+    // the flag was set since the PlayStation last checked, it then raises IRQ9
+    irq_pending: bool,
+    pub reverb: Reverb,
+    noise_level: i16,
+    noise_timer: i32,
+    // CPU cycles not turned into a sample yet
+    cycles: usize,
+    // 44.1 kHz stereo samples coming from the CD-ROM after its volume matrix
+    pub cd_input: VecDeque<(i16, i16)>,
+    output: VecDeque<(i16, i16)>,
 }
 
+// the output belongs to whoever plays it
+crate::state::snapshot!(SPU {
+    registers,
+    voices,
+    main_volume,
+    ram,
+    transfer_address,
+    fifo,
+    capture_index,
+    irq_flag,
+    irq_pending,
+    reverb,
+    noise_level,
+    noise_timer,
+    cycles,
+    cd_input,
+});
+
 impl SPU {
     pub fn new() -> SPU {
-        SPU { registers: [0; 256] }
+        SPU {
+            registers: [0; 512],
+            voices: [Voice::new(); VOICE_COUNT],
+            main_volume: [Volume::new(); 2],
+            ram: vec![0; RAM_SIZE].into_boxed_slice(),
+            transfer_address: 0,
+            fifo: VecDeque::new(),
+            capture_index: 0,
+            irq_flag: false,
+            irq_pending: false,
+            reverb: Reverb::new(),
+            noise_level: 1,
+            noise_timer: 0,
+            cycles: 0,
+            cd_input: VecDeque::new(),
+            output: VecDeque::new(),
+        }
+    }
+
+    pub fn push_cd_input(&mut self, left: i16, right: i16) {
+        self.cd_input.push_back((left, right));
+    }
+
+    fn register(&self, offset: u32) -> u16 {
+        self.registers[(offset >> 1) as usize]
+    }
+
+    // two halfword registers holding one bit per voice
+    fn voice_bits(&self, low_offset: u32) -> u32 {
+        self.register(low_offset) as u32 | ((self.register(low_offset + 2) as u32) << 16)
+    }
+
+    fn control(&self) -> u16 {
+        self.register(SPUCNT)
     }
 
     pub fn read_halfword(&self, address: u32) -> u16 {
-        let offset = ((address - map::SPU_START) & 0x3FF) >> 1;
+        let offset = (address - map::SPU_START) & 0x3FF;
         //println!("SPU Read: 0x{:08X}, offset: 0x{:04X}", address, offset);
-        self.registers[offset as usize]
+        match offset {
+            0x000..=0x17F => {
+                let voice = &self.voices[(offset >> 4) as usize];
+                match offset & 0xf {
+                    0xC => voice.adsr.level as u16,
+                    0xE => voice.repeat_address,
+                    _ => self.register(offset),
+                }
+            }
+            ENDX_LOW => self.endx() as u16,
+            ENDX_HIGH => (self.endx() >> 16) as u16,
+            SPUSTAT => self.status(),
+            CURRENT_MAIN_VOLUME_LEFT => self.main_volume[0].level as u16,
+            CURRENT_MAIN_VOLUME_RIGHT => self.main_volume[1].level as u16,
+            VOICE_VOLUMES_START..=VOICE_VOLUMES_END => {
+                let voice = &self.voices[((offset - VOICE_VOLUMES_START) >> 2) as usize];
+                voice.volume[((offset >> 1) & 1) as usize].level as u16
+            }
+            _ => self.register(offset),
+        }
     }
 
     pub fn write_halfword(&mut self, address: u32, value: u16) {
-        let offset = ((address - map::SPU_START) & 0x3FF) >> 1;
+        let offset = (address - map::SPU_START) & 0x3FF;
         //println!("SPU Write: 0x{:08X}, value: 0x{:04X}, offset: 0x{:04X}", address, value, offset);
-        self.registers[offset as usize] = value;
+        self.registers[(offset >> 1) as usize] = value;
+
+        match offset {
+            0x000..=0x17F => {
+                let voice = &mut self.voices[(offset >> 4) as usize];
+                match offset & 0xf {
+                    0x0 => voice.volume[0].set(value),
+                    0x2 => voice.volume[1].set(value),
+                    0x4 => voice.pitch = value,
+                    0x6 => voice.start_address = value,
+                    0x8 => voice.adsr.register = (voice.adsr.register & 0xffff0000) | value as u32,
+                    0xA => {
+                        voice.adsr.register =
+                            (voice.adsr.register & 0xffff) | ((value as u32) << 16)
+                    }
+                    0xC => voice.adsr.level = value as i16,
+                    0xE => voice.repeat_address = value,
+                    _ => unreachable!(),
+                }
+            }
+            MAIN_VOLUME_LEFT => self.main_volume[0].set(value),
+            MAIN_VOLUME_RIGHT => self.main_volume[1].set(value),
+            KEY_ON_LOW => self.key_on(value as u32),
+            KEY_ON_HIGH => self.key_on((value as u32) << 16),
+            KEY_OFF_LOW => self.key_off(value as u32),
+            KEY_OFF_HIGH => self.key_off((value as u32) << 16),
+            reverb::BASE => self.reverb.set_base(value),
+            TRANSFER_ADDRESS => self.transfer_address = (value as u32 * 8) & RAM_MASK,
+            // writes to a full FIFO are lost
+            TRANSFER_FIFO if self.fifo.len() < FIFO_SIZE => self.fifo.push_back(value),
+            SPUCNT => {
+                if value & 0x40 == 0 {
+                    self.irq_flag = false;
+                }
+                if self.transfer_mode() == TRANSFER_MANUAL {
+                    self.flush_fifo();
+                }
+            }
+            _ => (),
+        }
     }
 
     pub fn write_byte(&mut self, address: u32, value: u8) {
@@ -43,7 +230,238 @@ impl SPU {
             halfword = (halfword & 0x00FF) | ((value as u16) << 8);
         }
 
-        // Write the updated halfword back
-        self.registers[halfword_index] = halfword;
+        // Write the updated halfword back, through the register handling
+        self.write_halfword(map::SPU_START + halfword_offset as u32, halfword);
+    }
+
+    fn transfer_mode(&self) -> u16 {
+        (self.control() >> 4) & 0x3
+    }
+
+    // SPUSTAT mirrors the low SPUCNT bits. bit 10 (transfer busy) is always 0, a known gap: DMA4
+    // and FIFO flushes move all their data at once instead of at the SPU's pace, so there is no
+    // busy time to report. games that wait for the bit to drop carry on right away
+    fn status(&self) -> u16 {
+        let control = self.control();
+        let mut status = control & 0x3f;
+        status |= (control & 0x20) << 2;
+        status |= (self.irq_flag as u16) << 6;
+        status |= ((self.capture_index >= CAPTURE_SAMPLES / 2) as u16) << 11;
+        match self.transfer_mode() {
+            TRANSFER_DMA_WRITE => status |= 1 << 8,
+            TRANSFER_DMA_READ => status |= 1 << 9,
+            TRANSFER_STOP | TRANSFER_MANUAL => (),
+            _ => unreachable!(),
+        }
+        status
+    }
+
+    fn flush_fifo(&mut self) {
+        while let Some(halfword) = self.fifo.pop_front() {
+            self.write_ram(halfword);
+        }
+    }
+
+    // any access to the IRQ address while SPUCNT bit 6 is set latches the flag and raises IRQ9
+    fn check_irq(&mut self, address: u32, length: u32) {
+        if self.control() & 0x40 == 0 || self.irq_flag {
+            return;
+        }
+        let irq_address = self.register(IRQ_ADDRESS) as u32 * 8;
+        if irq_address >= address && irq_address < address + length {
+            self.irq_flag = true;
+            self.irq_pending = true;
+        }
+    }
+
+    // true once for every IRQ the SPU raised
+    pub fn take_interrupt(&mut self) -> bool {
+        std::mem::take(&mut self.irq_pending)
+    }
+
+    fn write_ram(&mut self, halfword: u16) {
+        self.check_irq(self.transfer_address, 2);
+        let address = self.transfer_address as usize;
+        self.ram[address..address + 2].copy_from_slice(&halfword.to_le_bytes());
+        self.transfer_address = (self.transfer_address + 2) & RAM_MASK;
+    }
+
+    fn read_ram(&mut self) -> u16 {
+        self.check_irq(self.transfer_address, 2);
+        let address = self.transfer_address as usize;
+        self.transfer_address = (self.transfer_address + 2) & RAM_MASK;
+        u16::from_le_bytes([self.ram[address], self.ram[address + 1]])
+    }
+
+    // DMA channel 4, one word is two halfwords at the transfer address
+    pub fn dma_write(&mut self, word: u32) {
+        self.write_ram(word as u16);
+        self.write_ram((word >> 16) as u16);
+    }
+
+    pub fn dma_read(&mut self) -> u32 {
+        let low = self.read_ram() as u32;
+        let high = self.read_ram() as u32;
+        low | (high << 16)
+    }
+
+    fn key_on(&mut self, bits: u32) {
+        for (i, voice) in self.voices.iter_mut().enumerate() {
+            if bits & (1 << i) != 0 {
+                voice.key_on();
+            }
+        }
+    }
+
+    fn key_off(&mut self, bits: u32) {
+        for (i, voice) in self.voices.iter_mut().enumerate() {
+            if bits & (1 << i) != 0 {
+                voice.key_off();
+            }
+        }
+    }
+
+    fn endx(&self) -> u32 {
+        self.voices
+            .iter()
+            .enumerate()
+            .fold(0, |bits, (i, voice)| bits | ((voice.ended as u32) << i))
+    }
+
+    // advances the SPU by a number of CPU cycles, producing a sample every 768 cycles
+    pub fn tick(&mut self, cycles: usize) {
+        self.cycles += cycles;
+        while self.cycles >= CYCLES_PER_SAMPLE {
+            self.cycles -= CYCLES_PER_SAMPLE;
+            let sample = self.clock();
+            if self.output.len() == OUTPUT_CAPACITY {
+                self.output.pop_front();
+            }
+            self.output.push_back(sample);
+        }
+    }
+
+    // samples produced since the last call
+    pub fn take_output(&mut self) -> Vec<(i16, i16)> {
+        self.output.drain(..).collect()
+    }
+
+    fn clock(&mut self) -> (i16, i16) {
+        self.tick_noise();
+        let pitch_modulation = self.voice_bits(PITCH_MOD_LOW);
+        let noise_mode = self.voice_bits(NOISE_MODE_LOW);
+        let reverb_mode = self.voice_bits(REVERB_MODE_LOW);
+
+        let mut left: i32 = 0;
+        let mut right: i32 = 0;
+        let mut reverb_left: i32 = 0;
+        let mut reverb_right: i32 = 0;
+        for i in 0..VOICE_COUNT {
+            // voice 0 can't be modulated, there is no voice before it
+            let modulator = if i > 0 && pitch_modulation & (1 << i) != 0 {
+                Some(self.voices[i - 1].output)
+            } else {
+                None
+            };
+            let noise = if noise_mode & (1 << i) != 0 { Some(self.noise_level) } else { None };
+
+            let voice = &mut self.voices[i];
+            let sample = voice.tick(&self.ram, noise, modulator) as i32;
+            let (active, block_address) = (voice.is_active(), voice.current_address);
+            let voice_left = (sample * voice.volume[0].level as i32) >> 15;
+            let voice_right = (sample * voice.volume[1].level as i32) >> 15;
+            left += voice_left;
+            right += voice_right;
+            if reverb_mode & (1 << i) != 0 {
+                reverb_left += voice_left;
+                reverb_right += voice_right;
+            }
+            if active {
+                self.check_irq(block_address, 16);
+            }
+        }
+
+        // the CD input is consumed at the output rate even while it's not mixed in
+        let cd = self.cd_input.pop_front().unwrap_or((0, 0));
+        self.capture(cd.0, cd.1, self.voices[1].output, self.voices[3].output);
+        if self.control() & 0x1 != 0 {
+            let cd_left = (cd.0 as i32 * self.register(CD_VOLUME_LEFT) as i16 as i32) >> 15;
+            let cd_right = (cd.1 as i32 * self.register(CD_VOLUME_RIGHT) as i16 as i32) >> 15;
+            left += cd_left;
+            right += cd_right;
+            // SPUCNT bit 2, CD audio reverb
+            if self.control() & 0x4 != 0 {
+                reverb_left += cd_left;
+                reverb_right += cd_right;
+            }
+        }
+
+        // SPUCNT bit 7 enables writes to the reverb work area, the output is still mixed without it
+        let write_enabled = self.control() & 0x80 != 0;
+        let (wet_left, wet_right) = self.reverb.tick(
+            &self.registers,
+            &mut self.ram,
+            (reverb_left, reverb_right),
+            write_enabled,
+        );
+        left += (wet_left as i32 * self.register(reverb::OUTPUT_VOLUME_LEFT) as i16 as i32) >> 15;
+        right +=
+            (wet_right as i32 * self.register(reverb::OUTPUT_VOLUME_RIGHT) as i16 as i32) >> 15;
+
+        self.main_volume[0].tick();
+        self.main_volume[1].tick();
+        let left = (clamp16(left) as i32 * self.main_volume[0].level as i32) >> 15;
+        let right = (clamp16(right) as i32 * self.main_volume[1].level as i32) >> 15;
+
+        // SPUCNT bit 14 is the mute switch, voices keep running while muted
+        if self.control() & 0x4000 == 0 {
+            return (0, 0);
+        }
+        (clamp16(left), clamp16(right))
+    }
+
+    // CD samples are captured before the CD volume, voices after ADSR but before their volume
+    fn capture(&mut self, cd_left: i16, cd_right: i16, voice1: i16, voice3: i16) {
+        let offset = self.capture_index * 2;
+        for (buffer, sample) in [
+            (CAPTURE_CD_LEFT, cd_left),
+            (CAPTURE_CD_RIGHT, cd_right),
+            (CAPTURE_VOICE1, voice1),
+            (CAPTURE_VOICE3, voice3),
+        ] {
+            let address = buffer + offset;
+            self.check_irq(address, 2);
+            self.ram[address as usize..address as usize + 2].copy_from_slice(&sample.to_le_bytes());
+        }
+        self.capture_index = (self.capture_index + 1) % CAPTURE_SAMPLES;
+    }
+
+    // the noise generator is a 16 bit LFSR clocked at a rate set by SPUCNT bits 13-8
+    fn tick_noise(&mut self) {
+        let control = self.control();
+        let step = ((control >> 8) & 0x3) as i32 + 4;
+        let shift = (control >> 10) & 0xf;
+
+        self.noise_timer -= step;
+        if self.noise_timer < 0 {
+            let level = self.noise_level as u16;
+            let parity = ((level >> 15) ^ (level >> 12) ^ (level >> 11) ^ (level >> 10) ^ 1) & 1;
+            self.noise_level = ((level << 1) | parity) as i16;
+
+            self.noise_timer += 0x20000 >> shift;
+            if self.noise_timer < 0 {
+                self.noise_timer += 0x20000 >> shift;
+            }
+        }
+    }
+}
+
+impl Default for SPU {
+    fn default() -> SPU {
+        SPU::new()
     }
 }
+
+fn clamp16(value: i32) -> i16 {
+    value.clamp(i16::MIN as i32, i16::MAX as i32) as i16
+}
@@ -0,0 +1,180 @@
+// The reverb runs at 22.05 kHz on a ring buffer in sound RAM going from mBASE to the end of RAM.
+// Every step handles both sides with the configuration registers at 0x1C0-0x1FF:
+//   [mLSAME] = (Lin + [dLSAME]*vWALL - [mLSAME-2])*vIIR + [mLSAME-2]
+//   [mLDIFF] = (Lin + [dRDIFF]*vWALL - [mLDIFF-2])*vIIR + [mLDIFF-2]
+//   Lout = vCOMB1*[mLCOMB1] + vCOMB2*[mLCOMB2] + vCOMB3*[mLCOMB3] + vCOMB4*[mLCOMB4]
+//   Lout = Lout - vAPF1*[mLAPF1-dAPF1], [mLAPF1] = Lout, Lout = Lout*vAPF1 + [mLAPF1-dAPF1]
+//   Lout = Lout - vAPF2*[mLAPF2-dAPF2], [mLAPF2] = Lout, Lout = Lout*vAPF2 + [mLAPF2-dAPF2]
+// and the same for the right side with the R registers (the DIFF taps cross sides).
+// The hardware resamples through a 39 tap FIR, we average the input pair and hold the output instead.
+
+use super::{RAM_MASK, RAM_SIZE, clamp16};
+
+// register offsets from SPU_START
+pub const OUTPUT_VOLUME_LEFT: u32 = 0x184;
+pub const OUTPUT_VOLUME_RIGHT: u32 = 0x186;
+pub const BASE: u32 = 0x1A2;
+const D_APF1: u32 = 0x1C0;
+const D_APF2: u32 = 0x1C2;
+const V_IIR: u32 = 0x1C4;
+const V_COMB1: u32 = 0x1C6;
+const V_COMB2: u32 = 0x1C8;
+const V_COMB3: u32 = 0x1CA;
+const V_COMB4: u32 = 0x1CC;
+const V_WALL: u32 = 0x1CE;
+const V_APF1: u32 = 0x1D0;
+const V_APF2: u32 = 0x1D2;
+const M_SAME: u32 = 0x1D4;
+const M_COMB1: u32 = 0x1D8;
+const M_COMB2: u32 = 0x1DC;
+const D_SAME: u32 = 0x1E0;
+const M_DIFF: u32 = 0x1E4;
+const M_COMB3: u32 = 0x1E8;
+const M_COMB4: u32 = 0x1EC;
+const D_DIFF: u32 = 0x1F0;
+const M_APF1: u32 = 0x1F4;
+const M_APF2: u32 = 0x1F8;
+const V_IN: u32 = 0x1FC;
+
+#[derive(Copy, Clone)]
+pub struct Reverb {
+    // byte address of the buffer start, mBASE * 8
+    base: u32,
+    // byte address the buffer offsets are relative to, moves by one halfword every step
+    current_address: u32,
+    // the input of the first half of a step
+    pending_input: (i32, i32),
+    odd_sample: bool,
+    pub output: (i16, i16),
+}
+
+crate::state::snapshot!(Reverb { base, current_address, pending_input, odd_sample, output });
+
+impl Reverb {
+    pub fn new() -> Reverb {
+        Reverb {
+            base: 0,
+            current_address: 0,
+            pending_input: (0, 0),
+            odd_sample: false,
+            output: (0, 0),
+        }
+    }
+
+    pub fn set_base(&mut self, value: u16) {
+        self.base = (value as u32 * 8) & RAM_MASK;
+        self.current_address = self.base;
+    }
+
+    // called at 44.1 kHz with the mix of the reverb enabled voices, returns the output before vLOUT/vROUT
+    pub fn tick(
+        &mut self,
+        registers: &[u16; 512],
+        ram: &mut [u8],
+        input: (i32, i32),
+        write_enabled: bool,
+    ) -> (i16, i16) {
+        self.odd_sample = !self.odd_sample;
+        if self.odd_sample {
+            self.pending_input = input;
+            return self.output;
+        }
+
+        let input = ((self.pending_input.0 + input.0) / 2, (self.pending_input.1 + input.1) / 2);
+        let register = |offset: u32| registers[(offset >> 1) as usize];
+        let volume = |offset: u32| register(offset) as i16 as i32;
+        let in_left = mul(clamp16(input.0) as i32, volume(V_IN));
+        let in_right = mul(clamp16(input.1) as i32, volume(V_IN + 2));
+
+        let left = self.process(&register, ram, in_left, 0, write_enabled);
+        let right = self.process(&register, ram, in_right, 2, write_enabled);
+        self.output = (left, right);
+
+        let size = RAM_SIZE as u32 - self.base;
+        self.current_address = self.base + (self.current_address - self.base + 2) % size.max(2);
+        self.output
+    }
+
+    // side is 0 for the left registers and 2 for the right ones
+    fn process(
+        &self,
+        register: &dyn Fn(u32) -> u16,
+        ram: &mut [u8],
+        input: i32,
+        side: u32,
+        write_enabled: bool,
+    ) -> i16 {
+        let volume = |offset: u32| register(offset) as i16 as i32;
+        let address = |offset: u32| register(offset) as u32 * 8;
+        let other = 2 - side;
+
+        let wall = volume(V_WALL);
+        let iir = volume(V_IIR);
+
+        // same side reflection
+        let same = address(M_SAME + side);
+        let same_previous = self.read(ram, same.wrapping_sub(2)) as i32;
+        let reflected = self.read(ram, address(D_SAME + side)) as i32;
+        let value = mul(input + mul(reflected, wall) - same_previous, iir) + same_previous;
+        if write_enabled {
+            self.write(ram, same, clamp16(value));
+        }
+
+        // different side reflection
+        let diff = address(M_DIFF + side);
+        let diff_previous = self.read(ram, diff.wrapping_sub(2)) as i32;
+        let reflected = self.read(ram, address(D_DIFF + other)) as i32;
+        let value = mul(input + mul(reflected, wall) - diff_previous, iir) + diff_previous;
+        if write_enabled {
+            self.write(ram, diff, clamp16(value));
+        }
+
+        // early echo
+        let mut out = mul(volume(V_COMB1), self.read(ram, address(M_COMB1 + side)) as i32)
+            + mul(volume(V_COMB2), self.read(ram, address(M_COMB2 + side)) as i32)
+            + mul(volume(V_COMB3), self.read(ram, address(M_COMB3 + side)) as i32)
+            + mul(volume(V_COMB4), self.read(ram, address(M_COMB4 + side)) as i32);
+
+        // late reverb all pass filters
+        for (m_apf, d_apf, v_apf) in [(M_APF1, D_APF1, V_APF1), (M_APF2, D_APF2, V_APF2)] {
+            let apf = address(m_apf + side);
+            let delayed = self.read(ram, apf.wrapping_sub(address(d_apf))) as i32;
+            out = clamp16(out - mul(volume(v_apf), delayed)) as i32;
+            if write_enabled {
+                self.write(ram, apf, out as i16);
+            }
+            out = mul(out, volume(v_apf)) + delayed;
+        }
+
+        clamp16(out)
+    }
+
+    // buffer offsets wrap around inside mBASE..end of sound RAM
+    fn translate(&self, offset: u32) -> usize {
+        let size = (RAM_SIZE as u32 - self.base).max(2) as i64;
+        // offsets like mSAME-2 can go below zero
+        let relative =
+            ((self.current_address - self.base) as i64 + offset as i32 as i64).rem_euclid(size);
+        ((self.base + relative as u32) & RAM_MASK & !1) as usize
+    }
+
+    fn read(&self, ram: &[u8], offset: u32) -> i16 {
+        let address = self.translate(offset);
+        i16::from_le_bytes([ram[address], ram[address + 1]])
+    }
+
+    fn write(&self, ram: &mut [u8], offset: u32, value: i16) {
+        let address = self.translate(offset);
+        ram[address..address + 2].copy_from_slice(&value.to_le_bytes());
+    }
+}
+
+impl Default for Reverb {
+    fn default() -> Reverb {
+        Reverb::new()
+    }
+}
+
+fn mul(a: i32, b: i32) -> i32 {
+    (a * b) >> 15
+}
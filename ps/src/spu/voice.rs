@@ -0,0 +1,195 @@
+use super::RAM_MASK;
+use super::adsr::{Adsr, AdsrPhase, Volume};
+use super::gauss;
+
+// SPU-ADPCM blocks are 16 bytes: shift/filter, flags, then 28 4bit samples
+const BLOCK_SIZE: u32 = 16;
+const SAMPLES_PER_BLOCK: u32 = 28;
+
+const POS_TABLE: [i32; 5] = [0, 60, 115, 98, 122];
+const NEG_TABLE: [i32; 5] = [0, 0, -52, -55, -60];
+
+// block flags
+const LOOP_END: u8 = 0x01;
+const LOOP_REPEAT: u8 = 0x02;
+const LOOP_START: u8 = 0x04;
+
+#[derive(Copy, Clone)]
+pub struct Voice {
+    // 0x1F801C00 + n * 0x10
+    pub volume: [Volume; 2],
+    pub pitch: u16,
+    // in 8 byte units like the registers
+    pub start_address: u16,
+    pub adsr: Adsr,
+    pub repeat_address: u16,
+
+    // byte address of the block being played
+    pub current_address: u32,
+    // 4.12 fixed point position in the block, bits 4-11 index the gaussian table
+    counter: u32,
+    block_flags: u8,
+    // last 3 samples of the previous block followed by the 28 of the current one
+    samples: [i16; 3 + SAMPLES_PER_BLOCK as usize],
+    adpcm_history: (i32, i32),
+    decode_pending: bool,
+
+    // ADSR applied output of the last tick, feeds the pitch modulation of the next voice
+    pub output: i16,
+    // ENDX, set when a block with the loop end flag was played
+    pub ended: bool,
+}
+
+crate::state::snapshot!(Voice {
+    volume,
+    pitch,
+    start_address,
+    adsr,
+    repeat_address,
+    current_address,
+    counter,
+    block_flags,
+    samples,
+    adpcm_history,
+    decode_pending,
+    output,
+    ended,
+});
+
+impl Voice {
+    pub fn new() -> Voice {
+        Voice {
+            volume: [Volume::new(); 2],
+            pitch: 0,
+            start_address: 0,
+            adsr: Adsr::new(),
+            repeat_address: 0,
+            current_address: 0,
+            counter: 0,
+            block_flags: 0,
+            samples: [0; 3 + SAMPLES_PER_BLOCK as usize],
+            adpcm_history: (0, 0),
+            decode_pending: false,
+            output: 0,
+            ended: false,
+        }
+    }
+
+    pub fn key_on(&mut self) {
+        self.current_address = self.start_address as u32 * 8;
+        self.counter = 0;
+        self.adpcm_history = (0, 0);
+        self.samples = [0; 3 + SAMPLES_PER_BLOCK as usize];
+        self.decode_pending = true;
+        self.ended = false;
+        self.adsr.key_on();
+    }
+
+    pub fn key_off(&mut self) {
+        self.adsr.key_off();
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.adsr.phase != AdsrPhase::Off
+    }
+
+    // returns the ADSR scaled sample, before the voice volume
+    pub fn tick(&mut self, ram: &[u8], noise: Option<i16>, modulator: Option<i16>) -> i16 {
+        if self.decode_pending {
+            self.decode_block(ram);
+        }
+
+        let sample = match noise {
+            Some(level) => level,
+            None => self.interpolate(),
+        };
+        self.output = ((sample as i32 * self.adsr.level as i32) >> 15) as i16;
+
+        let mut step = self.pitch as u32;
+        if let Some(modulator) = modulator {
+            let factor = modulator as i64 + 0x8000;
+            step = ((self.pitch as i16 as i64 * factor) >> 15) as u32 & 0xffff;
+        }
+        self.counter += step.min(0x4000);
+
+        while self.counter >= SAMPLES_PER_BLOCK << 12 {
+            self.counter -= SAMPLES_PER_BLOCK << 12;
+            self.next_block();
+            self.decode_block(ram);
+        }
+
+        self.adsr.tick();
+        self.volume[0].tick();
+        self.volume[1].tick();
+        self.output
+    }
+
+    fn interpolate(&self) -> i16 {
+        let gauss = &gauss::TABLE;
+        let index = (self.counter >> 12) as usize;
+        let i = ((self.counter >> 4) & 0xff) as usize;
+        let s = &self.samples[index..index + 4];
+
+        let mut out = (gauss[0x0ff - i] as i32 * s[0] as i32) >> 15;
+        out += (gauss[0x1ff - i] as i32 * s[1] as i32) >> 15;
+        out += (gauss[0x100 + i] as i32 * s[2] as i32) >> 15;
+        out += (gauss[i] as i32 * s[3] as i32) >> 15;
+        out.clamp(i16::MIN as i32, i16::MAX as i32) as i16
+    }
+
+    // handles the flags of the block that just finished playing
+    fn next_block(&mut self) {
+        if self.block_flags & LOOP_END != 0 {
+            self.ended = true;
+            self.current_address = self.repeat_address as u32 * 8;
+            if self.block_flags & LOOP_REPEAT == 0 {
+                self.adsr.mute();
+            }
+        } else {
+            self.current_address = (self.current_address + BLOCK_SIZE) & RAM_MASK;
+        }
+    }
+
+    fn decode_block(&mut self, ram: &[u8]) {
+        self.decode_pending = false;
+        let address = self.current_address;
+        let block: [u8; BLOCK_SIZE as usize] =
+            std::array::from_fn(|i| ram[((address + i as u32) & RAM_MASK) as usize]);
+
+        // shift values 13..15 are reserved and behave like 9
+        let shift = match block[0] & 0xf {
+            shift @ 0..=12 => shift,
+            _ => 9,
+        };
+        let filter = ((block[0] >> 4) & 0x7).min(4) as usize;
+        self.block_flags = block[1];
+        if self.block_flags & LOOP_START != 0 {
+            self.repeat_address = (self.current_address / 8) as u16;
+        }
+
+        // keep the tail of the previous block for the interpolation
+        let last = self.samples.len();
+        self.samples.copy_within(last - 3..last, 0);
+
+        let (mut old, mut older) = self.adpcm_history;
+        for n in 0..SAMPLES_PER_BLOCK as usize {
+            let byte = block[2 + n / 2];
+            let nibble = (byte >> ((n & 1) * 4)) & 0xf;
+            let raw = ((nibble as i16) << 12) >> shift;
+
+            let sample =
+                raw as i32 + (old * POS_TABLE[filter] + older * NEG_TABLE[filter] + 32) / 64;
+            let sample = sample.clamp(i16::MIN as i32, i16::MAX as i32);
+            older = old;
+            old = sample;
+            self.samples[3 + n] = sample as i16;
+        }
+        self.adpcm_history = (old, older);
+    }
+}
+
+impl Default for Voice {
+    fn default() -> Voice {
+        Voice::new()
+    }
+}
@@ -0,0 +1,403 @@
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+
+pub mod rewind;
+
+// Save state layout, little endian:
+//   "PSXSTATE", u32 version
+//   sections: 4 byte tag, u32 payload length, payload
+// every device writes its own section so a reader can skip the ones it doesn't know,
+// a section that doesn't parse to the exact end is an error.
+pub const MAGIC: &[u8; 8] = b"PSXSTATE";
+pub const VERSION: u32 = 2;
+
+pub struct StateWriter {
+    data: Vec<u8>,
+}
+
+impl StateWriter {
+    pub fn new() -> StateWriter {
+        let mut data = MAGIC.to_vec();
+        data.extend_from_slice(&VERSION.to_le_bytes());
+        StateWriter { data }
+    }
+
+    // without the save state header, for files built from the same pieces
+    pub fn raw() -> StateWriter {
+        StateWriter { data: Vec::new() }
+    }
+
+    pub fn write_bytes(&mut self, bytes: &[u8]) {
+        self.data.extend_from_slice(bytes);
+    }
+
+    // writes a tagged section, its length is filled in once the payload is written
+    pub fn section(&mut self, tag: &[u8; 4], payload: impl FnOnce(&mut StateWriter)) {
+        self.data.extend_from_slice(tag);
+        let length = self.data.len();
+        self.data.extend_from_slice(&[0; 4]);
+        payload(self);
+        let size = (self.data.len() - length - 4) as u32;
+        self.data[length..length + 4].copy_from_slice(&size.to_le_bytes());
+    }
+
+    pub fn finish(self) -> Vec<u8> {
+        self.data
+    }
+}
+
+impl Default for StateWriter {
+    fn default() -> StateWriter {
+        StateWriter::new()
+    }
+}
+
+pub struct StateReader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> StateReader<'a> {
+    pub fn new(data: &'a [u8]) -> StateReader<'a> {
+        StateReader { data, position: 0 }
+    }
+
+    // checks the header, the reader is left at the first section
+    pub fn open(data: &'a [u8]) -> Result<StateReader<'a>, String> {
+        let mut reader = StateReader::new(data);
+        if reader.read_bytes(MAGIC.len()).ok() != Some(&MAGIC[..]) {
+            return Err("not a save state".to_string());
+        }
+        let version = u32::read(&mut reader)?;
+        if version != VERSION {
+            return Err(format!(
+                "save state version {} is not supported, expected {}",
+                version, VERSION
+            ));
+        }
+        Ok(reader)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.position == self.data.len()
+    }
+
+    pub fn read_bytes(&mut self, count: usize) -> Result<&'a [u8], String> {
+        // counts come from the file, a corrupt one can be anything
+        let bytes = self
+            .position
+            .checked_add(count)
+            .and_then(|end| self.data.get(self.position..end))
+            .ok_or_else(|| format!("save state is truncated at byte {}", self.position))?;
+        self.position += count;
+        Ok(bytes)
+    }
+
+    // the next section's tag and payload
+    pub fn next_section(&mut self) -> Result<([u8; 4], StateReader<'a>), String> {
+        let tag: [u8; 4] = self.read_bytes(4)?.try_into().unwrap();
+        let size = u32::read(self)? as usize;
+        Ok((tag, StateReader::new(self.read_bytes(size)?)))
+    }
+
+    // a section must be read to its end, leftover bytes mean the layout changed
+    pub fn finish(&self) -> Result<(), String> {
+        match self.data.len() - self.position {
+            0 => Ok(()),
+            left => Err(format!("{} unexpected bytes at the end", left)),
+        }
+    }
+}
+
+// everything that goes into a save state
+pub trait Snapshot {
+    fn save(&self, state: &mut StateWriter);
+    fn load(&mut self, state: &mut StateReader) -> Result<(), String>;
+}
+
+// for values that are rebuilt on load rather than loaded in place
+pub trait FromState: Sized {
+    fn read(state: &mut StateReader) -> Result<Self, String>;
+}
+
+impl<T: Snapshot + Default> FromState for T {
+    fn read(state: &mut StateReader) -> Result<T, String> {
+        let mut value = T::default();
+        value.load(state)?;
+        Ok(value)
+    }
+}
+
+macro_rules! snapshot_integers {
+    ($($t:ty),*) => {$(
+        impl Snapshot for $t {
+            fn save(&self, state: &mut StateWriter) {
+                state.write_bytes(&self.to_le_bytes());
+            }
+            fn load(&mut self, state: &mut StateReader) -> Result<(), String> {
+                *self = <$t>::from_le_bytes(state.read_bytes(size_of::<$t>())?.try_into().unwrap());
+                Ok(())
+            }
+        }
+    )*};
+}
+snapshot_integers!(u8, u16, u32, u64, i8, i16, i32, i64);
+
+// stored as 64 bits so states move between platforms
+impl Snapshot for usize {
+    fn save(&self, state: &mut StateWriter) {
+        (*self as u64).save(state);
+    }
+    fn load(&mut self, state: &mut StateReader) -> Result<(), String> {
+        *self = usize::try_from(u64::read(state)?).map_err(|e| e.to_string())?;
+        Ok(())
+    }
+}
+
+impl Snapshot for bool {
+    fn save(&self, state: &mut StateWriter) {
+        (*self as u8).save(state);
+    }
+    fn load(&mut self, state: &mut StateReader) -> Result<(), String> {
+        *self = match u8::read(state)? {
+            0 => false,
+            1 => true,
+            value => return Err(format!("bad boolean {}", value)),
+        };
+        Ok(())
+    }
+}
+
+impl<T: Snapshot, const N: usize> Snapshot for [T; N] {
+    fn save(&self, state: &mut StateWriter) {
+        for value in self {
+            value.save(state);
+        }
+    }
+    fn load(&mut self, state: &mut StateReader) -> Result<(), String> {
+        for value in self {
+            value.load(state)?;
+        }
+        Ok(())
+    }
+}
+
+// RAM sized buffers, the size is fixed by the hardware so a different one is an error
+impl Snapshot for Box<[u8]> {
+    fn save(&self, state: &mut StateWriter) {
+        self.len().save(state);
+        state.write_bytes(self);
+    }
+    fn load(&mut self, state: &mut StateReader) -> Result<(), String> {
+        let length = usize::read(state)?;
+        if length != self.len() {
+            return Err(format!("buffer is {} bytes, expected {}", length, self.len()));
+        }
+        self.copy_from_slice(state.read_bytes(length)?);
+        Ok(())
+    }
+}
+
+impl<T: Snapshot + Default> Snapshot for Vec<T> {
+    fn save(&self, state: &mut StateWriter) {
+        self.len().save(state);
+        for value in self {
+            value.save(state);
+        }
+    }
+    fn load(&mut self, state: &mut StateReader) -> Result<(), String> {
+        let length = usize::read(state)?;
+        self.clear();
+        for _ in 0..length {
+            self.push(T::read(state)?);
+        }
+        Ok(())
+    }
+}
+
+impl<T: Snapshot + Default> Snapshot for VecDeque<T> {
+    fn save(&self, state: &mut StateWriter) {
+        self.len().save(state);
+        for value in self {
+            value.save(state);
+        }
+    }
+    fn load(&mut self, state: &mut StateReader) -> Result<(), String> {
+        let length = usize::read(state)?;
+        self.clear();
+        for _ in 0..length {
+            self.push_back(T::read(state)?);
+        }
+        Ok(())
+    }
+}
+
+impl<T: Snapshot + Default> Snapshot for Option<T> {
+    fn save(&self, state: &mut StateWriter) {
+        self.is_some().save(state);
+        if let Some(value) = self {
+            value.save(state);
+        }
+    }
+    fn load(&mut self, state: &mut StateReader) -> Result<(), String> {
+        *self = if bool::read(state)? { Some(T::read(state)?) } else { None };
+        Ok(())
+    }
+}
+
+impl<A: Snapshot, B: Snapshot> Snapshot for (A, B) {
+    fn save(&self, state: &mut StateWriter) {
+        self.0.save(state);
+        self.1.save(state);
+    }
+    fn load(&mut self, state: &mut StateReader) -> Result<(), String> {
+        self.0.load(state)?;
+        self.1.load(state)
+    }
+}
+
+impl Snapshot for String {
+    fn save(&self, state: &mut StateWriter) {
+        self.len().save(state);
+        state.write_bytes(self.as_bytes());
+    }
+    fn load(&mut self, state: &mut StateReader) -> Result<(), String> {
+        let length = usize::read(state)?;
+        *self = String::from_utf8(state.read_bytes(length)?.to_vec()).map_err(|e| e.to_string())?;
+        Ok(())
+    }
+}
+
+// saves and loads the listed fields in order, used inside each module so private fields are reachable
+macro_rules! snapshot {
+    ($type:ty { $($field:ident),* $(,)? }) => {
+        impl $crate::state::Snapshot for $type {
+            fn save(&self, state: &mut $crate::state::StateWriter) {
+                $($crate::state::Snapshot::save(&self.$field, state);)*
+            }
+            fn load(&mut self, state: &mut $crate::state::StateReader) -> Result<(), String> {
+                $($crate::state::Snapshot::load(&mut self.$field, state)?;)*
+                Ok(())
+            }
+        }
+    };
+}
+pub(crate) use snapshot;
+
+// fieldless enums are stored as their index in the listed variants
+macro_rules! snapshot_enum {
+    ($type:ident { $($variant:ident),* $(,)? }) => {
+        impl $crate::state::Snapshot for $type {
+            fn save(&self, state: &mut $crate::state::StateWriter) {
+                let variants = [$($type::$variant),*];
+                let index = variants.iter().position(|variant| variant == self).unwrap() as u8;
+                $crate::state::Snapshot::save(&index, state);
+            }
+            fn load(&mut self, state: &mut $crate::state::StateReader) -> Result<(), String> {
+                let variants = [$($type::$variant),*];
+                let index = <u8 as $crate::state::FromState>::read(state)?;
+                *self = *variants
+                    .get(index as usize)
+                    .ok_or_else(|| format!("bad {} {}", stringify!($type), index))?;
+                Ok(())
+            }
+        }
+    };
+}
+pub(crate) use snapshot_enum;
+
+// FNV-1a, to tell BIOS images and discs apart
+pub fn hash(data: &[u8]) -> u64 {
+    fnv(0xcbf29ce484222325, data)
+}
+
+// the same hash read from a file a piece at a time, disc images are hundreds of MiB
+pub fn hash_file(path: &Path) -> io::Result<u64> {
+    let mut file = File::open(path)?;
+    let mut buffer = vec![0; 64 * 1024];
+    let mut hash = 0xcbf29ce484222325;
+    loop {
+        match file.read(&mut buffer)? {
+            0 => return Ok(hash),
+            count => hash = fnv(hash, &buffer[..count]),
+        }
+    }
+}
+
+fn fnv(hash: u64, data: &[u8]) -> u64 {
+    data.iter().fold(hash, |hash, &byte| (hash ^ byte as u64).wrapping_mul(0x100000001b3))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::mipsr3000::run_instruction;
+    use crate::map::BIOS_SIZE;
+    use crate::playstation::PlayStation;
+
+    // counts up a word in RAM forever
+    fn machine() -> PlayStation {
+        let mut bios = vec![0; BIOS_SIZE as usize];
+        let program: [u32; 4] = [0x25080001, 0xac080100, 0x0bf00000, 0];
+        for (i, word) in program.iter().enumerate() {
+            bios[i * 4..i * 4 + 4].copy_from_slice(&word.to_le_bytes());
+        }
+        let mut ps = PlayStation::new(bios.into_boxed_slice());
+        for _ in 0..1000 {
+            run_instruction(&mut ps);
+        }
+        ps
+    }
+
+    #[test]
+    fn save_load_save_is_identical() {
+        let state = machine().save_state();
+        let mut other = PlayStation::new(machine().bios.data.clone());
+        other.load_state(&state).unwrap();
+        assert_eq!(other.save_state(), state);
+        assert_eq!(other.ram.read32(0x100), 250);
+    }
+
+    #[test]
+    fn bad_states_leave_the_machine_alone() {
+        let state = machine().save_state();
+        let mut ps = machine();
+        for _ in 0..10 {
+            run_instruction(&mut ps);
+        }
+        let before = ps.save_state();
+
+        let mut huge_section = state.clone();
+        huge_section[MAGIC.len() + 8..MAGIC.len() + 12].copy_from_slice(&u32::MAX.to_le_bytes());
+        let mut bad_version = state.clone();
+        bad_version[MAGIC.len()] ^= 0xff;
+        for bad in [&state[..state.len() - 10], &state[..4], &huge_section, &bad_version] {
+            assert!(ps.load_state(bad).is_err());
+            assert_eq!(ps.save_state(), before);
+        }
+    }
+
+    #[test]
+    fn file_hash_matches() {
+        let data: Vec<u8> = (0..200_000u32).map(|i| (i * 7 / 3) as u8).collect();
+        let path = std::env::temp_dir().join(format!("ps-hash-{}", std::process::id()));
+        std::fs::write(&path, &data).unwrap();
+        let file_hash = hash_file(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(file_hash.unwrap(), hash(&data));
+    }
+
+    #[test]
+    fn huge_lengths_are_errors() {
+        let mut writer = StateWriter::raw();
+        u64::MAX.save(&mut writer);
+        let data = writer.finish();
+        assert!(String::read(&mut StateReader::new(&data)).is_err());
+        assert!(StateReader::new(&data).read_bytes(usize::MAX).is_err());
+        let mut reader = StateReader::new(&data);
+        reader.read_bytes(1).unwrap();
+        assert!(reader.read_bytes(usize::MAX).is_err());
+    }
+}
@@ -0,0 +1,282 @@
+use std::collections::VecDeque;
+
+// a new keyframe every second, deltas against an old keyframe grow as the game moves on
+const KEYFRAME_INTERVAL: usize = 60;
+
+// Save states of the last frames, oldest first. Each group starts with a full keyframe and the
+// following states are stored as their XOR against it, with the runs of zeros squeezed out.
+// Once the budget is exceeded whole groups are dropped from the old end, the newest one always stays.
+pub struct RewindBuffer {
+    budget: usize,
+    groups: VecDeque<Vec<Entry>>,
+    size: usize,
+}
+
+struct Entry {
+    // machine cycle count when the state was taken
+    cycle: u64,
+    // the keyframe itself for the first entry of a group, a delta against it for the others
+    data: Vec<u8>,
+}
+
+impl RewindBuffer {
+    // budget in bytes
+    pub fn new(budget: usize) -> RewindBuffer {
+        RewindBuffer { budget, groups: VecDeque::new(), size: 0 }
+    }
+
+    pub fn push(&mut self, state: &[u8], cycle: u64) {
+        let data = match self.groups.back() {
+            Some(group) if group.len() < KEYFRAME_INTERVAL => encode(&group[0].data, state),
+            _ => {
+                self.groups.push_back(Vec::new());
+                state.to_vec()
+            }
+        };
+        self.size += data.len();
+        self.groups.back_mut().unwrap().push(Entry { cycle, data });
+
+        while self.size > self.budget && self.groups.len() > 1 {
+            let group = self.groups.pop_front().unwrap();
+            self.size -= group.iter().map(|entry| entry.data.len()).sum::<usize>();
+        }
+    }
+
+    // number of states held
+    pub fn len(&self) -> usize {
+        self.groups.iter().map(Vec::len).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.groups.is_empty()
+    }
+
+    // bytes used by the states
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    pub fn newest_cycle(&self) -> Option<u64> {
+        self.groups.back().and_then(|group| group.last()).map(|entry| entry.cycle)
+    }
+
+    // the state `frames` states back, 1 being the newest. the newer ones are dropped,
+    // the returned one stays so rewinding again starts from it
+    pub fn rewind(&mut self, frames: usize) -> Option<Vec<u8>> {
+        let keep = self.len().saturating_sub(frames.max(1) - 1).max(1);
+        self.truncate(keep);
+        self.newest()
+    }
+
+    // the newest state taken at or before the cycle, newer ones are dropped
+    pub fn rewind_to(&mut self, cycle: u64) -> Option<Vec<u8>> {
+        let keep = self.groups.iter().flatten().take_while(|entry| entry.cycle <= cycle).count();
+        if keep == 0 {
+            return None;
+        }
+        self.truncate(keep);
+        self.newest()
+    }
+
+    pub fn clear(&mut self) {
+        self.groups.clear();
+        self.size = 0;
+    }
+
+    fn truncate(&mut self, mut keep: usize) {
+        let mut groups = VecDeque::new();
+        for mut group in self.groups.drain(..) {
+            if keep == 0 {
+                break;
+            }
+            group.truncate(keep);
+            keep -= group.len();
+            groups.push_back(group);
+        }
+        self.size = groups.iter().flatten().map(|entry| entry.data.len()).sum();
+        self.groups = groups;
+    }
+
+    fn newest(&self) -> Option<Vec<u8>> {
+        let group = self.groups.back()?;
+        let entry = group.last()?;
+        Some(if group.len() == 1 {
+            entry.data.clone()
+        } else {
+            decode(&group[0].data, &entry.data)
+        })
+    }
+}
+
+// Deltas are the state length followed by (zero run, literal count, literal bytes) of the
+// XOR against the keyframe, counts as LEB128. Bytes past the end of the keyframe XOR with 0.
+fn encode(keyframe: &[u8], state: &[u8]) -> Vec<u8> {
+    let xor = |i: usize| state[i] ^ keyframe.get(i).copied().unwrap_or(0);
+    let mut delta = Vec::new();
+    write_count(&mut delta, state.len());
+
+    let mut i = 0;
+    while i < state.len() {
+        let zeros = (i..state.len()).take_while(|&j| xor(j) == 0).count();
+        i += zeros;
+        // a literal run goes on until there are a few zeros in a row, shorter gaps cost more than they save
+        let start = i;
+        while i < state.len() && (i..state.len().min(i + 4)).any(|j| xor(j) != 0) {
+            i += 1;
+        }
+        write_count(&mut delta, zeros);
+        write_count(&mut delta, i - start);
+        delta.extend((start..i).map(xor));
+    }
+    delta
+}
+
+fn decode(keyframe: &[u8], delta: &[u8]) -> Vec<u8> {
+    let mut position = 0;
+    let length = read_count(delta, &mut position);
+    let mut state: Vec<u8> = (0..length).map(|i| keyframe.get(i).copied().unwrap_or(0)).collect();
+
+    let mut i = 0;
+    while position < delta.len() {
+        i += read_count(delta, &mut position);
+        let literals = read_count(delta, &mut position);
+        for byte in &delta[position..position + literals] {
+            state[i] ^= byte;
+            i += 1;
+        }
+        position += literals;
+    }
+    state
+}
+
+fn write_count(data: &mut Vec<u8>, mut count: usize) {
+    while count >= 0x80 {
+        data.push(count as u8 | 0x80);
+        count >>= 7;
+    }
+    data.push(count as u8);
+}
+
+fn read_count(data: &[u8], position: &mut usize) -> usize {
+    let mut count = 0;
+    let mut shift = 0;
+    loop {
+        let byte = data[*position];
+        *position += 1;
+        count |= ((byte & 0x7f) as usize) << shift;
+        if byte & 0x80 == 0 {
+            return count;
+        }
+        shift += 7;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // bytes that change a little from one state to the next
+    fn state(seed: u8, length: usize) -> Vec<u8> {
+        (0..length)
+            .map(|i| if i % 97 < 3 { seed.wrapping_add(i as u8) } else { (i / 13) as u8 })
+            .collect()
+    }
+
+    fn round_trip(keyframe: &[u8], state: &[u8]) -> Vec<u8> {
+        let delta = encode(keyframe, state);
+        assert_eq!(decode(keyframe, &delta), state);
+        delta
+    }
+
+    #[test]
+    fn longer_and_shorter_than_the_keyframe() {
+        let keyframe = state(1, 1000);
+        round_trip(&keyframe, &state(2, 1500));
+        round_trip(&keyframe, &state(3, 400));
+        round_trip(&keyframe, &[]);
+        round_trip(&[], &state(4, 300));
+    }
+
+    #[test]
+    fn identical_state_is_tiny() {
+        let keyframe = state(5, 10_000);
+        let delta = round_trip(&keyframe, &keyframe);
+        // the length and one run of zeros
+        assert!(delta.len() <= 8, "{} bytes", delta.len());
+    }
+
+    #[test]
+    fn long_runs() {
+        let keyframe = vec![0; 5000];
+        // a 300 byte literal run between zero runs of 200 and 4000 bytes
+        let mut changed = keyframe.clone();
+        changed[200..500].fill(0xaa);
+        round_trip(&keyframe, &changed);
+        round_trip(&keyframe, &vec![0x55; 5000]);
+
+        let mut count = Vec::new();
+        for value in [0, 127, 128, 300, 16_383, 16_384, usize::MAX >> 1] {
+            count.clear();
+            write_count(&mut count, value);
+            let mut position = 0;
+            assert_eq!(read_count(&count, &mut position), value);
+            assert_eq!(position, count.len());
+        }
+    }
+
+    fn filled(count: usize) -> RewindBuffer {
+        let mut buffer = RewindBuffer::new(usize::MAX);
+        for i in 0..count {
+            buffer.push(&state(i as u8, 2000), i as u64 * 100);
+        }
+        buffer
+    }
+
+    #[test]
+    fn rewind_returns_the_states() {
+        let mut buffer = filled(150);
+        assert_eq!(buffer.len(), 150);
+        assert_eq!(buffer.rewind(1), Some(state(149, 2000)));
+        assert_eq!(buffer.len(), 150);
+        // 61 back crosses into the previous group, its keyframe is the newest then
+        assert_eq!(buffer.rewind(30), Some(state(120, 2000)));
+        assert_eq!(buffer.rewind(61), Some(state(60, 2000)));
+        assert_eq!(buffer.len(), 61);
+        // further back than there is stops at the oldest, which stays
+        assert_eq!(buffer.rewind(1000), Some(state(0, 2000)));
+        assert_eq!(buffer.len(), 1);
+        assert_eq!(buffer.size(), 2000);
+    }
+
+    #[test]
+    fn rewind_to_cycles() {
+        let mut buffer = filled(100);
+        assert_eq!(buffer.rewind_to(10_000), Some(state(99, 2000)));
+        assert_eq!(buffer.rewind_to(5_050), Some(state(50, 2000)));
+        assert_eq!(buffer.newest_cycle(), Some(5_000));
+        assert_eq!(buffer.rewind_to(0), Some(state(0, 2000)));
+        assert_eq!(buffer.len(), 1);
+
+        let mut emptied = filled(10);
+        emptied.truncate(0);
+        assert!(emptied.is_empty() && emptied.size() == 0);
+        assert_eq!(emptied.rewind(1), None);
+        let mut buffer = RewindBuffer::new(usize::MAX);
+        buffer.push(&state(0, 100), 500);
+        assert_eq!(buffer.rewind_to(499), None);
+        assert_eq!(buffer.len(), 1);
+    }
+
+    #[test]
+    fn budget_drops_old_groups() {
+        let mut buffer = RewindBuffer::new(5000);
+        for i in 0..200 {
+            buffer.push(&state(i as u8, 4000), i as u64);
+        }
+        // only the newest group fits, it always stays even when over budget
+        assert_eq!(buffer.len(), 200 - 180);
+        assert_eq!(buffer.rewind(1), Some(state(199, 4000)));
+        let size: usize = buffer.groups.iter().flatten().map(|entry| entry.data.len()).sum();
+        assert_eq!(buffer.size(), size);
+    }
+}
@@ -0,0 +1,353 @@
+use std::cell::RefCell;
+use std::fmt::{self, Write as _};
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::mem;
+use std::ops::{Range, RangeInclusive};
+use std::path::Path;
+
+use crate::cpu::instruction::Instruction;
+use crate::cpu::mipsr3000::{self, Cpu};
+use crate::cpu::utils::register_name;
+use crate::playstation::{PlayStation, mask_region};
+
+// a read or write the CPU made through the bus, the value is what went over it
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct BusAccess {
+    pub address: u32,
+    pub value: u32,
+    // in bytes
+    pub size: u8,
+    pub write: bool,
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Format {
+    // index, pc, opcode, disassembly, the registers the instruction changed and its bus accesses
+    Full,
+    // "index: 0xPC", the lines of the old pc_trace_rust.txt
+    Pc,
+    // pc, opcode, disassembly and every register after the instruction, the way reference
+    // emulators log their runs, so both sides can be diffed line by line
+    Registers,
+}
+
+impl Format {
+    pub fn parse(name: &str) -> Option<Format> {
+        match name {
+            "full" => Some(Format::Full),
+            "pc" => Some(Format::Pc),
+            "registers" => Some(Format::Registers),
+            _ => None,
+        }
+    }
+}
+
+// which instructions make it to the trace, None lets everything through
+#[derive(Clone, Default, Debug)]
+pub struct Filter {
+    pub pc: Option<RangeInclusive<u32>>,
+    // indexes of the instructions run since the trace started
+    pub instructions: Option<Range<u64>>,
+    // only the instructions that read or write in the range, physical addresses
+    pub address: Option<RangeInclusive<u32>>,
+}
+
+impl Filter {
+    fn passes(&self, pc: u32, index: u64, accesses: &[BusAccess]) -> bool {
+        self.pc.as_ref().is_none_or(|range| range.contains(&pc))
+            && self.instructions.as_ref().is_none_or(|range| range.contains(&index))
+            && self.address.as_ref().is_none_or(|range| {
+                accesses.iter().any(|access| range.contains(&mask_region(access.address)))
+            })
+    }
+}
+
+// Runs instructions in place of run_instruction and writes a line for those that get through
+// the filter. The output is buffered, flush() or dropping the tracer writes the rest.
+pub struct Tracer {
+    writer: BufWriter<Box<dyn Write>>,
+    format: Format,
+    filter: Filter,
+    // instructions run since the trace started, traced or not
+    index: u64,
+    // lines written
+    lines: u64,
+    // kept between instructions so the bus log doesn't allocate every time
+    accesses: Vec<BusAccess>,
+}
+
+impl Tracer {
+    pub fn new(output: Box<dyn Write>, format: Format, filter: Filter) -> Tracer {
+        Tracer {
+            writer: BufWriter::new(output),
+            format,
+            filter,
+            index: 0,
+            lines: 0,
+            accesses: Vec::new(),
+        }
+    }
+
+    pub fn create(path: &Path, format: Format, filter: Filter) -> io::Result<Tracer> {
+        Ok(Tracer::new(Box::new(File::create(path)?), format, filter))
+    }
+
+    pub fn index(&self) -> u64 {
+        self.index
+    }
+
+    pub fn lines(&self) -> u64 {
+        self.lines
+    }
+
+    // past the end of the instruction window, nothing more will be traced
+    pub fn finished(&self) -> bool {
+        self.filter.instructions.as_ref().is_some_and(|range| self.index >= range.end)
+    }
+
+    pub fn step(&mut self, ps: &mut PlayStation) -> io::Result<()> {
+        let before = registers(&ps.cpu);
+        self.accesses.clear();
+        ps.bus_log = Some(RefCell::new(mem::take(&mut self.accesses)));
+        mipsr3000::run_instruction(ps);
+        self.accesses = ps.bus_log.take().map(RefCell::into_inner).unwrap_or_default();
+
+        // current_pc is the instruction that ran, a sideload can move pc before it.
+        // the first access is the fetch, there is none when the pc was misaligned
+        let pc = ps.cpu.current_pc;
+        let opcode = if pc.is_multiple_of(4) && !self.accesses.is_empty() {
+            Some(self.accesses.remove(0).value)
+        } else {
+            None
+        };
+
+        let index = self.index;
+        self.index += 1;
+        if !self.filter.passes(pc, index, &self.accesses) {
+            return Ok(());
+        }
+        self.lines += 1;
+
+        let mut line = String::new();
+        self.format_line(&mut line, index, pc, opcode, &before, &registers(&ps.cpu))
+            .expect("formatting into a string");
+        writeln!(self.writer, "{}", line.trim_end())
+    }
+
+    fn format_line(
+        &self,
+        line: &mut String,
+        index: u64,
+        pc: u32,
+        opcode: Option<u32>,
+        before: &[u32; 34],
+        after: &[u32; 34],
+    ) -> fmt::Result {
+        let disassembly = match opcode {
+            Some(opcode) => Instruction(opcode).operation().to_string(),
+            None => "address error".to_string(),
+        };
+        match self.format {
+            Format::Pc => write!(line, "{}: 0x{:08X}", index, pc),
+            Format::Full => {
+                write!(
+                    line,
+                    "{:>9} {:08x}: {:08x}  {:<32}",
+                    index,
+                    pc,
+                    opcode.unwrap_or(0),
+                    disassembly
+                )?;
+                for (i, (old, new)) in before.iter().zip(after).enumerate() {
+                    if old != new {
+                        write!(line, " {}={:08x}", name(i), new)?;
+                    }
+                }
+                for access in &self.accesses {
+                    let (kind, arrow) = if access.write { ('w', "<-") } else { ('r', "->") };
+                    let width = access.size as usize * 2;
+                    write!(
+                        line,
+                        " [{}{} {:08x} {} {:0width$x}]",
+                        kind,
+                        access.size * 8,
+                        access.address,
+                        arrow,
+                        access.value
+                    )?;
+                }
+                Ok(())
+            }
+            Format::Registers => {
+                write!(line, "{:08x}: {:08x} {:<32}", pc, opcode.unwrap_or(0), disassembly)?;
+                // $zero never changes
+                for (i, value) in after.iter().enumerate().skip(1) {
+                    write!(line, " {}={:08x}", name(i), value)?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+// the general purpose registers, then hi and lo
+fn registers(cpu: &Cpu) -> [u32; 34] {
+    let mut registers = [0; 34];
+    registers[..32].copy_from_slice(&cpu.registers);
+    registers[32] = cpu.hi;
+    registers[33] = cpu.lo;
+    registers
+}
+
+fn name(index: usize) -> &'static str {
+    match index {
+        32 => "hi",
+        33 => "lo",
+        _ => &register_name(index as u8)[1..],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::map::BIOS_SIZE;
+    use std::rc::Rc;
+
+    // the lines written so far, the tracer owns its writer
+    #[derive(Clone, Default)]
+    struct Output(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for Output {
+        fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().write(data)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Output {
+        fn lines(&self) -> Vec<String> {
+            String::from_utf8(self.0.borrow().clone())
+                .unwrap()
+                .lines()
+                .map(str::to_string)
+                .collect()
+        }
+    }
+
+    // counts up a word in RAM forever, like the state tests' machine
+    fn machine() -> PlayStation {
+        let mut bios = vec![0; BIOS_SIZE as usize];
+        let program: [u32; 4] = [0x25080001, 0xac080100, 0x0bf00000, 0];
+        for (i, word) in program.iter().enumerate() {
+            bios[i * 4..i * 4 + 4].copy_from_slice(&word.to_le_bytes());
+        }
+        PlayStation::new(bios.into_boxed_slice())
+    }
+
+    fn trace(format: Format, filter: Filter, instructions: usize) -> Vec<String> {
+        let output = Output::default();
+        let mut tracer = Tracer::new(Box::new(output.clone()), format, filter);
+        let mut ps = machine();
+        for _ in 0..instructions {
+            tracer.step(&mut ps).unwrap();
+        }
+        tracer.flush().unwrap();
+        assert_eq!(tracer.index(), instructions as u64);
+        assert_eq!(tracer.lines(), output.lines().len() as u64);
+        output.lines()
+    }
+
+    fn pcs(lines: &[String]) -> Vec<&str> {
+        lines.iter().map(|line| line.split(": ").next().unwrap()).collect()
+    }
+
+    #[test]
+    fn full_format() {
+        let lines = trace(Format::Full, Filter::default(), 5);
+        assert_eq!(
+            lines,
+            [
+                "        0 bfc00000: 25080001  addiu $t0, $t0, 0x0001           t0=00000001",
+                "        1 bfc00004: ac080100  sw $t0, 0x0100($zero)            [w32 00000100 <- 00000001]",
+                "        2 bfc00008: 0bf00000  j 0x0fc00000",
+                "        3 bfc0000c: 00000000  noop",
+                "        4 bfc00000: 25080001  addiu $t0, $t0, 0x0001           t0=00000002",
+            ]
+        );
+    }
+
+    #[test]
+    fn pc_format() {
+        let lines = trace(Format::Pc, Filter::default(), 5);
+        assert_eq!(
+            lines,
+            ["0: 0xBFC00000", "1: 0xBFC00004", "2: 0xBFC00008", "3: 0xBFC0000C", "4: 0xBFC00000"]
+        );
+    }
+
+    #[test]
+    fn registers_format() {
+        let lines = trace(Format::Registers, Filter::default(), 5);
+        assert_eq!(lines.len(), 5);
+        assert!(lines[0].starts_with(
+            "bfc00000: 25080001 addiu $t0, $t0, 0x0001           at=00000000 v0=00000000"
+        ));
+        assert!(lines[1].starts_with("bfc00004: ac080100 sw $t0, 0x0100($zero)            at="));
+        assert!(lines[4].contains(" t7=00000000 s0=00000000 "));
+        for (line, t0) in lines.iter().zip([1, 1, 1, 1, 2]) {
+            // every register but $zero, hi and lo last
+            assert_eq!(line.matches('=').count(), 33);
+            assert!(!line.contains("zero="));
+            assert!(line.contains(&format!(" t0={:08x} ", t0)));
+            assert!(line.ends_with(" ra=00000000 hi=00000000 lo=00000000"));
+        }
+    }
+
+    #[test]
+    fn pc_filter() {
+        let filter = Filter { pc: Some(0xbfc00004..=0xbfc00008), ..Filter::default() };
+        let lines = trace(Format::Pc, filter, 10);
+        assert_eq!(pcs(&lines), ["1", "2", "5", "6", "9"]);
+        assert_eq!(lines[0], "1: 0xBFC00004");
+    }
+
+    #[test]
+    fn instruction_window() {
+        let filter = Filter { instructions: Some(3..6), ..Filter::default() };
+        let output = Output::default();
+        let mut tracer = Tracer::new(Box::new(output.clone()), Format::Pc, filter);
+        let mut ps = machine();
+        while !tracer.finished() {
+            tracer.step(&mut ps).unwrap();
+        }
+        tracer.flush().unwrap();
+        assert_eq!(tracer.index(), 6);
+        assert_eq!(output.lines(), ["3: 0xBFC0000C", "4: 0xBFC00000", "5: 0xBFC00004"]);
+    }
+
+    #[test]
+    fn address_filter() {
+        // the store, the fetches don't count as accesses
+        let stores = Filter { address: Some(0x100..=0x103), ..Filter::default() };
+        assert_eq!(pcs(&trace(Format::Pc, stores, 10)), ["1", "5", "9"]);
+        let next_word = Filter { address: Some(0x104..=0x107), ..Filter::default() };
+        assert!(trace(Format::Pc, next_word, 10).is_empty());
+        let bios = Filter { address: Some(0x1fc00000..=0x1fc0000f), ..Filter::default() };
+        assert!(trace(Format::Pc, bios, 10).is_empty());
+    }
+
+    #[test]
+    fn filters_combine() {
+        let filter =
+            Filter { pc: Some(0xbfc00000..=0xbfc00004), instructions: Some(2..9), address: None };
+        assert_eq!(pcs(&trace(Format::Pc, filter, 10)), ["4", "5", "8"]);
+    }
+}
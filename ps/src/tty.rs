@@ -0,0 +1,66 @@
+// the text kept for the frontends, the oldest half goes once it gets that long
+const LIMIT: usize = 1024 * 1024;
+
+// What the BIOS and programs print, through the kernel putchar and puts functions and the DUART
+// on the expansion port. The frontends show text() or pick up the new part with take().
+pub struct Tty {
+    text: String,
+    // end of the text take() handed out last
+    taken: usize,
+    // puts may print each character through putchar again, those are already in.
+    // the return address of the puts call until it returns
+    pub(crate) muted_until: Option<u32>,
+}
+
+impl Tty {
+    pub fn new() -> Tty {
+        Tty { text: String::new(), taken: 0, muted_until: None }
+    }
+
+    pub fn push(&mut self, byte: u8) {
+        // line ends are \r\n on the serial port
+        if byte == b'\r' {
+            return;
+        }
+        self.text.push(byte as char);
+        if self.text.len() > LIMIT {
+            let mut cut = self.text.len() / 2;
+            while !self.text.is_char_boundary(cut) {
+                cut += 1;
+            }
+            self.text.drain(..cut);
+            self.taken = self.taken.saturating_sub(cut);
+        }
+    }
+
+    pub fn push_str(&mut self, text: &[u8]) {
+        for &byte in text {
+            self.push(byte);
+        }
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    // what was printed since the last call, None when nothing was
+    pub fn take(&mut self) -> Option<&str> {
+        if self.taken == self.text.len() {
+            return None;
+        }
+        let start = self.taken;
+        self.taken = self.text.len();
+        Some(&self.text[start..])
+    }
+
+    pub fn clear(&mut self) {
+        self.text.clear();
+        self.taken = 0;
+    }
+}
+
+impl Default for Tty {
+    fn default() -> Tty {
+        Tty::new()
+    }
+}
@@ -0,0 +1,69 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use crate::cdrom::xa::OUTPUT_RATE;
+
+const HEADER_SIZE: u32 = 44;
+const CHANNELS: u16 = 2;
+const BITS_PER_SAMPLE: u16 = 16;
+
+// 16 bit stereo PCM, at the SPU rate unless told otherwise, the chunk sizes are filled in by finish()
+pub struct WavWriter {
+    writer: BufWriter<File>,
+    rate: u32,
+    samples: u32,
+}
+
+impl WavWriter {
+    pub fn create(path: &Path) -> io::Result<WavWriter> {
+        WavWriter::create_with_rate(path, OUTPUT_RATE)
+    }
+
+    pub fn create_with_rate(path: &Path, rate: u32) -> io::Result<WavWriter> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        write_header(&mut writer, rate, 0)?;
+        Ok(WavWriter { writer, rate, samples: 0 })
+    }
+
+    pub fn write_samples(&mut self, samples: &[(i16, i16)]) -> io::Result<()> {
+        for &(left, right) in samples {
+            self.writer.write_all(&left.to_le_bytes())?;
+            self.writer.write_all(&right.to_le_bytes())?;
+        }
+        self.samples += samples.len() as u32;
+        Ok(())
+    }
+
+    pub fn samples(&self) -> u32 {
+        self.samples
+    }
+
+    pub fn finish(mut self) -> io::Result<()> {
+        self.writer.seek(SeekFrom::Start(0))?;
+        write_header(&mut self.writer, self.rate, self.samples)?;
+        self.writer.flush()
+    }
+}
+
+fn write_header(writer: &mut impl Write, rate: u32, samples: u32) -> io::Result<()> {
+    let block_align = CHANNELS * BITS_PER_SAMPLE / 8;
+    let data_size = samples * block_align as u32;
+
+    writer.write_all(b"RIFF")?;
+    writer.write_all(&(HEADER_SIZE - 8 + data_size).to_le_bytes())?;
+    writer.write_all(b"WAVE")?;
+
+    writer.write_all(b"fmt ")?;
+    writer.write_all(&16u32.to_le_bytes())?;
+    // PCM
+    writer.write_all(&1u16.to_le_bytes())?;
+    writer.write_all(&CHANNELS.to_le_bytes())?;
+    writer.write_all(&rate.to_le_bytes())?;
+    writer.write_all(&(rate * block_align as u32).to_le_bytes())?;
+    writer.write_all(&block_align.to_le_bytes())?;
+    writer.write_all(&BITS_PER_SAMPLE.to_le_bytes())?;
+
+    writer.write_all(b"data")?;
+    writer.write_all(&data_size.to_le_bytes())
+}
@@ -0,0 +1,195 @@
+// Runs conformance test programs through the emulator and checks what they print on the TTY.
+//
+// The test programs aren't part of the repository, they go in tests/roms (or $PS_TEST_ROMS):
+//   cpu/        amidog's psxtest_cpu.exe and other CPU tests
+//   ps1-tests/  the cpu/ executables of JaCzekanski's ps1-tests
+// every .exe in those folders is booted with the BIOS from binaries/SCPH1001.BIN ($PS_TEST_BIOS).
+// No line may have a failure marker, and the output must match the .expected file next to the
+// executable or, without one, end in a line with a pass marker. PS_TEST_BLESS=1 writes the
+// .expected files from the current output.
+// The suites are ignored by default, `cargo test -p ps --test conformance -- --ignored` runs them
+// and fails when the folder or the BIOS is missing.
+// The GTE suites (amidog's psxtest_gte, ps1-tests gte/) are left out until there is a GTE, COP2
+// instructions panic. The GPU suites (ps1-tests gpu/, amidog's GPU tests) check VRAM contents and
+// are left out until the GPU has VRAM. ps1-tests folders with those names are skipped.
+
+use ps::cpu::mipsr3000::run_instruction;
+use ps::exe::Executable;
+use ps::playstation::{self, CYCLES_PER_FRAME, PlayStation};
+use std::env;
+use std::fs;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+
+struct Suite {
+    folder: &'static str,
+    // most tests print their results within a few seconds and then spin forever
+    frames: u64,
+    // a line with one of these means a test failed, the run stops there
+    failures: &'static [&'static str],
+    // a test without an .expected file passed when its last line has one of these
+    passes: &'static [&'static str],
+    // subfolders with tests for hardware the emulator doesn't have yet
+    skipped: &'static [&'static str],
+}
+
+// a test that printed something and then went quiet for that long is done
+const QUIET_FRAMES: u64 = 120;
+
+const CPU: Suite = Suite {
+    folder: "cpu",
+    frames: 1800,
+    failures: &["FAIL", "Fail", "ERROR"],
+    passes: &["PASS", "Pass", "passed"],
+    skipped: &[],
+};
+const PS1_TESTS: Suite = Suite {
+    folder: "ps1-tests",
+    frames: 1800,
+    failures: &["FAIL", "fail:"],
+    passes: &["pass", "Pass", "PASS"],
+    skipped: &["gte", "gpu"],
+};
+
+#[test]
+#[ignore = "needs the test programs and a BIOS"]
+fn cpu() {
+    run_suite(&CPU);
+}
+
+#[test]
+#[ignore = "needs the test programs and a BIOS"]
+fn ps1_tests() {
+    run_suite(&PS1_TESTS);
+}
+
+fn run_suite(suite: &Suite) {
+    let manifest = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let roms = env::var_os("PS_TEST_ROMS")
+        .map_or(manifest.join("tests/roms"), PathBuf::from)
+        .join(suite.folder);
+    let bios = env::var_os("PS_TEST_BIOS")
+        .map_or(manifest.join("../binaries/SCPH1001.BIN"), PathBuf::from);
+    let bios =
+        fs::read(&bios).unwrap_or_else(|e| panic!("can't read the BIOS {}: {}", bios.display(), e));
+    assert!(roms.is_dir(), "{} is missing, the {} suite goes there", roms.display(), suite.folder);
+
+    let mut executables = Vec::new();
+    find_executables(&roms, suite.skipped, &mut executables);
+    executables.sort();
+
+    let bless = env::var_os("PS_TEST_BLESS").is_some();
+    let failures: Vec<String> = executables
+        .iter()
+        .filter_map(|path| {
+            let name = path.strip_prefix(&roms).unwrap_or(path).display().to_string();
+            let result = run_test(suite, &bios, path, bless);
+            match &result {
+                Ok(()) => eprintln!("ok    {}", name),
+                Err(e) => eprintln!("FAIL  {}: {}", name, e),
+            }
+            result.err().map(|e| format!("{}: {}", name, e))
+        })
+        .collect();
+
+    assert!(
+        failures.is_empty(),
+        "{} of {} failed:\n{}",
+        failures.len(),
+        executables.len(),
+        failures.join("\n")
+    );
+}
+
+fn find_executables(folder: &Path, skipped: &[&str], executables: &mut Vec<PathBuf>) {
+    let Ok(entries) = fs::read_dir(folder) else { return };
+    for path in entries.filter_map(Result::ok).map(|entry| entry.path()) {
+        if path.is_dir() {
+            let name = path.file_name().and_then(|name| name.to_str()).unwrap_or("");
+            if !skipped.contains(&name) {
+                find_executables(&path, skipped, executables);
+            }
+        } else if path.extension().is_some_and(|extension| extension.eq_ignore_ascii_case("exe")) {
+            executables.push(path);
+        }
+    }
+}
+
+fn run_test(suite: &Suite, bios: &[u8], path: &Path, bless: bool) -> Result<(), String> {
+    let data = fs::read(path).map_err(|e| e.to_string())?;
+    let exe = Executable::parse(&data)?;
+    let expected_path = path.with_extension("expected");
+    let expected = if bless { None } else { fs::read_to_string(&expected_path).ok() };
+
+    let mut ps = PlayStation::new(bios.to_vec().into_boxed_slice());
+    ps.sideload(exe);
+    let mut tty = String::new();
+
+    // a test that crashes the emulator fails on its own, the others still run
+    let run = panic::catch_unwind(AssertUnwindSafe(|| {
+        let end = suite.frames * CYCLES_PER_FRAME as u64;
+        let mut last_output = 0;
+        while ps.cycles < end {
+            if !tty.is_empty() && ps.cycles - last_output > QUIET_FRAMES * CYCLES_PER_FRAME as u64 {
+                break;
+            }
+            run_instruction(&mut ps);
+            if let Some(text) = ps.tty.take() {
+                tty.push_str(text);
+                last_output = ps.cycles;
+                let line_end = tty.ends_with('\n');
+                let failed = line_end
+                    && suite.failures.iter().any(|marker| last_line(&tty).contains(marker));
+                let complete = line_end
+                    && expected.as_ref().is_some_and(|expected| tty.len() >= expected.len());
+                if failed || complete {
+                    break;
+                }
+            }
+        }
+    }));
+    if let Err(payload) = run {
+        return Err(format!(
+            "the emulator panicked at pc {:08x}: {}",
+            ps.cpu.pc,
+            playstation::panic_message(&*payload)
+        ));
+    }
+
+    if bless {
+        return fs::write(&expected_path, &tty)
+            .map_err(|e| format!("{}: {}", expected_path.display(), e));
+    }
+    if let Some(line) =
+        tty.lines().find(|line| suite.failures.iter().any(|marker| line.contains(marker)))
+    {
+        return Err(format!("printed \"{}\"", line.trim()));
+    }
+    match expected {
+        Some(expected) if tty.trim_end() != expected.trim_end() => {
+            let shorter = tty.lines().count().min(expected.lines().count());
+            let line =
+                tty.lines().zip(expected.lines()).position(|(a, b)| a != b).unwrap_or(shorter);
+            Err(format!(
+                "output differs from {} at line {}: \"{}\", expected \"{}\"",
+                expected_path.display(),
+                line + 1,
+                tty.lines().nth(line).unwrap_or(""),
+                expected.lines().nth(line).unwrap_or("")
+            ))
+        }
+        Some(_) => Ok(()),
+        // a test that stopped printing halfway doesn't get to pass
+        None if suite.passes.iter().any(|marker| last_line(&tty).contains(marker)) => Ok(()),
+        None if tty.is_empty() => Err("printed nothing".to_string()),
+        None => Err(format!(
+            "the last line \"{}\" doesn't say it passed and there is no {}",
+            last_line(&tty).trim(),
+            expected_path.display()
+        )),
+    }
+}
+
+fn last_line(tty: &str) -> &str {
+    tty.trim_end_matches('\n').rsplit('\n').next().unwrap_or("")
+}
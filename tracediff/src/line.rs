@@ -0,0 +1,143 @@
+use ps::cpu::utils::register_name;
+
+// One instruction of a trace. The ps formats and the usual reference emulator logs read the same
+// way: the first 8 digit hex word is the pc, an 8 digit hex word right after it the opcode and
+// name=value words are registers. A decimal number before the pc is the instruction index (see
+// leads_with_index), bus accesses in [brackets] are skipped.
+#[derive(Clone, Debug)]
+pub struct Line {
+    pub index: Option<u64>,
+    pub pc: u32,
+    pub opcode: Option<u32>,
+    pub registers: Vec<(String, u32)>,
+}
+
+pub fn parse(text: &str) -> Option<Line> {
+    let tokens: Vec<&str> = text.split_whitespace().collect();
+    let mut index = None;
+    let mut pc = None;
+    let mut opcode = None;
+    let mut registers = Vec::new();
+    let mut in_access = false;
+
+    for (i, token) in tokens.iter().enumerate() {
+        if in_access || token.starts_with('[') {
+            in_access = !token.ends_with(']');
+            continue;
+        }
+        let token = token.trim_end_matches([':', ',', '|', ';']);
+        if let Some((name, value)) = token.split_once('=') {
+            let Some(value) = hex(value) else { continue };
+            match normalize(name) {
+                Some(name) if name == "pc" => pc = pc.or(Some(value)),
+                Some(name) => registers.push((name, value)),
+                None => {}
+            }
+            continue;
+        }
+
+        if pc.is_none() {
+            if i == 0 && leads_with_index(&tokens) {
+                index = token.parse().ok();
+            } else if let Some(value) = word(token) {
+                pc = Some(value);
+                // only right after the pc, addresses and immediates come later
+                opcode = tokens.get(i + 1).and_then(|next| word(next));
+            }
+        }
+    }
+
+    Some(Line { index, pc: pc?, opcode, registers })
+}
+
+// A pc can be all decimal digits too (80010000), a number is only the index when the rest of
+// the line says so: the full format and most logs have the pc and the opcode after it, the pc
+// format is "index: 0xPC" on its own.
+fn leads_with_index(tokens: &[&str]) -> bool {
+    let Some(first) = tokens.first() else { return false };
+    let digits = first.strip_suffix(':').unwrap_or(first);
+    if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return false;
+    }
+    let words = |range: std::ops::Range<usize>| {
+        range.into_iter().all(|i| tokens.get(i).is_some_and(|token| word(token).is_some()))
+    };
+    let pc_format =
+        tokens.len() == 2 && first.ends_with(':') && tokens[1].starts_with("0x") && words(1..2);
+    pc_format || words(1..3)
+}
+
+// an 8 digit hex word, with or without 0x
+fn word(token: &str) -> Option<u32> {
+    let token = token.trim_end_matches([':', ',', '|', ';']);
+    let digits = token.strip_prefix("0x").or_else(|| token.strip_prefix("0X")).unwrap_or(token);
+    if digits.len() == 8 { u32::from_str_radix(digits, 16).ok() } else { None }
+}
+
+fn hex(value: &str) -> Option<u32> {
+    let digits = value.strip_prefix("0x").or_else(|| value.strip_prefix("0X")).unwrap_or(value);
+    u32::from_str_radix(digits, 16).ok()
+}
+
+// "$t0", "T0" and "r8" are all t0, names that aren't registers are dropped
+fn normalize(name: &str) -> Option<String> {
+    let name = name.trim_start_matches('$').to_ascii_lowercase();
+    if let Some(number) = name.strip_prefix('r').and_then(|n| n.parse::<u8>().ok()) {
+        return (number < 32).then(|| register_name(number)[1..].to_string());
+    }
+    let name = match name.as_str() {
+        "s8" => "fp".to_string(),
+        "zr" => "zero".to_string(),
+        _ => name,
+    };
+    let known = (0..32).any(|i| register_name(i)[1..] == name)
+        || name == "hi"
+        || name == "lo"
+        || name == "pc";
+    known.then_some(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn full_format() {
+        let line = parse("      120 80010000: 3c1a8001  lui $k0, 0x8001                  k0=80010000 [r32 80010000 -> 3c1a8001]").unwrap();
+        assert_eq!(line.index, Some(120));
+        assert_eq!(line.pc, 0x80010000);
+        assert_eq!(line.opcode, Some(0x3c1a8001));
+        assert_eq!(line.registers, vec![("k0".to_string(), 0x80010000)]);
+    }
+
+    #[test]
+    fn pc_format() {
+        let line = parse("120: 0x80051054").unwrap();
+        assert_eq!((line.index, line.pc, line.opcode), (Some(120), 0x80051054, None));
+        let line = parse("12345678: 0xBFC00000").unwrap();
+        assert_eq!((line.index, line.pc), (Some(12345678), 0xbfc00000));
+    }
+
+    #[test]
+    fn registers_format() {
+        // a pc made of decimal digits isn't an index
+        let line = parse("80010000: 3c1a8001 lui $k0, 0x8001                   at=00000000 v0=00000001 k0=80010000").unwrap();
+        assert_eq!(line.index, None);
+        assert_eq!(line.pc, 0x80010000);
+        assert_eq!(line.opcode, Some(0x3c1a8001));
+        assert_eq!(line.registers.len(), 3);
+        assert_eq!(line.registers[1], ("v0".to_string(), 1));
+
+        let line = parse("bfc00000: 3c080013 lui $t0, 0x13").unwrap();
+        assert_eq!((line.index, line.pc, line.opcode), (None, 0xbfc00000, Some(0x3c080013)));
+    }
+
+    #[test]
+    fn reference_logs() {
+        let line = parse("PC=80051054 $R8=0000002A r31=80010010 S8=7 zr=0").unwrap();
+        assert_eq!(line.pc, 0x80051054);
+        let names: Vec<&str> = line.registers.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, ["t0", "ra", "fp", "zero"]);
+        assert!(parse("a header without a pc").is_none());
+    }
+}
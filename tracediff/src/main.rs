@@ -0,0 +1,178 @@
+mod line;
+
+use disassembler::disassembler::disassemble;
+use line::Line;
+use ps::cpu::instruction::Instruction;
+use std::collections::{BTreeMap, VecDeque};
+use std::env;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Lines};
+use std::process::exit;
+
+const USAGE: &str = "usage: tracediff <trace> <reference> [--context <lines>]
+traces are lined up by instruction index (the line number when there is none) and compared on the pc, the opcode and the registers
+both name. the ps trace formats and reference logs with an 8 digit pc and name=value registers
+both work. exits with 0 when they agree, 1 when they diverge and 2 on errors";
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let (ours, reference, context) =
+        match args.iter().map(String::as_str).collect::<Vec<_>>().as_slice() {
+            [ours, reference] => (*ours, *reference, 10),
+            [ours, reference, "--context", lines] => {
+                (*ours, *reference, lines.parse().unwrap_or_else(|_| usage()))
+            }
+            _ => usage(),
+        };
+
+    let result =
+        Trace::open(ours, context).and_then(|ours| Ok((ours, Trace::open(reference, context)?)));
+    match result.and_then(|(mut ours, mut reference)| diff(&mut ours, &mut reference)) {
+        Ok(true) => {}
+        Ok(false) => exit(1),
+        Err(e) => {
+            eprintln!("{}", e);
+            exit(2);
+        }
+    }
+}
+
+fn usage() -> ! {
+    eprintln!("{}", USAGE);
+    exit(2);
+}
+
+struct Trace {
+    path: String,
+    lines: Lines<BufReader<File>>,
+    // instructions read, the index of those without one in the file
+    count: u64,
+    // the last value each register was seen with
+    registers: BTreeMap<String, u32>,
+    // the latest instructions with their index and the registers they changed, for context
+    recent: VecDeque<(u64, Line)>,
+    context: usize,
+}
+
+impl Trace {
+    fn open(path: &str, context: usize) -> Result<Trace, String> {
+        let file = File::open(path).map_err(|e| format!("{}: {}", path, e))?;
+        Ok(Trace {
+            path: path.to_string(),
+            lines: BufReader::new(file).lines(),
+            count: 0,
+            registers: BTreeMap::new(),
+            recent: VecDeque::new(),
+            context,
+        })
+    }
+
+    // the next instruction, lines without a pc (headers, summaries) are skipped
+    fn next(&mut self) -> Result<Option<u64>, String> {
+        for text in self.lines.by_ref() {
+            let text = text.map_err(|e: io::Error| format!("{}: {}", self.path, e))?;
+            let Some(mut line) = line::parse(&text) else { continue };
+            let index = line.index.unwrap_or(self.count);
+            self.count += 1;
+            // context only shows what the instruction changed, full register dumps would drown it
+            line.registers.retain(|(name, value)| {
+                self.registers.insert(name.clone(), *value) != Some(*value)
+            });
+            self.recent.push_back((index, line));
+            if self.recent.len() > self.context + 1 {
+                self.recent.pop_front();
+            }
+            return Ok(Some(index));
+        }
+        Ok(None)
+    }
+
+    fn current(&self) -> &Line {
+        &self.recent.back().unwrap().1
+    }
+
+    fn print_context(&self) {
+        println!("{}:", self.path);
+        for (index, line) in &self.recent {
+            let (opcode, disassembly) = match line.opcode {
+                Some(opcode) => (format!("{:08x}", opcode), disassemble(&Instruction(opcode))),
+                None => ("--------".to_string(), String::new()),
+            };
+            let registers: Vec<String> = line
+                .registers
+                .iter()
+                .map(|(name, value)| format!("{}={:08x}", name, value))
+                .collect();
+            let text = format!(
+                "{:>10} {:08x}: {}  {:<32} {}",
+                index,
+                line.pc,
+                opcode,
+                disassembly,
+                registers.join(" ")
+            );
+            println!("{}", text.trim_end());
+        }
+    }
+}
+
+// Ok(false) once it found and printed a divergence. Registers are compared once both traces have
+// named them, the ps full format only names the ones an instruction changed.
+fn diff(ours: &mut Trace, reference: &mut Trace) -> Result<bool, String> {
+    let mut compared = 0u64;
+    loop {
+        let (mut index, mut other) = (ours.next()?, reference.next()?);
+        // a trace that starts later or skips instructions catches up with the other one
+        while let (Some(x), Some(y)) = (index, other)
+            && x != y
+        {
+            if x < y { index = ours.next()? } else { other = reference.next()? }
+        }
+        let index = match (index, other) {
+            (Some(index), Some(_)) => index,
+            (None, None) => {
+                println!("no divergence in {} instructions", compared);
+                return Ok(true);
+            }
+            (None, Some(_)) => return Ok(ends_first(compared, ours, reference)),
+            (Some(_), None) => return Ok(ends_first(compared, reference, ours)),
+        };
+
+        let mut differences = Vec::new();
+        let (a, b) = (ours.current(), reference.current());
+        if a.pc != b.pc {
+            differences.push(format!("pc {:08x} vs {:08x}", a.pc, b.pc));
+        }
+        if let (Some(x), Some(y)) = (a.opcode, b.opcode)
+            && x != y
+        {
+            differences.push(format!("opcode {:08x} vs {:08x}", x, y));
+        }
+        for (name, value) in &ours.registers {
+            if let Some(other) = reference.registers.get(name)
+                && other != value
+            {
+                differences.push(format!("{} {:08x} vs {:08x}", name, value, other));
+            }
+        }
+
+        if !differences.is_empty() {
+            println!("first divergence at instruction {}: {}", index, differences.join(", "));
+            println!();
+            ours.print_context();
+            println!();
+            reference.print_context();
+            return Ok(false);
+        }
+        compared += 1;
+    }
+}
+
+// traces cut at different points still agree on what they both have
+fn ends_first(compared: u64, short: &Trace, long: &Trace) -> bool {
+    println!(
+        "no divergence in {} instructions, {} ends first and {} goes on",
+        compared, short.path, long.path
+    );
+    true
+}